@@ -0,0 +1,317 @@
+use std::collections::HashSet;
+use std::fmt::Write as _;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::panic::catch_unwind;
+
+use layout::backends::svg::SVGWriter;
+use layout::gv::{DotParser, GraphBuilder};
+
+use crate::markdown::CodeBlockRenderer;
+
+/// Diagram languages drawn by the layout engine bundled into the editor.
+pub const DIAGRAMS: &[(&str, CodeBlockRenderer)] = &[
+    ("dot", render_dot),
+    ("graphviz", render_dot),
+    ("mermaid", render_mermaid),
+];
+
+/// Lines of a Mermaid flowchart that only style or group nodes, which are ignored.
+const MERMAID_IGNORED: &[&str] = &["style", "classDef", "class", "linkStyle", "click", "subgraph", "end", "direction"];
+
+/// Node shapes as their opening and closing brackets, longest first, with the DOT shape
+/// each is drawn as. Shapes the layout engine can't draw are drawn as boxes.
+const MERMAID_SHAPES: &[(&str, &str, &str)] = &[
+    ("(((", ")))", "doublecircle"),
+    ("((", "))", "circle"),
+    ("([", "])", "box"),
+    ("[[", "]]", "box"),
+    ("[(", ")]", "box"),
+    ("{{", "}}", "box"),
+    ("[", "]", "box"),
+    ("(", ")", "box"),
+    ("{", "}", "box"),
+    (">", "]", "box"),
+];
+
+/// Lays out a Graphviz graph and draws it as SVG.
+pub fn render_dot(source: &str) -> Result<String, String> {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    let prefix = format!("graph-{:x}-", hasher.finish());
+    let source = source.to_string();
+    // The layout engine panics on some graphs it can't handle, which mustn't take the
+    // editor down with it.
+    catch_unwind(move || {
+        let graph = DotParser::new(&source).process().map_err(|err| format!("Couldn't parse the graph: {err}"))?;
+        let mut builder = GraphBuilder::new();
+        builder.visit_graph(&graph);
+        let mut graph = builder.get();
+        if graph.num_nodes() == 0 {
+            return Err("The graph has no nodes.".to_string());
+        }
+        let mut svg = SVGWriter::new();
+        graph.do_it(false, false, false, &mut svg);
+        let svg = svg.finalize();
+        // The XML declaration has no place inside an HTML document, and the ids of edges
+        // and clip paths have to be unique in it, next to other drawings.
+        let svg = svg[svg.find("<svg").unwrap_or(0)..]
+            .replace("id=\"arrow", &format!("id=\"{prefix}arrow"))
+            .replace("href=\"#arrow", &format!("href=\"#{prefix}arrow"))
+            .replace("id=\"C", &format!("id=\"{prefix}C"))
+            .replace("url(#C", &format!("url(#{prefix}C"));
+        Ok(svg)
+    })
+    .unwrap_or_else(|_| Err("The graph couldn't be laid out.".to_string()))
+}
+
+/// Draws a Mermaid flowchart (`graph` or `flowchart`) as SVG. Other Mermaid diagrams
+/// aren't supported.
+pub fn render_mermaid(source: &str) -> Result<String, String> {
+    render_dot(&Flowchart::to_dot(source)?)
+}
+
+/// Translates a Mermaid flowchart to a DOT graph.
+#[derive(Default)]
+struct Flowchart {
+    dot: String,
+    /// Nodes written to `dot` so far.
+    nodes: HashSet<String>,
+}
+
+impl Flowchart {
+    fn to_dot(source: &str) -> Result<String, String> {
+        let mut lines = statements(source)
+            .into_iter()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with("%%"));
+        let header = lines.next().ok_or("The diagram is empty.")?;
+        let mut words = header.split_whitespace();
+        match words.next() {
+            Some("graph" | "flowchart") => {}
+            Some(kind) => return Err(format!("`{kind}` diagrams aren't supported, only flowcharts (`graph` or `flowchart`).")),
+            None => unreachable!("blank lines are skipped"),
+        }
+        let rankdir = match words.next() {
+            None | Some("TD" | "TB" | "BT") => "TB",
+            Some("LR" | "RL") => "LR",
+            Some(direction) => return Err(format!("Unknown direction `{direction}`, expected TD, TB, BT, LR or RL.")),
+        };
+
+        let mut flowchart = Flowchart { dot: format!("digraph {{\nrankdir={rankdir}\n"), ..Default::default() };
+        for line in lines {
+            let keyword = line.split_whitespace().next().unwrap_or_default();
+            if !MERMAID_IGNORED.contains(&keyword) {
+                flowchart.statement(line)?;
+            }
+        }
+        flowchart.dot.push_str("}\n");
+        Ok(flowchart.dot)
+    }
+
+    /// A chain of nodes joined by links, e.g. `A[Start] --> B{Ok?} -->|yes| C`, where each
+    /// node may be several nodes joined by `&`.
+    fn statement(&mut self, line: &str) -> Result<(), String> {
+        let mut rest = line;
+        let mut from = self.nodes_group(&mut rest)?;
+        while !rest.is_empty() {
+            let link = link(&mut rest)?;
+            let to = self.nodes_group(&mut rest)?;
+            for a in &from {
+                for b in &to {
+                    let op = if link.arrow { "->" } else { "--" };
+                    let _ = writeln!(self.dot, "{} {op} {}{}", quote(a), quote(b), link.attributes());
+                }
+            }
+            from = to;
+        }
+        Ok(())
+    }
+
+    /// One or more nodes joined by `&`, declaring any that are new or given a label.
+    fn nodes_group(&mut self, rest: &mut &str) -> Result<Vec<String>, String> {
+        let mut ids = vec![self.node(rest)?];
+        while let Some(after) = rest.trim_start().strip_prefix('&') {
+            *rest = after;
+            ids.push(self.node(rest)?);
+        }
+        *rest = rest.trim_start();
+        Ok(ids)
+    }
+
+    fn node(&mut self, rest: &mut &str) -> Result<String, String> {
+        let text = rest.trim_start();
+        let len = text.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(text.len());
+        if text.is_empty() {
+            return Err("Expected a node at the end of the line.".to_string());
+        } else if len == 0 {
+            return Err(format!("Expected a node at `{text}`."));
+        }
+        let id = text[..len].to_string();
+        *rest = &text[len..];
+
+        let shape = MERMAID_SHAPES.iter().find(|(open, _, _)| rest.starts_with(open));
+        let (label, shape) = match shape {
+            Some((open, close, shape)) => {
+                let inner = &rest[open.len()..];
+                let end = inner.find(close).ok_or_else(|| format!("`{id}`'s label is missing its closing `{close}`."))?;
+                let label = inner[..end].trim();
+                *rest = &inner[end + close.len()..];
+                (label.strip_prefix('"').and_then(|l| l.strip_suffix('"')).unwrap_or(label), *shape)
+            }
+            // A node without a shape is a box with its id for a label, unless it has one already.
+            None if self.nodes.contains(&id) => return Ok(id),
+            None => (id.as_str(), "box"),
+        };
+        let _ = writeln!(self.dot, "{} [label={}, shape={shape}]", quote(&id), quote(label));
+        self.nodes.insert(id.clone());
+        Ok(id)
+    }
+}
+
+/// A link between nodes: `-->`, `---`, `-.->`, `==>`, with an optional label either as
+/// `-->|label|` or `-- label -->`.
+struct Link {
+    arrow: bool,
+    dotted: bool,
+    thick: bool,
+    label: String,
+}
+
+impl Link {
+    fn attributes(&self) -> String {
+        let mut attributes = Vec::new();
+        if !self.label.is_empty() {
+            attributes.push(format!("label={}", quote(&self.label)));
+        }
+        if self.dotted {
+            attributes.push("style=dashed".to_string());
+        }
+        if self.thick {
+            attributes.push("penwidth=2".to_string());
+        }
+        match attributes.is_empty() {
+            true => String::new(),
+            false => format!(" [{}]", attributes.join(", ")),
+        }
+    }
+}
+
+fn link(rest: &mut &str) -> Result<Link, String> {
+    let text = *rest;
+    let line = |s: &str| s.find(|c: char| !matches!(c, '-' | '.' | '=')).unwrap_or(s.len());
+    let len = line(text);
+    let mut strokes = text[..len].to_string();
+    let mut label = String::new();
+    let mut end = &text[len..];
+    // `-- label -->`: the label runs up to the rest of the link.
+    if matches!(strokes.as_str(), "--" | "==" | "-.") && !end.starts_with('>') {
+        let close = ["-->", "---", "==>", "===", ".->", ".-"]
+            .iter()
+            .filter_map(|close| end.find(close))
+            .min()
+            .ok_or_else(|| format!("Expected the end of the link at `{text}`."))?;
+        label = end[..close].trim().to_string();
+        end = &end[close..];
+        let len = line(end);
+        strokes.push_str(&end[..len]);
+        end = &end[len..];
+    }
+    if strokes.len() < 2 {
+        return Err(format!("Expected a link such as `-->` at `{text}`."));
+    }
+    let arrow = end.starts_with('>');
+    if arrow {
+        end = &end[1..];
+    }
+    if let Some(after) = end.strip_prefix('|') {
+        let close = after.find('|').ok_or("A link's label is missing its closing `|`.")?;
+        label = after[..close].trim().to_string();
+        end = &after[close + 1..];
+    }
+    *rest = end.trim_start();
+    Ok(Link { arrow, dotted: strokes.contains('.'), thick: strokes.contains('='), label })
+}
+
+/// Splits a flowchart into statements, at line ends and at `;`s outside of labels.
+fn statements(source: &str) -> Vec<&str> {
+    let mut statements = Vec::new();
+    let (mut start, mut depth, mut quoted) = (0, 0usize, false);
+    for (i, c) in source.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '[' | '(' | '{' if !quoted => depth += 1,
+            ']' | ')' | '}' if !quoted => depth = depth.saturating_sub(1),
+            '\n' | ';' if c == '\n' || (!quoted && depth == 0) => {
+                statements.push(&source[start..i]);
+                start = i + 1;
+                // A label never spans lines.
+                (depth, quoted) = (0, false);
+            }
+            _ => {}
+        }
+    }
+    statements.push(&source[start..]);
+    statements
+}
+
+/// A DOT string literal.
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_links_and_shapes() {
+        assert_eq!(
+            Flowchart::to_dot("graph TD; A-->B").unwrap(),
+            "digraph {\nrankdir=TB\n\"A\" [label=\"A\", shape=box]\n\"B\" [label=\"B\", shape=box]\n\"A\" -> \"B\"\n}\n"
+        );
+        let dot = Flowchart::to_dot("flowchart LR\n  A[Start here] --> B{Ok?}\n  B -->|yes| C((Done))\n  B -- no --- A").unwrap();
+        assert!(dot.starts_with("digraph {\nrankdir=LR\n"));
+        assert!(dot.contains("\"A\" [label=\"Start here\", shape=box]\n"));
+        assert!(dot.contains("\"B\" [label=\"Ok?\", shape=box]\n"));
+        assert!(dot.contains("\"C\" [label=\"Done\", shape=circle]\n"));
+        assert!(dot.contains("\"B\" -> \"C\" [label=\"yes\"]\n"));
+        assert!(dot.contains("\"B\" -- \"A\" [label=\"no\"]\n"));
+        // Nodes are only declared once.
+        assert_eq!(dot.matches("\"B\" [").count(), 1);
+    }
+
+    #[test]
+    fn splits_statements_at_semicolons_outside_labels() {
+        assert_eq!(statements("graph TD; A[a;b]-->B; B-->C"), ["graph TD", " A[a;b]-->B", " B-->C"]);
+        let dot = Flowchart::to_dot("graph TD; A[a;b] -.-> B & C; C ==> A").unwrap();
+        assert!(dot.contains("\"A\" [label=\"a;b\", shape=box]\n"));
+        assert!(dot.contains("\"A\" -> \"B\" [style=dashed]\n\"A\" -> \"C\" [style=dashed]\n"));
+        assert!(dot.contains("\"C\" -> \"A\" [penwidth=2]\n"));
+    }
+
+    #[test]
+    fn reports_unsupported_and_malformed_diagrams() {
+        let err = Flowchart::to_dot("sequenceDiagram\n  A->>B: hi").unwrap_err();
+        assert!(err.contains("`sequenceDiagram` diagrams aren't supported"), "{err}");
+        assert!(Flowchart::to_dot("graph XY").unwrap_err().contains("Unknown direction `XY`"));
+        assert_eq!(Flowchart::to_dot("  \n%% comment").unwrap_err(), "The diagram is empty.");
+        assert!(Flowchart::to_dot("graph TD\nA -> B").unwrap_err().contains("Expected a link"));
+        assert!(Flowchart::to_dot("graph TD\nA -->").unwrap_err().contains("Expected a node"));
+        assert!(Flowchart::to_dot("graph TD\nA[open --> B").unwrap_err().contains("missing its closing `]`"));
+        assert!(render_mermaid("pie\n\"a\": 1").is_err());
+    }
+
+    #[test]
+    fn draws_dot_as_svg() {
+        let svg = render_dot("digraph { a -> b }").unwrap();
+        assert!(svg.starts_with("<svg"), "{svg}");
+        assert!(render_mermaid("graph LR; A-->B").unwrap().starts_with("<svg"));
+    }
+
+    #[test]
+    fn reports_bad_dot() {
+        assert!(render_dot("digraph {").unwrap_err().starts_with("Couldn't parse the graph"));
+        assert!(render_dot("not a graph").is_err());
+        assert_eq!(render_dot("digraph { }").unwrap_err(), "The graph has no nodes.");
+    }
+}
@@ -0,0 +1,880 @@
+use std::ops::Range;
+
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+use crate::history::{CaretState, Edit, EditKind, History};
+
+/// Lines moved by PageUp/PageDown.
+const PAGE_LINES: usize = 20;
+
+/// A caret movement, independent of the key that triggered it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Motion {
+    Left,
+    Right,
+    Up,
+    Down,
+    LineStart,
+    LineEnd,
+    /// To the start of the word before the caret.
+    WordLeft,
+    /// To the end of the word after the caret.
+    WordRight,
+    /// To the start of the paragraph, or of the previous one when already there.
+    ParagraphUp,
+    /// To the end of the paragraph, or of the next one when already there.
+    ParagraphDown,
+    PageUp,
+    PageDown,
+    DocumentStart,
+    DocumentEnd,
+}
+
+/// The editing core: the raw Markdown text plus caret, selection and undo history.
+///
+/// All offsets are byte offsets into `text` and always sit on a grapheme cluster
+/// boundary, so the caret never ends up inside a user-perceived character.
+/// Nothing in here knows about the DOM, so it can be driven by any frontend (or a test);
+/// use [`utf16_to_byte`] and [`byte_to_utf16`] (or the renderer's source map) at the
+/// boundary with the webview.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EditorBuffer {
+    text: String,
+    caret: usize,
+    /// The fixed end of the selection. The caret is always the moving end.
+    anchor: Option<usize>,
+    /// Additional carets (Alt+click, Ctrl+D, Alt+Shift+Up/Down), each with its own selection.
+    /// Typing and deleting apply at all of them; anything that places the caret drops them.
+    extra_carets: Vec<CaretState>,
+    history: History,
+}
+
+impl EditorBuffer {
+    pub fn new(text: String) -> Self {
+        Self {
+            text,
+            caret: 0,
+            anchor: None,
+            extra_carets: Vec::new(),
+            history: History::new(),
+        }
+    }
+
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    pub fn caret(&self) -> usize {
+        self.caret
+    }
+
+    /// The selection as `(anchor, caret)`, or `None` when nothing is selected.
+    pub fn selection(&self) -> Option<(usize, usize)> {
+        self.anchor
+            .filter(|&anchor| anchor != self.caret)
+            .map(|anchor| (anchor, self.caret))
+    }
+
+    /// The selection as an ordered `(start, end)` pair.
+    pub fn selection_bounds(&self) -> Option<(usize, usize)> {
+        self.selection()
+            .map(|(anchor, caret)| (anchor.min(caret), anchor.max(caret)))
+    }
+
+    pub fn selected_text(&self) -> Option<&str> {
+        self.selection_bounds().map(|(s, e)| &self.text[s..e])
+    }
+
+    /// The text to copy: every caret's selection in document order, one per line.
+    pub fn copy_text(&self) -> Option<String> {
+        let mut ranges = self
+            .cursors()
+            .into_iter()
+            .map(cursor_range)
+            .filter(|range| !range.is_empty())
+            .collect::<Vec<_>>();
+        ranges.sort_by_key(|range| range.start);
+        let texts = ranges.into_iter().map(|range| &self.text[range]).collect::<Vec<_>>();
+        (!texts.is_empty()).then(|| texts.join("\n"))
+    }
+
+    /// The carets besides the main one.
+    pub fn extra_carets(&self) -> &[CaretState] {
+        &self.extra_carets
+    }
+
+    /// Places the caret, dropping any selection.
+    pub fn set_caret(&mut self, pos: usize) {
+        self.caret = self.clamp(pos);
+        self.anchor = None;
+        self.extra_carets.clear();
+        self.history.seal();
+    }
+
+    /// Selects `range`, with the caret at its end.
+    pub fn select(&mut self, range: Range<usize>) {
+        self.anchor = Some(self.clamp(range.start));
+        self.caret = self.clamp(range.end);
+        self.extra_carets.clear();
+        self.history.seal();
+    }
+
+    /// Adds a caret at `pos` (Alt+click), which becomes the main one.
+    pub fn add_caret(&mut self, pos: usize) {
+        let pos = self.clamp(pos);
+        let mut cursors = self.cursors();
+        cursors.insert(0, CaretState { caret: pos, anchor: None });
+        self.set_cursors(cursors);
+        self.history.seal();
+    }
+
+    /// Adds a caret on the line above (or below) the main caret, in the same column.
+    pub fn add_caret_vertical(&mut self, up: bool) {
+        if let Some(pos) = self.vertical_offset(self.caret, 1, up) {
+            self.add_caret(pos);
+        }
+    }
+
+    /// Ctrl+D: selects the word at the caret, or adds a caret selecting the next occurrence
+    /// of the selected text.
+    pub fn select_next_occurrence(&mut self) {
+        let Some((start, end)) = self.selection_bounds() else {
+            if let Some(word) = self.word_at(self.caret) {
+                self.anchor = Some(word.start);
+                self.caret = word.end;
+                self.history.seal();
+            }
+            return;
+        };
+        let needle = &self.text[start..end];
+        let taken = self.cursors().into_iter().map(cursor_range).collect::<Vec<_>>();
+        let occurrences = self
+            .text
+            .match_indices(needle)
+            .map(|(i, _)| i..i + needle.len())
+            .filter(|range| !taken.contains(range))
+            .collect::<Vec<_>>();
+        // The next one after the main selection, wrapping around to the start.
+        let Some(next) = occurrences
+            .iter()
+            .find(|range| range.start >= end)
+            .or(occurrences.first())
+            .cloned()
+        else {
+            return;
+        };
+        let mut cursors = self.cursors();
+        cursors.insert(
+            0,
+            CaretState {
+                caret: next.end,
+                anchor: Some(next.start),
+            },
+        );
+        self.set_cursors(cursors);
+        self.history.seal();
+    }
+
+    /// Drops the extra carets, keeping the main one.
+    pub fn clear_extra_carets(&mut self) {
+        self.extra_carets.clear();
+    }
+
+    /// The word at or touching `pos`, per Unicode word boundaries.
+    pub fn word_at(&self, pos: usize) -> Option<Range<usize>> {
+        self.text
+            .split_word_bound_indices()
+            .map(|(i, word)| i..i + word.len())
+            .filter(|range| range.start <= pos && pos <= range.end)
+            .find(|range| is_word(&self.text[range.clone()]))
+    }
+
+    /// The paragraph (run of non-blank lines) at `pos`, e.g. for triple-click.
+    pub fn paragraph_at(&self, pos: usize) -> Option<Range<usize>> {
+        self.paragraphs()
+            .into_iter()
+            .find(|range| range.start <= pos && pos <= range.end)
+    }
+
+    /// Replaces several non-overlapping ranges at once, e.g. for "replace all", as one undo step.
+    ///
+    /// `replacements` must be sorted by position. The caret keeps its place in the text around it.
+    pub fn replace_ranges(&mut self, replacements: &[(Range<usize>, String)]) {
+        self.replace_ranges_and_select(replacements, None);
+    }
+
+    /// Like [`EditorBuffer::replace_ranges`], then selects `selection` (in the edited text),
+    /// e.g. the text a formatting toggle just wrapped.
+    pub fn replace_ranges_and_select(&mut self, replacements: &[(Range<usize>, String)], selection: Option<Range<usize>>) {
+        let before = self.cursors();
+        let mut caret = self.caret;
+        // Back to front, so earlier offsets stay valid while later ranges change length.
+        let edits = replacements
+            .iter()
+            .rev()
+            .filter(|(range, s)| !(range.is_empty() && s.is_empty()))
+            .map(|(range, s)| {
+                let edit = Edit {
+                    offset: range.start,
+                    removed: self.text[range.clone()].to_string(),
+                    inserted: s.clone(),
+                };
+                edit.apply(&mut self.text);
+                if caret >= range.end {
+                    caret = caret - range.len() + s.len();
+                } else if caret > range.start {
+                    caret = range.start + s.len();
+                }
+                edit
+            })
+            .collect::<Vec<_>>();
+        match selection {
+            Some(selection) => {
+                self.anchor = Some(self.clamp(selection.start)).filter(|_| !selection.is_empty());
+                self.caret = self.clamp(selection.end);
+            }
+            None => {
+                self.caret = self.clamp(caret);
+                self.anchor = None;
+            }
+        }
+        self.extra_carets.clear();
+        self.history.record(edits, EditKind::Other, before, self.cursors());
+    }
+
+    /// Types `s` over the selection (if any) and moves the caret after it.
+    ///
+    /// Consecutive typing is undone as one step.
+    pub fn insert(&mut self, s: &str) {
+        let kind = if self.selection().is_none() && !s.contains('\n') {
+            EditKind::Typing
+        } else {
+            EditKind::Other
+        };
+        self.replace_selection(s, kind);
+    }
+
+    /// Inserts `s` over the selection (if any) as an undo step of its own.
+    pub fn paste(&mut self, s: &str) {
+        self.replace_selection(s, EditKind::Other);
+    }
+
+    /// Backspace: removes the selection, or the grapheme cluster before the caret.
+    pub fn delete_backward(&mut self) {
+        self.delete_motion(Motion::Left);
+    }
+
+    /// Delete: removes the selection, or the grapheme cluster after the caret.
+    pub fn delete_forward(&mut self) {
+        self.delete_motion(Motion::Right);
+    }
+
+    /// Removes the selection, or the text between the caret and where `motion` would move it,
+    /// e.g. [`Motion::WordLeft`] for Ctrl+Backspace.
+    pub fn delete_motion(&mut self, motion: Motion) {
+        let kind = if self.selection().is_some() {
+            EditKind::Other
+        } else {
+            EditKind::Deleting
+        };
+        self.edit_cursors(kind, |buffer, cursor| {
+            let range = cursor_range(cursor);
+            if !range.is_empty() {
+                return (range, String::new());
+            }
+            let target = buffer.offset_for(motion, cursor.caret);
+            (cursor.caret.min(target)..cursor.caret.max(target), String::new())
+        });
+    }
+
+    /// Removes the selected text. Does nothing when there is no selection.
+    pub fn delete_selection(&mut self) {
+        self.edit_cursors(EditKind::Other, |_, cursor| (cursor_range(cursor), String::new()));
+    }
+
+    /// Replaces the whole document with `text` as one undo step, then selects `selection`.
+    ///
+    /// Only the changed middle part is recorded, so undo stays cheap.
+    pub fn replace_text_and_select(&mut self, text: String, selection: Range<usize>) {
+        let prefix = common_prefix(&self.text, &text);
+        let suffix = common_suffix(&self.text[prefix..], &text[prefix..]);
+        let changed = (prefix..self.text.len() - suffix, text[prefix..text.len() - suffix].to_string());
+        self.replace_ranges_and_select(&[changed], Some(selection));
+    }
+
+    /// Moves every caret, collapsing any selection.
+    pub fn move_caret(&mut self, motion: Motion) {
+        let cursors = self
+            .cursors()
+            .into_iter()
+            .map(|cursor| CaretState {
+                caret: self.offset_for(motion, cursor.caret),
+                anchor: None,
+            })
+            .collect();
+        self.set_cursors(cursors);
+        self.history.seal();
+    }
+
+    /// Moves every caret while keeping its selection anchored where it started.
+    pub fn extend_selection(&mut self, motion: Motion) {
+        let cursors = self
+            .cursors()
+            .into_iter()
+            .map(|cursor| CaretState {
+                caret: self.offset_for(motion, cursor.caret),
+                anchor: Some(cursor.anchor.unwrap_or(cursor.caret)),
+            })
+            .collect();
+        self.set_cursors(cursors);
+        self.history.seal();
+    }
+
+    /// Reverts the last undo step, restoring the carets and selections from before it.
+    /// Returns `false` if there was nothing to undo.
+    pub fn undo(&mut self) -> bool {
+        match self.history.undo(&mut self.text) {
+            Some(cursors) => {
+                self.restore(cursors);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Re-applies the last undone step. Returns `false` if there was nothing to redo.
+    pub fn redo(&mut self) -> bool {
+        match self.history.redo(&mut self.text) {
+            Some(cursors) => {
+                self.restore(cursors);
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn caret_state(&self) -> CaretState {
+        CaretState {
+            caret: self.caret,
+            anchor: self.anchor,
+        }
+    }
+
+    fn restore(&mut self, cursors: Vec<CaretState>) {
+        let cursors = cursors
+            .into_iter()
+            .map(|cursor| CaretState {
+                caret: self.clamp(cursor.caret),
+                anchor: cursor.anchor.map(|anchor| self.clamp(anchor)),
+            })
+            .collect();
+        self.set_cursors(cursors);
+    }
+
+    /// All carets, the main one first.
+    fn cursors(&self) -> Vec<CaretState> {
+        std::iter::once(self.caret_state())
+            .chain(self.extra_carets.iter().copied())
+            .collect()
+    }
+
+    /// Sets all carets, the first becoming the main one. Carets that landed on the same
+    /// spot are merged.
+    fn set_cursors(&mut self, cursors: Vec<CaretState>) {
+        let mut cursors = cursors.into_iter();
+        let Some(main) = cursors.next() else {
+            return;
+        };
+        self.caret = main.caret;
+        self.anchor = main.anchor;
+        self.extra_carets.clear();
+        for cursor in cursors {
+            if cursor.caret != self.caret && self.extra_carets.iter().all(|other| other.caret != cursor.caret) {
+                self.extra_carets.push(cursor);
+            }
+        }
+    }
+
+    fn replace_selection(&mut self, s: &str, kind: EditKind) {
+        self.edit_cursors(kind, |_, cursor| (cursor_range(cursor), s.to_string()));
+    }
+
+    /// Replaces text at every caret as one undo step. `edit` picks the range to replace for a
+    /// caret and what to put there; each caret ends up after its replacement.
+    fn edit_cursors(&mut self, kind: EditKind, mut edit: impl FnMut(&Self, CaretState) -> (Range<usize>, String)) {
+        if self.extra_carets.is_empty() {
+            let (range, s) = edit(self, self.caret_state());
+            self.replace(range, &s, kind);
+            return;
+        }
+
+        let before = self.cursors();
+        let mut targets = self
+            .cursors()
+            .into_iter()
+            .enumerate()
+            .map(|(i, cursor)| {
+                let (range, s) = edit(self, cursor);
+                (i, range, s)
+            })
+            .collect::<Vec<_>>();
+        targets.sort_by_key(|(_, range, _)| range.start);
+        // Carets whose edits overlap (e.g. two deleting the same character) merge into one.
+        targets.dedup_by(|(_, next, _), (_, prev, _)| next.start < prev.end || next.start == prev.start);
+        if targets.iter().all(|(_, range, s)| range.is_empty() && s.is_empty()) {
+            return;
+        }
+
+        // New caret positions, front to back, accounting for the length changes before them.
+        let mut delta = 0isize;
+        let mut carets = targets
+            .iter()
+            .map(|(i, range, s)| {
+                let caret = (range.start as isize + delta) as usize + s.len();
+                delta += s.len() as isize - range.len() as isize;
+                (*i, caret)
+            })
+            .collect::<Vec<_>>();
+        // Back to front, so the original offsets stay valid.
+        let edits = targets
+            .iter()
+            .rev()
+            .filter(|(_, range, s)| !(range.is_empty() && s.is_empty()))
+            .map(|(_, range, s)| {
+                let edit = Edit {
+                    offset: range.start,
+                    removed: self.text[range.clone()].to_string(),
+                    inserted: s.clone(),
+                };
+                edit.apply(&mut self.text);
+                edit
+            })
+            .collect::<Vec<_>>();
+
+        // Keep the main caret first.
+        carets.sort_by_key(|(i, _)| *i);
+        let cursors = carets
+            .into_iter()
+            .map(|(_, caret)| CaretState { caret, anchor: None })
+            .collect();
+        self.set_cursors(cursors);
+        self.history.record(edits, kind, before, self.cursors());
+    }
+
+    /// Replaces `range` with `s`, leaving the caret after it and recording the edit.
+    fn replace(&mut self, range: Range<usize>, s: &str, kind: EditKind) {
+        if range.is_empty() && s.is_empty() {
+            return;
+        }
+        let before = self.cursors();
+        let edit = Edit {
+            offset: range.start,
+            removed: self.text[range.clone()].to_string(),
+            inserted: s.to_string(),
+        };
+        edit.apply(&mut self.text);
+        self.caret = range.start + s.len();
+        self.anchor = None;
+        self.history.record(vec![edit], kind, before, self.cursors());
+    }
+
+    /// Clamps `pos` into the text and rounds it down to a grapheme boundary.
+    fn clamp(&self, pos: usize) -> usize {
+        let mut pos = pos.min(self.text.len());
+        while !self.text.is_char_boundary(pos) {
+            pos -= 1;
+        }
+        let mut cursor = GraphemeCursor::new(pos, self.text.len(), true);
+        match cursor.is_boundary(&self.text, 0) {
+            Ok(true) => pos,
+            _ => self.prev_grapheme(pos),
+        }
+    }
+
+    fn prev_grapheme(&self, pos: usize) -> usize {
+        let mut cursor = GraphemeCursor::new(pos, self.text.len(), true);
+        cursor.prev_boundary(&self.text, 0).ok().flatten().unwrap_or(0)
+    }
+
+    fn next_grapheme(&self, pos: usize) -> usize {
+        let mut cursor = GraphemeCursor::new(pos, self.text.len(), true);
+        cursor.next_boundary(&self.text, 0).ok().flatten().unwrap_or(pos)
+    }
+
+    /// The byte offset `column` grapheme clusters into the line starting at `line_start`.
+    fn column_offset(&self, line_start: usize, column: usize) -> usize {
+        let line_end = self.line_end(line_start);
+        self.text[line_start..line_end]
+            .grapheme_indices(true)
+            .nth(column)
+            .map_or(line_end, |(i, _)| line_start + i)
+    }
+
+    fn line_start(&self, pos: usize) -> usize {
+        self.text[..pos].rfind('\n').map_or(0, |i| i + 1)
+    }
+
+    fn line_end(&self, pos: usize) -> usize {
+        self.text[pos..].find('\n').map_or(self.text.len(), |i| pos + i)
+    }
+
+    /// The offset `lines` lines above or below `pos`, keeping its column in grapheme clusters.
+    /// `None` if `pos` is already on the first (or last) line.
+    fn vertical_offset(&self, pos: usize, lines: usize, up: bool) -> Option<usize> {
+        let mut start = self.line_start(pos);
+        let column = self.text[start..pos].graphemes(true).count();
+        let mut moved = false;
+        for _ in 0..lines {
+            if up {
+                if start == 0 {
+                    break;
+                }
+                start = self.line_start(start - 1);
+            } else {
+                let end = self.line_end(start);
+                if end == self.text.len() {
+                    break;
+                }
+                start = end + 1;
+            }
+            moved = true;
+        }
+        moved.then(|| self.column_offset(start, column))
+    }
+
+    /// The start of the word before `pos`, skipping whitespace and punctuation, per Unicode
+    /// word boundaries.
+    fn word_start(&self, pos: usize) -> usize {
+        self.text[..pos]
+            .split_word_bound_indices()
+            .rev()
+            .find(|(_, word)| is_word(word))
+            .map_or(0, |(i, _)| i)
+    }
+
+    /// The end of the word after `pos`, skipping whitespace and punctuation.
+    fn word_end(&self, pos: usize) -> usize {
+        self.text[pos..]
+            .split_word_bound_indices()
+            .find(|(_, word)| is_word(word))
+            .map_or(self.text.len(), |(i, word)| pos + i + word.len())
+    }
+
+    /// Byte ranges of the paragraphs (runs of non-blank lines) in the text.
+    fn paragraphs(&self) -> Vec<Range<usize>> {
+        let mut paragraphs = Vec::new();
+        let mut current: Option<Range<usize>> = None;
+        let mut start = 0;
+        for line in self.text.split('\n') {
+            let end = start + line.len();
+            if line.trim().is_empty() {
+                paragraphs.extend(current.take());
+            } else {
+                current.get_or_insert(start..end).end = end;
+            }
+            start = end + 1;
+        }
+        paragraphs.extend(current);
+        paragraphs
+    }
+
+    /// Where the caret would end up after `motion` from `pos`.
+    fn offset_for(&self, motion: Motion, pos: usize) -> usize {
+        match motion {
+            Motion::Left => self.prev_grapheme(pos),
+            Motion::Right => self.next_grapheme(pos),
+            Motion::Up => self.vertical_offset(pos, 1, true).unwrap_or(pos),
+            Motion::Down => self.vertical_offset(pos, 1, false).unwrap_or(pos),
+            Motion::LineStart => self.line_start(pos),
+            Motion::LineEnd => self.line_end(pos),
+            Motion::WordLeft => self.word_start(pos),
+            Motion::WordRight => self.word_end(pos),
+            Motion::ParagraphUp => self
+                .paragraphs()
+                .iter()
+                .rev()
+                .map(|paragraph| paragraph.start)
+                .find(|&start| start < pos)
+                .unwrap_or(0),
+            Motion::ParagraphDown => self
+                .paragraphs()
+                .iter()
+                .map(|paragraph| paragraph.end)
+                .find(|&end| end > pos)
+                .unwrap_or(self.text.len()),
+            // Paging past the first or last line goes all the way to the start or end.
+            Motion::PageUp => self.vertical_offset(pos, PAGE_LINES, true).unwrap_or(0),
+            Motion::PageDown => self
+                .vertical_offset(pos, PAGE_LINES, false)
+                .unwrap_or(self.text.len()),
+            Motion::DocumentStart => 0,
+            Motion::DocumentEnd => self.text.len(),
+        }
+    }
+}
+
+/// The selected range of a caret, empty when nothing is selected.
+fn cursor_range(cursor: CaretState) -> Range<usize> {
+    let anchor = cursor.anchor.unwrap_or(cursor.caret);
+    anchor.min(cursor.caret)..anchor.max(cursor.caret)
+}
+
+/// Whether a word-boundary segment is a word, rather than whitespace or punctuation.
+fn is_word(segment: &str) -> bool {
+    segment.chars().any(char::is_alphanumeric)
+}
+
+/// Converts a byte offset into `text` to the UTF-16 offset the DOM uses.
+pub fn byte_to_utf16(text: &str, byte: usize) -> usize {
+    text[..byte.min(text.len())]
+        .chars()
+        .map(char::len_utf16)
+        .sum()
+}
+
+/// Converts a UTF-16 offset from the DOM to a byte offset into `text`.
+///
+/// An offset that falls inside a surrogate pair is rounded down to the start of the character.
+pub fn utf16_to_byte(text: &str, utf16: usize) -> usize {
+    let mut units = 0;
+    for (i, ch) in text.char_indices() {
+        units += ch.len_utf16();
+        if units > utf16 {
+            return i;
+        }
+    }
+    text.len()
+}
+
+/// Length in bytes of the longest common prefix of `a` and `b`, on a char boundary.
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.char_indices()
+        .zip(b.chars())
+        .find(|((_, x), y)| x != y)
+        .map_or(a.len().min(b.len()), |((i, _), _)| i)
+}
+
+/// Length in bytes of the longest common suffix of `a` and `b`, on a char boundary.
+fn common_suffix(a: &str, b: &str) -> usize {
+    a.chars()
+        .rev()
+        .zip(b.chars().rev())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer_at(text: &str, caret: usize) -> EditorBuffer {
+        let mut buffer = EditorBuffer::new(text.to_string());
+        buffer.set_caret(caret);
+        buffer
+    }
+
+    /// Where the caret lands after each of `motions` in turn, starting at `caret`.
+    fn motions(text: &str, caret: usize, motion: Motion, count: usize) -> Vec<usize> {
+        let mut buffer = buffer_at(text, caret);
+        (0..count)
+            .map(|_| {
+                buffer.move_caret(motion);
+                buffer.caret()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn moves_within_and_between_lines() {
+        let text = "ab\ncde";
+        assert_eq!(motions(text, 0, Motion::Right, 4), [1, 2, 3, 4]);
+        assert_eq!(motions(text, 4, Motion::Left, 2), [3, 2]);
+        // The column is kept where the line is long enough, else the caret goes to its end.
+        assert_eq!(motions(text, 1, Motion::Down, 2), [4, 4]);
+        assert_eq!(motions(text, 6, Motion::Up, 2), [2, 2]);
+        assert_eq!(motions(text, 4, Motion::LineEnd, 1), [6]);
+        assert_eq!(motions(text, 5, Motion::LineStart, 1), [3]);
+    }
+
+    #[test]
+    fn types_over_the_selection() {
+        let mut buffer = buffer_at("hello world", 6);
+        buffer.extend_selection(Motion::LineEnd);
+        assert_eq!(buffer.selection(), Some((6, 11)));
+        assert_eq!(buffer.selected_text(), Some("world"));
+        buffer.insert("there");
+        assert_eq!((buffer.text(), buffer.caret(), buffer.selection()), ("hello there", 11, None));
+
+        buffer.extend_selection(Motion::LineStart);
+        assert_eq!(buffer.selection_bounds(), Some((0, 11)));
+        buffer.delete_backward();
+        assert_eq!((buffer.text(), buffer.caret()), ("", 0));
+    }
+
+    #[test]
+    fn deletes_around_the_caret() {
+        let mut buffer = buffer_at("abc", 0);
+        buffer.delete_backward();
+        assert_eq!((buffer.text(), buffer.caret()), ("abc", 0));
+        buffer.delete_forward();
+        assert_eq!((buffer.text(), buffer.caret()), ("bc", 0));
+        buffer.move_caret(Motion::LineEnd);
+        buffer.delete_forward();
+        buffer.delete_backward();
+        assert_eq!((buffer.text(), buffer.caret()), ("b", 1));
+    }
+
+    #[test]
+    fn moves_over_whole_grapheme_clusters() {
+        // "e" + combining acute, a family emoji joined with ZWJs, and a CJK character.
+        let text = "e\u{301}👨\u{200d}👩\u{200d}👧漢";
+        assert_eq!(motions(text, 0, Motion::Right, 4), [3, 21, 24, 24]);
+        assert_eq!(motions(text, text.len(), Motion::Left, 4), [21, 3, 0, 0]);
+    }
+
+    #[test]
+    fn deletes_whole_grapheme_clusters() {
+        let mut buffer = buffer_at("ae\u{301}b", 4);
+        buffer.delete_backward();
+        assert_eq!((buffer.text(), buffer.caret()), ("ab", 1));
+        buffer.delete_forward();
+        assert_eq!((buffer.text(), buffer.caret()), ("a", 1));
+    }
+
+    #[test]
+    fn clamps_offsets_to_grapheme_boundaries() {
+        let mut buffer = buffer_at("e\u{301}x", 2);
+        assert_eq!(buffer.caret(), 0);
+        buffer.set_caret(100);
+        assert_eq!(buffer.caret(), 4);
+    }
+
+    #[test]
+    fn keeps_the_grapheme_column_between_lines() {
+        // A shorter line puts the caret at its end, and the next line continues from there.
+        assert_eq!(motions("漢字abc\nxy\nhello", 7, Motion::Down, 3), [12, 15, 15]);
+        assert_eq!(motions("hello\nxy\n漢字abc", 15, Motion::Up, 2), [8, 2]);
+    }
+
+    #[test]
+    fn moves_by_words() {
+        let text = "one, two  three";
+        assert_eq!(motions(text, 0, Motion::WordRight, 4), [3, 8, 15, 15]);
+        assert_eq!(motions(text, text.len(), Motion::WordLeft, 4), [10, 5, 0, 0]);
+    }
+
+    #[test]
+    fn moves_by_paragraphs() {
+        let text = "a\nb\n\nc\n\n\nd";
+        assert_eq!(motions(text, 0, Motion::ParagraphDown, 4), [3, 6, 10, 10]);
+        assert_eq!(motions(text, text.len(), Motion::ParagraphUp, 4), [9, 5, 0, 0]);
+        assert_eq!(buffer_at(text, 6).paragraph_at(6), Some(5..6));
+    }
+
+    #[test]
+    fn deletes_words_backwards() {
+        let mut buffer = buffer_at("foo bar baz", 11);
+        buffer.delete_motion(Motion::WordLeft);
+        assert_eq!((buffer.text(), buffer.caret()), ("foo bar ", 8));
+    }
+
+    #[test]
+    fn types_and_deletes_at_every_caret() {
+        let mut buffer = buffer_at("ab\nab", 1);
+        buffer.add_caret(4);
+        buffer.insert("X");
+        assert_eq!(buffer.text(), "aXb\naXb");
+        buffer.delete_backward();
+        buffer.delete_backward();
+        assert_eq!(buffer.text(), "b\nb");
+        assert_eq!((buffer.caret(), buffer.extra_carets()), (2, &[CaretState { caret: 0, anchor: None }][..]));
+    }
+
+    #[test]
+    fn merges_carets_that_meet() {
+        let mut buffer = buffer_at("ab", 1);
+        buffer.add_caret(2);
+        buffer.move_caret(Motion::DocumentEnd);
+        assert!(buffer.extra_carets().is_empty());
+    }
+
+    #[test]
+    fn selects_next_occurrences() {
+        let mut buffer = buffer_at("cat dog cat", 1);
+        buffer.select_next_occurrence();
+        assert_eq!(buffer.selected_text(), Some("cat"));
+        buffer.select_next_occurrence();
+        assert_eq!(buffer.selected_text(), Some("cat"));
+        assert_eq!(buffer.selection(), Some((8, 11)));
+        buffer.insert("cow");
+        assert_eq!(buffer.text(), "cow dog cow");
+    }
+
+    #[test]
+    fn undoes_consecutive_typing_as_one_step() {
+        let mut buffer = buffer_at("", 0);
+        for ch in ["a", "b", "c"] {
+            buffer.insert(ch);
+        }
+        buffer.move_caret(Motion::Left);
+        buffer.insert("d");
+        assert_eq!(buffer.text(), "abdc");
+
+        assert!(buffer.undo());
+        assert_eq!((buffer.text(), buffer.caret()), ("abc", 2));
+        assert!(buffer.undo());
+        assert_eq!((buffer.text(), buffer.caret()), ("", 0));
+        assert!(!buffer.undo());
+        assert!(buffer.redo());
+        assert_eq!((buffer.text(), buffer.caret()), ("abc", 3));
+    }
+
+    #[test]
+    fn newlines_and_pastes_are_steps_of_their_own() {
+        let mut buffer = buffer_at("", 0);
+        buffer.insert("a");
+        buffer.insert("\n");
+        buffer.paste("bc");
+        buffer.insert("d");
+        for expected in ["a\nbc", "a\n", "a", ""] {
+            buffer.undo();
+            assert_eq!(buffer.text(), expected);
+        }
+    }
+
+    #[test]
+    fn undo_restores_the_selection() {
+        let mut buffer = buffer_at("hello world", 0);
+        buffer.select(6..11);
+        buffer.insert("there");
+        assert!(buffer.undo());
+        assert_eq!(buffer.selection(), Some((6, 11)));
+    }
+
+    #[test]
+    fn converts_between_bytes_and_utf16() {
+        let text = "a漢🦀b";
+        assert_eq!(byte_to_utf16(text, 8), 4);
+        assert_eq!(utf16_to_byte(text, 4), 8);
+        assert_eq!(utf16_to_byte(text, 3), 4);
+    }
+
+    #[test]
+    fn undoes_typing_at_several_carets_as_one_step() {
+        let mut buffer = EditorBuffer::new("one\ntwo".to_string());
+        buffer.set_caret(3);
+        buffer.add_caret(7);
+        buffer.insert("!");
+        buffer.insert("?");
+        assert_eq!(buffer.text(), "one!?\ntwo!?");
+
+        assert!(buffer.undo());
+        assert_eq!(buffer.text(), "one\ntwo");
+        assert_eq!((buffer.caret(), buffer.extra_carets()), (7, &[CaretState { caret: 3, anchor: None }][..]));
+
+        assert!(buffer.redo());
+        assert_eq!(buffer.text(), "one!?\ntwo!?");
+        assert_eq!((buffer.caret(), buffer.extra_carets()), (11, &[CaretState { caret: 5, anchor: None }][..]));
+    }
+}
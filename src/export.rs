@@ -0,0 +1,223 @@
+use std::fmt::Write as _;
+
+use dioxus::dioxus_core::{AttributeValue, DynamicNode, TemplateAttribute, TemplateNode, VNode};
+use dioxus::prelude::*;
+
+use crate::markdown::{escape_href, escape_html, escape_html_body, MarkdownRenderer, RenderMode, RendererOptions};
+use crate::metadata::Metadata;
+
+/// Elements that never have children or a closing tag.
+const VOID_TAGS: &[&str] = &["br", "hr", "img", "input"];
+
+/// Styles embedded into exported documents, so they look right without the editor.
+const EXPORT_CSS: &str = r#"
+body { max-width: 48rem; margin: 2rem auto; padding: 0 1rem; font-family: system-ui, sans-serif; line-height: 1.6; color: #1f2328; }
+h1, h2 { border-bottom: 1px solid #d1d9e0; padding-bottom: .3em; }
+a { color: #0969da; }
+code, pre { font-family: ui-monospace, monospace; font-size: 90%; background: #f6f8fa; border-radius: 6px; }
+code { padding: .2em .4em; }
+pre { padding: 1rem; overflow: auto; }
+pre code { padding: 0; background: none; }
+blockquote { margin: 0; padding: 0 1em; color: #59636e; border-left: .25em solid #d1d9e0; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d1d9e0; padding: 6px 13px; }
+img { max-width: 100%; }
+hr { border: 0; border-top: 1px solid #d1d9e0; }
+.markdown-alert-note, .markdown-alert-tip, .markdown-alert-important, .markdown-alert-warning, .markdown-alert-caution { color: inherit; }
+.markdown-alert-note { border-left-color: #0969da; }
+.markdown-alert-tip { border-left-color: #1a7f37; }
+.markdown-alert-important { border-left-color: #8250df; }
+.markdown-alert-warning { border-left-color: #9a6700; }
+.markdown-alert-caution { border-left-color: #cf222e; }
+.footnote-definition { font-size: 90%; color: #59636e; }
+.footnote-definition sup { margin-right: .5em; }
+.footnotes { margin-top: 2em; border-top: 1px solid #d1d9e0; }
+.footnotes .footnote-definition > p:last-of-type { display: inline; }
+.footnote-backref { margin-left: .25em; text-decoration: none; }
+.rendered-code { margin: 1em 0; }
+.rendered-code-output svg { max-width: 100%; height: auto; }
+.rendered-code summary { color: #59636e; font-size: 90%; cursor: pointer; }
+.rendered-code-error { padding: .5em 1em; border: 1px solid #cf222e; border-radius: 6px; background: #ffebe9; color: #cf222e; }
+"#;
+
+/// Renders `content` to an HTML fragment, without markers or editor attributes.
+pub fn html_fragment(content: &str, options: RendererOptions) -> String {
+    write_nodes(&MarkdownRenderer::with_mode(content.to_string(), options, RenderMode::Reading))
+}
+
+/// Renders `content` to a standalone HTML document with its styles embedded. The title,
+/// tags and date come from the front matter, with `title` used if it has none.
+pub fn html_document(content: &str, options: RendererOptions, title: &str) -> String {
+    let renderer = MarkdownRenderer::with_mode(content.to_string(), options, RenderMode::Reading);
+    let metadata = renderer.metadata.as_ref();
+    let title = metadata.and_then(Metadata::title).unwrap_or(title);
+    let mut head = String::new();
+    let tags = metadata.map(Metadata::tags).unwrap_or_default();
+    if !tags.is_empty() {
+        let _ = writeln!(head, "<meta name=\"keywords\" content=\"{}\">", escape_html(&tags.join(", ")));
+    }
+    if let Some(date) = metadata.and_then(Metadata::date) {
+        let _ = writeln!(head, "<meta name=\"date\" content=\"{}\">", escape_html(&date));
+    }
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{}</title>\n{head}<style>{EXPORT_CSS}</style>\n</head>\n<body>\n{}</body>\n</html>\n",
+        escape_html_body(title),
+        write_nodes(&renderer),
+    )
+}
+
+fn write_nodes(renderer: &MarkdownRenderer) -> String {
+    let mut html = String::new();
+    for node in &renderer.nodes {
+        write_element(&mut html, node);
+        html.push('\n');
+    }
+    html
+}
+
+fn write_element(html: &mut String, element: &Element) {
+    if let Ok(node) = element {
+        write_vnode(html, node);
+    }
+}
+
+fn write_vnode(html: &mut String, node: &VNode) {
+    for root in node.template.roots {
+        write_template_node(html, node, root);
+    }
+}
+
+fn write_template_node(html: &mut String, node: &VNode, template: &TemplateNode) {
+    match template {
+        TemplateNode::Element { tag, attrs, children, .. } => {
+            let _ = write!(html, "<{tag}");
+            // Markup rendered elsewhere, such as math, which replaces the element's children.
+            let mut inner_html = None;
+            for attr in attrs.iter() {
+                match attr {
+                    TemplateAttribute::Static { name, value, .. } => write_attribute(html, name, Some(value)),
+                    TemplateAttribute::Dynamic { id } => {
+                        for attr in node.dynamic_attrs[*id].iter() {
+                            match &attr.value {
+                                AttributeValue::Text(text) if attr.name == "dangerous_inner_html" => {
+                                    inner_html = Some(text.as_str());
+                                }
+                                AttributeValue::Text(text) => write_attribute(html, attr.name, Some(text)),
+                                AttributeValue::Float(f) => write_attribute(html, attr.name, Some(&f.to_string())),
+                                AttributeValue::Int(i) => write_attribute(html, attr.name, Some(&i.to_string())),
+                                AttributeValue::Bool(true) => write_attribute(html, attr.name, None),
+                                _ => {}
+                            }
+                        }
+                    }
+                }
+            }
+            html.push('>');
+            if VOID_TAGS.contains(tag) {
+                return;
+            }
+            match inner_html {
+                Some(inner_html) => html.push_str(inner_html),
+                None => {
+                    for child in children.iter() {
+                        write_template_node(html, node, child);
+                    }
+                }
+            }
+            let _ = write!(html, "</{tag}>");
+        }
+        TemplateNode::Text { text } => {
+            let _ = write!(html, "{}", escape_html_body(text));
+        }
+        TemplateNode::Dynamic { id } => match &node.dynamic_nodes[*id] {
+            DynamicNode::Text(text) => {
+                let _ = write!(html, "{}", escape_html_body(&text.value));
+            }
+            DynamicNode::Fragment(nodes) => {
+                for child in nodes {
+                    write_vnode(html, child);
+                }
+            }
+            DynamicNode::Component(_) | DynamicNode::Placeholder(_) => {}
+        },
+    }
+}
+
+/// Writes ` name="value"`, or just ` name` for a boolean attribute (`None`).
+///
+/// Empty values and the editor's source mapping attributes are left out.
+fn write_attribute(html: &mut String, name: &str, value: Option<&str>) {
+    if name.starts_with("data-src-") || matches!(name, "data-block" | "data-run" | "contenteditable") {
+        return;
+    }
+    let _ = match (name, value) {
+        (_, None) => write!(html, " {name}"),
+        (_, Some("")) => Ok(()),
+        ("href" | "src", Some(value)) => write!(html, " {name}=\"{}\"", escape_href(value)),
+        (_, Some(value)) => write!(html, " {name}=\"{}\"", escape_html(value)),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn leaves_out_editor_attributes() {
+        let text = "# Title\n\n*a* $x$ and `code`\n\n- [ ] task\n";
+        let editing = MarkdownRenderer::with_mode(text.to_string(), RendererOptions::gfm(), RenderMode::Editing);
+        let html = write_nodes(&editing);
+        for attribute in ["data-src-start", "data-src-end", "data-block", "data-run", "contenteditable"] {
+            assert!(!html.contains(attribute), "{attribute} in {html}");
+        }
+        assert!(html.contains(r#"<span class="src-text src-marker">*</span>"#));
+    }
+
+    #[test]
+    fn writes_void_elements_without_closing_tags() {
+        let html = html_fragment("line  \nbreak ![alt](a.png)\n\n- [x] done\n\n***\n", RendererOptions::gfm());
+        assert_eq!(
+            html,
+            concat!(
+                "<p>line<br>break <img src=\"a.png\" alt=\"alt\"></p>\n",
+                "<ul><li><input type=\"checkbox\" checked disabled>done</li></ul>\n",
+                "<hr>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn passes_rendered_markup_through() {
+        let html = html_fragment("$x^2$", RendererOptions::gfm());
+        assert_eq!(
+            html,
+            concat!(
+                r#"<p><span class="math inline-math"><span class="math-rendered">"#,
+                r#"<math xmlns="http://www.w3.org/1998/Math/MathML"><msup><mi>x</mi><mn>2</mn></msup></math>"#,
+                "</span></span></p>\n",
+            )
+        );
+    }
+
+    #[test]
+    fn escapes_text_and_attributes() {
+        let html = html_fragment(r#"a < b & [l](http://x?a=1&b="2" 'say "hi"')"#, RendererOptions::gfm());
+        assert_eq!(
+            html,
+            "<p>a &lt; b &amp; <a href=\"http://x?a=1&amp;b=%222%22\" title=\"say &quot;hi&quot;\">l</a></p>\n"
+        );
+    }
+
+    #[test]
+    fn writes_front_matter_into_the_head() {
+        let text = "---\ntitle: A <b> & co\ntags: [x, y]\ndate: 2024-01-02\n---\n# Heading\n";
+        let html = html_document(text, RendererOptions::gfm(), "fallback.md");
+        assert!(html.contains("<title>A &lt;b&gt; &amp; co</title>\n"));
+        assert!(html.contains("<meta name=\"keywords\" content=\"x, y\">\n"));
+        assert!(html.contains("<meta name=\"date\" content=\"2024-01-02\">\n"));
+        assert!(html.contains("<body>\n\n<h1 id=\"heading\">Heading</h1>\n</body>"));
+
+        let html = html_document("plain\n", RendererOptions::gfm(), "fallback.md");
+        assert!(html.contains("<title>fallback.md</title>\n<style>"));
+    }
+}
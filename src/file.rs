@@ -0,0 +1,297 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::Context as _;
+use rfd::{AsyncFileDialog, AsyncMessageDialog, MessageButtons, MessageDialogResult, MessageLevel};
+
+use crate::{export::html_document, markdown::RendererOptions};
+
+const APP_NAME: &str = "Unified Markdown Editor";
+const MARKDOWN_EXTENSIONS: &[&str] = &["md", "markdown", "mdown", "txt"];
+const DEFAULT_ASSETS_DIR: &str = "assets";
+
+/// The file backing the editor, and its text as it was last loaded or saved.
+///
+/// The document is dirty whenever the editor text differs from `saved_text`, so undoing
+/// back to the saved state makes it clean again.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Document {
+    path: Option<PathBuf>,
+    saved_text: String,
+    /// Markdown extensions this document is rendered with.
+    pub options: RendererOptions,
+    /// Folder, relative to the document, that pasted and dropped images are stored in.
+    pub assets_dir: String,
+}
+
+impl Default for Document {
+    fn default() -> Self {
+        Self {
+            path: None,
+            saved_text: String::new(),
+            options: RendererOptions::default(),
+            assets_dir: DEFAULT_ASSETS_DIR.to_string(),
+        }
+    }
+}
+
+/// What to do with unsaved changes before they would be lost.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnsavedChoice {
+    Save,
+    Discard,
+    Cancel,
+}
+
+impl Document {
+    /// An untitled document that has never been saved.
+    pub fn untitled() -> Self {
+        Self::default()
+    }
+
+    /// Loads `path`, returning the document and its text. A missing file opens as an
+    /// empty document that will be created on first save.
+    pub fn open(path: PathBuf) -> anyhow::Result<(Self, String)> {
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(err) => {
+                return Err(err).with_context(|| format!("Failed to open {}", path.display()))
+            }
+        };
+        let document = Self {
+            path: Some(path),
+            saved_text: text.clone(),
+            ..Self::default()
+        };
+        Ok((document, text))
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn is_dirty(&self, text: &str) -> bool {
+        self.saved_text != text
+    }
+
+    /// Writes `text` to the document's path. Fails for untitled documents; use [`Document::save_as`].
+    pub fn save(&mut self, text: &str) -> anyhow::Result<()> {
+        let path = self.path.as_ref().context("Document has no path yet")?;
+        fs::write(path, text).with_context(|| format!("Failed to save {}", path.display()))?;
+        self.saved_text = text.to_string();
+        Ok(())
+    }
+
+    /// Writes `text` to `path` and makes it the document's path.
+    pub fn save_as(&mut self, path: PathBuf, text: &str) -> anyhow::Result<()> {
+        let previous = self.path.replace(path);
+        let result = self.save(text);
+        if result.is_err() {
+            self.path = previous;
+        }
+        result
+    }
+
+    /// The file name shown to the user, or "Untitled".
+    pub fn display_name(&self) -> String {
+        self.path
+            .as_deref()
+            .and_then(Path::file_name)
+            .map_or_else(|| "Untitled".to_string(), |name| name.to_string_lossy().into_owned())
+    }
+
+    /// The document's title for exports: its file name without the extension.
+    pub fn title(&self) -> String {
+        self.path
+            .as_deref()
+            .and_then(Path::file_stem)
+            .map_or_else(|| "Untitled".to_string(), |stem| stem.to_string_lossy().into_owned())
+    }
+
+    /// Writes `text` as a standalone HTML page to `path`.
+    pub fn export_html(&self, path: &Path, text: &str) -> anyhow::Result<()> {
+        let html = html_document(text, self.options, &self.title());
+        fs::write(path, html).with_context(|| format!("Failed to export {}", path.display()))
+    }
+
+    /// Writes `bytes` to the assets folder as `name` (a file name or path), numbering it if
+    /// the name is taken. Returns the file's path relative to the document, with `/` separators.
+    pub fn add_asset(&self, name: &str, bytes: &[u8]) -> anyhow::Result<String> {
+        let dir = self
+            .path
+            .as_deref()
+            .and_then(Path::parent)
+            .context("Save the document first, so images can be stored next to it")?;
+        let assets = self.assets_dir.trim().trim_matches(['/', '\\']).replace('\\', "/");
+        let folder = dir.join(&assets);
+        fs::create_dir_all(&folder).with_context(|| format!("Failed to create {}", folder.display()))?;
+
+        let name = Path::new(name);
+        let stem = name.file_stem().map_or_else(|| "image".into(), |stem| stem.to_string_lossy());
+        let extension = name.extension().map_or_else(|| "png".into(), |ext| ext.to_string_lossy());
+        let file_name = (0..)
+            .map(|i| match i {
+                0 => format!("{stem}.{extension}"),
+                i => format!("{stem}-{i}.{extension}"),
+            })
+            .find(|file_name| !folder.join(file_name).exists())
+            .context("No free file name")?;
+        let path = folder.join(&file_name);
+        fs::write(&path, bytes).with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(match assets.is_empty() {
+            true => file_name,
+            false => format!("{assets}/{file_name}"),
+        })
+    }
+
+    /// The window title, marking unsaved changes with a leading `•`. A `title` from the
+    /// front matter is shown ahead of the file name.
+    pub fn window_title(&self, text: &str, title: Option<&str>) -> String {
+        let dirty = if self.is_dirty(text) { "• " } else { "" };
+        match title {
+            Some(title) => format!("{dirty}{title} ({}) — {APP_NAME}", self.display_name()),
+            None => format!("{dirty}{} — {APP_NAME}", self.display_name()),
+        }
+    }
+}
+
+/// Asks the user for a Markdown file to open.
+pub async fn pick_open_path() -> Option<PathBuf> {
+    AsyncFileDialog::new()
+        .set_title("Open")
+        .add_filter("Markdown", MARKDOWN_EXTENSIONS)
+        .pick_file()
+        .await
+        .map(|file| file.path().to_path_buf())
+}
+
+/// Asks the user where to save `document`.
+pub async fn pick_save_path(document: &Document) -> Option<PathBuf> {
+    let mut dialog = AsyncFileDialog::new()
+        .set_title("Save As")
+        .add_filter("Markdown", MARKDOWN_EXTENSIONS);
+    match document.path() {
+        Some(path) => {
+            if let Some(dir) = path.parent() {
+                dialog = dialog.set_directory(dir);
+            }
+            dialog = dialog.set_file_name(document.display_name());
+        }
+        None => dialog = dialog.set_file_name("Untitled.md"),
+    }
+    dialog.save_file().await.map(|file| file.path().to_path_buf())
+}
+
+/// Asks where to export `document` as HTML, suggesting its name next to it.
+pub async fn pick_export_path(document: &Document) -> Option<PathBuf> {
+    let mut dialog = AsyncFileDialog::new()
+        .set_title("Export HTML")
+        .add_filter("HTML", &["html", "htm"])
+        .set_file_name(format!("{}.html", document.title()));
+    if let Some(dir) = document.path().and_then(Path::parent) {
+        dialog = dialog.set_directory(dir);
+    }
+    dialog.save_file().await.map(|file| file.path().to_path_buf())
+}
+
+/// Asks whether to save `document`'s unsaved changes before they are lost.
+pub async fn confirm_unsaved(document: &Document) -> UnsavedChoice {
+    let result = AsyncMessageDialog::new()
+        .set_level(MessageLevel::Warning)
+        .set_title(APP_NAME)
+        .set_description(format!(
+            "Save changes to {} before closing?",
+            document.display_name()
+        ))
+        .set_buttons(MessageButtons::YesNoCancel)
+        .show()
+        .await;
+    match result {
+        MessageDialogResult::Yes => UnsavedChoice::Save,
+        MessageDialogResult::No => UnsavedChoice::Discard,
+        _ => UnsavedChoice::Cancel,
+    }
+}
+
+/// Shows an error (e.g. a failed save) to the user.
+pub async fn show_error(err: &anyhow::Error) {
+    AsyncMessageDialog::new()
+        .set_level(MessageLevel::Error)
+        .set_title(APP_NAME)
+        .set_description(format!("{err:#}"))
+        .set_buttons(MessageButtons::Ok)
+        .show()
+        .await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saves_as_a_new_path() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        let mut document = Document::untitled();
+        assert!(document.save("# Notes").is_err());
+
+        document.save_as(path.clone(), "# Notes").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "# Notes");
+        assert_eq!(document.path(), Some(path.as_path()));
+        assert!(!document.is_dirty("# Notes"));
+        assert_eq!(document.display_name(), "notes.md");
+        assert_eq!(document.title(), "notes");
+    }
+
+    #[test]
+    fn keeps_the_old_path_when_saving_as_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        let (mut document, text) = Document::open(path.clone()).unwrap();
+        assert_eq!(text, "");
+        document.save("first").unwrap();
+
+        let missing = dir.path().join("missing").join("other.md");
+        assert!(document.save_as(missing, "second").is_err());
+        assert_eq!(document.path(), Some(path.as_path()));
+        assert!(document.is_dirty("second"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), "first");
+    }
+
+    #[test]
+    fn numbers_assets_whose_names_are_taken() {
+        let dir = tempfile::tempdir().unwrap();
+        let (mut document, _) = Document::open(dir.path().join("notes.md")).unwrap();
+        assert!(Document::untitled().add_asset("image.png", b"0").is_err());
+
+        assert_eq!(document.add_asset("image.png", b"0").unwrap(), "assets/image.png");
+        fs::write(dir.path().join("assets").join("image-1.png"), b"1").unwrap();
+        assert_eq!(document.add_asset("image.png", b"2").unwrap(), "assets/image-2.png");
+        assert_eq!(fs::read(dir.path().join("assets").join("image-2.png")).unwrap(), b"2");
+
+        // Names without an extension are taken as PNGs, and paths keep only the file name.
+        document.assets_dir = "/media/".to_string();
+        assert_eq!(document.add_asset("shots/screenshot", b"3").unwrap(), "media/screenshot.png");
+        document.assets_dir = String::new();
+        assert_eq!(document.add_asset("image.png", b"4").unwrap(), "image.png");
+    }
+
+    #[test]
+    fn marks_unsaved_changes_in_the_window_title() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut document = Document::untitled();
+        assert_eq!(document.window_title("", None), "Untitled — Unified Markdown Editor");
+        assert_eq!(document.window_title("text", None), "• Untitled — Unified Markdown Editor");
+
+        document.save_as(dir.path().join("notes.md"), "text").unwrap();
+        assert_eq!(document.window_title("text", None), "notes.md — Unified Markdown Editor");
+        assert_eq!(
+            document.window_title("more text", Some("My Notes")),
+            "• My Notes (notes.md) — Unified Markdown Editor"
+        );
+    }
+}
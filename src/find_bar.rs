@@ -0,0 +1,153 @@
+use std::ops::Range;
+
+use dioxus::prelude::*;
+
+use crate::editor::EditorBuffer;
+use crate::search::{next_match, prev_match, Search};
+
+/// The find/replace bar above the editor (Ctrl+F, Ctrl+H).
+///
+/// Matches are computed by `App`, which also highlights them in the editor. The current
+/// match is the buffer's selection, so navigating just selects the next one.
+#[component]
+pub fn FindBar(
+    buffer: Signal<EditorBuffer>,
+    search: Signal<Search>,
+    matches: Memo<Result<Vec<Range<usize>>, String>>,
+    open: Signal<bool>,
+    show_replace: Signal<bool>,
+) -> Element {
+    let mut find_next = move || {
+        let Ok(found) = &*matches.read() else { return };
+        let from = buffer.read().caret();
+        if let Some(range) = next_match(found, from) {
+            buffer.write().select(range);
+        }
+    };
+
+    let mut find_prev = move || {
+        let Ok(found) = &*matches.read() else { return };
+        let before = buffer.read().selection_bounds().map_or(buffer.read().caret(), |(start, _)| start);
+        if let Some(range) = prev_match(found, before) {
+            buffer.write().select(range);
+        }
+    };
+
+    // Replaces the selected match (if it is one) and moves on to the next.
+    let replace_one = move |_| {
+        let text = buffer.read().text().to_string();
+        let current = buffer.read().selection_bounds().map(|(start, end)| start..end);
+        let is_match = matches!((&current, &*matches.read()), (Some(range), Ok(found)) if found.contains(range));
+        if let (true, Some(range)) = (is_match, current) {
+            let Ok(replacements) = search.read().replacements(&text, &[range]) else { return };
+            buffer.write().replace_ranges(&replacements);
+        }
+        let Ok(found) = search.read().find_all(buffer.read().text()) else { return };
+        let from = buffer.read().caret();
+        if let Some(range) = next_match(&found, from) {
+            buffer.write().select(range);
+        }
+    };
+
+    let replace_all = move |_| {
+        let text = buffer.read().text().to_string();
+        let search = search.read();
+        let Ok(replacements) = search.find_all(&text).and_then(|found| search.replacements(&text, &found)) else {
+            return;
+        };
+        buffer.write().replace_ranges(&replacements);
+    };
+
+    let status = match &*matches.read() {
+        Err(err) => err.clone(),
+        Ok(_) if search.read().query.is_empty() => String::new(),
+        Ok(found) if found.is_empty() => "No results".to_string(),
+        Ok(found) => {
+            let current = buffer
+                .read()
+                .selection_bounds()
+                .and_then(|(start, end)| found.iter().position(|range| *range == (start..end)));
+            match current {
+                Some(i) => format!("{} of {}", i + 1, found.len()),
+                None => format!("{} matches", found.len()),
+            }
+        }
+    };
+    let invalid = matches.read().is_err();
+
+    rsx! {
+        div {
+            id: "find-bar",
+            style: "display: flex; flex-wrap: wrap; gap: 4px; align-items: center; margin-bottom: 4px;",
+            onkeydown: move |evt: KeyboardEvent| match evt.data().key() {
+                Key::Escape => {
+                    evt.prevent_default();
+                    open.set(false);
+                }
+                Key::Enter if evt.data().modifiers().shift() => {
+                    evt.prevent_default();
+                    find_prev();
+                }
+                Key::Enter => {
+                    evt.prevent_default();
+                    find_next();
+                }
+                _ => {}
+            },
+            input {
+                id: "find-query",
+                placeholder: "Find",
+                value: "{search.read().query}",
+                style: if invalid { "outline: 1px solid #cf222e;" },
+                onmounted: move |evt| async move {
+                    _ = evt.set_focus(true).await;
+                },
+                oninput: move |evt| search.write().query = evt.value(),
+            }
+            label {
+                title: "Match case",
+                input {
+                    r#type: "checkbox",
+                    checked: search.read().case_sensitive,
+                    onchange: move |evt| search.write().case_sensitive = evt.checked(),
+                }
+                "Aa"
+            }
+            label {
+                title: "Whole word",
+                input {
+                    r#type: "checkbox",
+                    checked: search.read().whole_word,
+                    onchange: move |evt| search.write().whole_word = evt.checked(),
+                }
+                "Word"
+            }
+            label {
+                title: "Regular expression",
+                input {
+                    r#type: "checkbox",
+                    checked: search.read().regex,
+                    onchange: move |evt| search.write().regex = evt.checked(),
+                }
+                ".*"
+            }
+            button { onclick: move |_| find_prev(), "↑" }
+            button { onclick: move |_| find_next(), "↓" }
+            span { style: "min-width: 6em; font-size: 90%;", "{status}" }
+            button { onclick: move |_| show_replace.toggle(), if show_replace() { "Hide Replace" } else { "Replace…" } }
+            button { onclick: move |_| open.set(false), "✕" }
+            if show_replace() {
+                div {
+                    style: "display: flex; gap: 4px; flex-basis: 100%;",
+                    input {
+                        placeholder: "Replace (use $1 for groups in regex mode)",
+                        value: "{search.read().replacement}",
+                        oninput: move |evt| search.write().replacement = evt.value(),
+                    }
+                    button { onclick: replace_one, "Replace" }
+                    button { onclick: replace_all, "Replace All" }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::ops::Range;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+/// Inline formatting that can be toggled around the selection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Inline {
+    Strong,
+    Emphasis,
+    Code,
+    Strikethrough,
+    Link,
+}
+
+impl Inline {
+    /// The markers inserted before and after the text when wrapping.
+    fn markers(self) -> (&'static str, &'static str) {
+        match self {
+            Inline::Strong => ("**", "**"),
+            Inline::Emphasis => ("*", "*"),
+            Inline::Code => ("`", "`"),
+            Inline::Strikethrough => ("~~", "~~"),
+            Inline::Link => ("[", "](url)"),
+        }
+    }
+
+    fn matches(self, tag: &Tag) -> bool {
+        matches!(
+            (self, tag),
+            (Inline::Strong, Tag::Strong)
+                | (Inline::Emphasis, Tag::Emphasis)
+                | (Inline::Strikethrough, Tag::Strikethrough)
+                | (Inline::Link, Tag::Link { .. })
+        )
+    }
+}
+
+/// Edits that apply a formatting toggle, and what to select afterwards.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Toggle {
+    /// Sorted, non-overlapping replacements in the original text.
+    pub edits: Vec<(Range<usize>, String)>,
+    pub selection: Range<usize>,
+}
+
+/// A piece of existing markup: all of it, and the content between its markers.
+struct Span {
+    outer: Range<usize>,
+    inner: Range<usize>,
+}
+
+/// Every `kind` span in `text`, as the parser sees it.
+fn spans(text: &str, options: Options, kind: Inline) -> Vec<Span> {
+    let mut spans = Vec::new();
+    // Open tags: whether they are `kind`, their range, and the range of their children so far.
+    let mut open: Vec<(bool, Range<usize>, Option<Range<usize>>)> = Vec::new();
+    for (event, range) in Parser::new_ext(text, options).into_offset_iter() {
+        if !matches!(event, Event::End(_)) {
+            if let Some((_, _, inner)) = open.last_mut() {
+                *inner = Some(match inner.take() {
+                    Some(inner) => inner.start.min(range.start)..inner.end.max(range.end),
+                    None => range.clone(),
+                });
+            }
+        }
+        match event {
+            Event::Start(tag) => open.push((kind.matches(&tag), range, None)),
+            Event::End(_) => {
+                if let Some((true, outer, inner)) = open.pop() {
+                    let inner = inner.unwrap_or(outer.start..outer.start);
+                    spans.push(Span { outer, inner });
+                }
+            }
+            Event::Code(_) if kind == Inline::Code => {
+                let inner = code_content(text, range.clone());
+                spans.push(Span { outer: range, inner });
+            }
+            _ => {}
+        }
+    }
+    spans
+}
+
+/// The content of a code span at `range`, without its backticks and padding spaces.
+fn code_content(text: &str, range: Range<usize>) -> Range<usize> {
+    let code = &text[range.clone()];
+    let ticks = code.len() - code.trim_start_matches('`').len();
+    let mut inner = range.start + ticks..range.end - ticks;
+    let content = &text[inner.clone()];
+    if content.len() > 2 && content.starts_with(' ') && content.ends_with(' ') && !content.trim().is_empty() {
+        inner = inner.start + 1..inner.end - 1;
+    }
+    inner
+}
+
+/// Wraps `target` (the selection, or the word at the caret) in `kind`'s markers, or removes
+/// them if `target` already sits inside such markup.
+pub fn toggle_inline(text: &str, target: Range<usize>, kind: Inline, options: Options) -> Toggle {
+    // The innermost enclosing span, so `***both***` unwraps one level at a time.
+    let enclosing = spans(text, options, kind)
+        .into_iter()
+        .filter(|span| span.outer.start <= target.start && target.end <= span.outer.end)
+        .min_by_key(|span| span.outer.len());
+    if let Some(span) = enclosing {
+        let content = text[span.inner.clone()].to_string();
+        let start = span.outer.start;
+        return Toggle {
+            edits: vec![(span.outer, content.clone())],
+            selection: start..start + content.len(),
+        };
+    }
+
+    let (open, close) = kind.markers();
+    // An empty pair around the caret, just inserted by this shortcut, is removed again.
+    if target.is_empty()
+        && kind != Inline::Link
+        && text[..target.start].ends_with(open)
+        && text[target.end..].starts_with(close)
+    {
+        let start = target.start - open.len();
+        return Toggle {
+            edits: vec![(start..target.end + close.len(), String::new())],
+            selection: start..start,
+        };
+    }
+
+    let inner = target.start + open.len()..target.end + open.len();
+    let selection = match kind {
+        // Select the placeholder URL, or put the caret in the brackets if there is no text yet.
+        Inline::Link if !target.is_empty() => inner.end + 2..inner.end + 5,
+        _ => inner,
+    };
+    Toggle {
+        edits: vec![
+            (target.start..target.start, open.to_string()),
+            (target.end..target.end, close.to_string()),
+        ],
+        selection,
+    }
+}
+
+/// Makes the line at `pos` a heading of `level` (1 to 6), or a plain line again if it
+/// already is one of that level. Setext headings are turned into ATX ones.
+pub fn toggle_heading(text: &str, pos: usize, level: usize, options: Options) -> Toggle {
+    let line_start = text[..pos].rfind('\n').map_or(0, |i| i + 1);
+    let heading = Parser::new_ext(text, options)
+        .into_offset_iter()
+        .find_map(|(event, range)| match event {
+            // A heading's range includes its line break, which isn't on its last line.
+            Event::Start(Tag::Heading { level, .. })
+                if range.start <= line_start && pos <= range.start + text[range.clone()].trim_end_matches(['\r', '\n']).len() =>
+            {
+                Some((level as usize, range))
+            }
+            _ => None,
+        });
+
+    let prefix = format!("{} ", "#".repeat(level));
+    let mut edits = Vec::new();
+    match heading {
+        Some((current, range)) if text[range.clone()].trim_start().starts_with('#') => {
+            let source = &text[range.clone()];
+            let hashes = source.trim_start_matches(' ').trim_start_matches('#');
+            let marker_len = source.len() - hashes.trim_start_matches([' ', '\t']).len();
+            let replacement = if current == level { String::new() } else { prefix };
+            edits.push((range.start..range.start + marker_len, replacement));
+        }
+        Some((current, range)) => {
+            // Setext: drop the underline, and add hashes unless the level is being toggled off.
+            let source = text[range.clone()].trim_end_matches('\n');
+            let underline = source.rfind('\n').map_or(range.start, |i| range.start + i);
+            if current != level {
+                edits.push((range.start..range.start, prefix));
+            }
+            edits.push((underline..range.start + source.len(), String::new()));
+        }
+        None => edits.push((line_start..line_start, prefix)),
+    }
+
+    let caret = map_offset(pos, &edits);
+    Toggle {
+        edits,
+        selection: caret..caret,
+    }
+}
+
+/// Where `pos` ends up after `edits` (sorted, in the original text) are applied.
+fn map_offset(pos: usize, edits: &[(Range<usize>, String)]) -> usize {
+    let mut mapped = pos;
+    for (range, s) in edits.iter().rev() {
+        if pos >= range.end {
+            mapped = mapped - range.len() + s.len();
+        } else if pos > range.start {
+            mapped = range.start + s.len();
+        }
+    }
+    mapped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Applies a toggle to `text`.
+    fn apply(text: &str, toggle: &Toggle) -> String {
+        let mut text = text.to_string();
+        for (range, replacement) in toggle.edits.iter().rev() {
+            text.replace_range(range.clone(), replacement);
+        }
+        text
+    }
+
+    #[test]
+    fn wraps_and_unwraps_the_selection() {
+        let toggle = toggle_inline("a word here", 2..6, Inline::Strong, Options::empty());
+        assert_eq!(apply("a word here", &toggle), "a **word** here");
+        assert_eq!(toggle.selection, 4..8);
+
+        let toggle = toggle_inline("a **word** here", 4..8, Inline::Strong, Options::empty());
+        assert_eq!(apply("a **word** here", &toggle), "a word here");
+        assert_eq!(toggle.selection, 2..6);
+    }
+
+    #[test]
+    fn unwraps_code_spans() {
+        let toggle = toggle_inline("see `x` now", 5..6, Inline::Code, Options::empty());
+        assert_eq!(apply("see `x` now", &toggle), "see x now");
+    }
+
+    #[test]
+    fn removes_an_empty_pair_at_the_caret() {
+        let toggle = toggle_inline("a **** b", 4..4, Inline::Strong, Options::empty());
+        assert_eq!(apply("a **** b", &toggle), "a  b");
+        assert_eq!(toggle.selection, 2..2);
+    }
+
+    #[test]
+    fn links_select_the_placeholder_url() {
+        let toggle = toggle_inline("go home", 3..7, Inline::Link, Options::empty());
+        assert_eq!(apply("go home", &toggle), "go [home](url)");
+        assert_eq!(&apply("go home", &toggle)[toggle.selection], "url");
+    }
+
+    #[test]
+    fn toggles_atx_headings() {
+        let toggle = toggle_heading("foo\n", 1, 2, Options::empty());
+        assert_eq!(apply("foo\n", &toggle), "## foo\n");
+        assert_eq!(toggle.selection, 4..4);
+
+        assert_eq!(apply("## foo\n", &toggle_heading("## foo\n", 4, 3, Options::empty())), "### foo\n");
+        assert_eq!(apply("## foo\n", &toggle_heading("## foo\n", 4, 2, Options::empty())), "foo\n");
+    }
+
+    #[test]
+    fn turns_setext_headings_into_atx() {
+        let text = "Title\n=====\n\nbody";
+        assert_eq!(apply(text, &toggle_heading(text, 2, 2, Options::empty())), "## Title\n\nbody");
+        assert_eq!(apply(text, &toggle_heading(text, 2, 1, Options::empty())), "Title\n\nbody");
+    }
+
+    #[test]
+    fn leaves_the_heading_above_the_caret_alone() {
+        let text = "# A\nfoo\n";
+        assert_eq!(toggle_heading(text, 4, 2, Options::empty()).edits, [(4..4, "## ".to_string())]);
+        let text = "# A\n\nfoo";
+        assert_eq!(toggle_heading(text, 4, 2, Options::empty()).edits, [(4..4, "## ".to_string())]);
+        // At the end of the heading's own line it's still the heading.
+        assert_eq!(toggle_heading("# A\nfoo", 3, 1, Options::empty()).edits, [(0..2, String::new())]);
+    }
+}
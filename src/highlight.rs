@@ -0,0 +1,141 @@
+use std::{ops::Range, sync::LazyLock};
+
+use syntect::{
+    easy::HighlightLines,
+    highlighting::{Color, FontStyle, Style, Theme, ThemeSet},
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+/// Grammars and themes bundled into the binary, loaded on first use.
+static SYNTAXES: LazyLock<SyntaxSet> = LazyLock::new(SyntaxSet::load_defaults_newlines);
+static THEMES: LazyLock<ThemeSet> = LazyLock::new(ThemeSet::load_defaults);
+
+/// Color theme for code blocks, one of the themes bundled with syntect.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CodeTheme {
+    #[default]
+    InspiredGitHub,
+    SolarizedLight,
+    SolarizedDark,
+    OceanLight,
+    OceanDark,
+    Eighties,
+    Mocha,
+}
+
+impl CodeTheme {
+    pub const ALL: [CodeTheme; 7] = [
+        CodeTheme::InspiredGitHub,
+        CodeTheme::SolarizedLight,
+        CodeTheme::SolarizedDark,
+        CodeTheme::OceanLight,
+        CodeTheme::OceanDark,
+        CodeTheme::Eighties,
+        CodeTheme::Mocha,
+    ];
+
+    /// The theme's name in syntect's default theme set.
+    pub fn name(self) -> &'static str {
+        match self {
+            CodeTheme::InspiredGitHub => "InspiredGitHub",
+            CodeTheme::SolarizedLight => "Solarized (light)",
+            CodeTheme::SolarizedDark => "Solarized (dark)",
+            CodeTheme::OceanLight => "base16-ocean.light",
+            CodeTheme::OceanDark => "base16-ocean.dark",
+            CodeTheme::Eighties => "base16-eighties.dark",
+            CodeTheme::Mocha => "base16-mocha.dark",
+        }
+    }
+
+    fn theme(self) -> &'static Theme {
+        THEMES
+            .themes
+            .get(self.name())
+            .unwrap_or_else(|| &THEMES.themes["InspiredGitHub"])
+    }
+
+    /// CSS for a code block's `pre`: the theme's background and default text color.
+    pub fn block_style(self) -> String {
+        let settings = &self.theme().settings;
+        let mut css = String::new();
+        if let Some(background) = settings.background {
+            css.push_str(&format!("background: {};", css_color(background)));
+        }
+        if let Some(foreground) = settings.foreground {
+            css.push_str(&format!(" color: {};", css_color(foreground)));
+        }
+        css
+    }
+}
+
+/// Ranges of highlighted code, with the CSS to show them with.
+pub type Tokens = Vec<(Range<usize>, Option<String>)>;
+
+/// Highlights the lines of a code block one piece of text at a time, keeping the parser
+/// state between them.
+pub struct CodeHighlighter {
+    lines: HighlightLines<'static>,
+    default: Option<Color>,
+}
+
+impl CodeHighlighter {
+    /// A highlighter for `language` (a fence's info string, e.g. `rust` or `py`), or `None`
+    /// if there is no grammar for it, in which case the code is shown in the theme's colors.
+    pub fn new(language: &str, theme: CodeTheme) -> Option<Self> {
+        let syntax = SYNTAXES
+            .find_syntax_by_token(language)
+            .or_else(|| SYNTAXES.find_syntax_by_name(language))?;
+        let theme = theme.theme();
+        Some(Self {
+            lines: HighlightLines::new(syntax, theme),
+            default: theme.settings.foreground,
+        })
+    }
+
+    /// Splits `code` into ranges with the CSS to show them with. Text in the default style
+    /// has no CSS.
+    pub fn highlight(&mut self, code: &str) -> Tokens {
+        let mut tokens = Tokens::new();
+        let mut start = 0;
+        for line in LinesWithEndings::from(code) {
+            let styled = match self.lines.highlight_line(line, &SYNTAXES) {
+                Ok(styled) => styled,
+                // A grammar error leaves the rest of the line unstyled.
+                Err(_) => vec![(Style::default(), line)],
+            };
+            for (style, text) in styled {
+                let css = self.css(style);
+                let range = start..start + text.len();
+                start = range.end;
+                // Merge neighbours with the same style, so there are fewer runs to render.
+                match tokens.last_mut() {
+                    Some((last, last_css)) if *last_css == css => last.end = range.end,
+                    _ => tokens.push((range, css)),
+                }
+            }
+        }
+        tokens
+    }
+
+    fn css(&self, style: Style) -> Option<String> {
+        let mut css = String::new();
+        if style.foreground.a > 0 && Some(style.foreground) != self.default {
+            css.push_str(&format!("color: {};", css_color(style.foreground)));
+        }
+        if style.font_style.contains(FontStyle::BOLD) {
+            css.push_str(" font-weight: bold;");
+        }
+        if style.font_style.contains(FontStyle::ITALIC) {
+            css.push_str(" font-style: italic;");
+        }
+        if style.font_style.contains(FontStyle::UNDERLINE) {
+            css.push_str(" text-decoration: underline;");
+        }
+        (!css.is_empty()).then(|| css.trim_start().to_string())
+    }
+}
+
+fn css_color(color: Color) -> String {
+    format!("#{:02x}{:02x}{:02x}", color.r, color.g, color.b)
+}
@@ -0,0 +1,249 @@
+use std::collections::VecDeque;
+
+/// Undo steps kept before the oldest ones are dropped.
+const MAX_STEPS: usize = 1000;
+/// Bytes of removed/inserted text kept across all undo steps.
+const MAX_BYTES: usize = 4 * 1024 * 1024;
+
+/// A single text replacement: `removed` at `offset` was replaced by `inserted`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edit {
+    pub offset: usize,
+    pub removed: String,
+    pub inserted: String,
+}
+
+impl Edit {
+    fn size(&self) -> usize {
+        self.removed.len() + self.inserted.len()
+    }
+
+    pub fn apply(&self, text: &mut String) {
+        text.replace_range(self.offset..self.offset + self.removed.len(), &self.inserted);
+    }
+
+    pub fn revert(&self, text: &mut String) {
+        text.replace_range(self.offset..self.offset + self.inserted.len(), &self.removed);
+    }
+}
+
+/// What kind of edit was made, used to decide which edits coalesce into one undo step.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EditKind {
+    /// Typing characters; consecutive typing is undone as a whole.
+    Typing,
+    /// Backspace/Delete of single characters; consecutive deletes are undone as a whole.
+    Deleting,
+    /// Anything else (paste, cut, newlines, replacing a selection) is a step of its own.
+    Other,
+}
+
+/// A caret and its selection anchor. Undo/redo restores every caret, the main one first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaretState {
+    pub caret: usize,
+    pub anchor: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+struct Step {
+    edits: Vec<Edit>,
+    kind: EditKind,
+    before: Vec<CaretState>,
+    after: Vec<CaretState>,
+}
+
+impl Step {
+    fn size(&self) -> usize {
+        self.edits.iter().map(Edit::size).sum()
+    }
+
+    /// Whether `edits`, made with the carets at `before`, directly continue this step: the
+    /// same kind of edit at every caret, which hasn't moved since.
+    fn continues(&self, edits: &[Edit], kind: EditKind, before: &[CaretState]) -> bool {
+        if self.kind != kind || self.after != before {
+            return false;
+        }
+        match kind {
+            EditKind::Typing => edits.iter().all(|edit| edit.removed.is_empty()),
+            EditKind::Deleting => edits.iter().all(|edit| edit.inserted.is_empty()),
+            EditKind::Other => false,
+        }
+    }
+}
+
+/// Undo/redo history of edit operations, with consecutive typing coalesced into one step,
+/// also when typing at several carets.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct History {
+    undo: VecDeque<Step>,
+    redo: Vec<Step>,
+    bytes: usize,
+    /// When set, the next edit starts a new step even if it could coalesce.
+    sealed: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records edits that have already been applied to the text, in the order they were
+    /// applied, as one step. `before` and `after` are the carets around them.
+    pub fn record(&mut self, edits: Vec<Edit>, kind: EditKind, before: Vec<CaretState>, after: Vec<CaretState>) {
+        if edits.is_empty() {
+            return;
+        }
+        self.redo.clear();
+        self.bytes += edits.iter().map(Edit::size).sum::<usize>();
+
+        let sealed = std::mem::take(&mut self.sealed);
+        match self.undo.back_mut() {
+            Some(step) if !sealed && step.continues(&edits, kind, &before) => {
+                step.edits.extend(edits);
+                step.after = after;
+            }
+            _ => self.undo.push_back(Step {
+                edits,
+                kind,
+                before,
+                after,
+            }),
+        }
+        self.trim();
+    }
+
+    /// Stops the current step from absorbing further edits, e.g. after the caret moved.
+    pub fn seal(&mut self) {
+        self.sealed = true;
+    }
+
+    /// Reverts the last step on `text`, returning the carets to restore.
+    pub fn undo(&mut self, text: &mut String) -> Option<Vec<CaretState>> {
+        let step = self.undo.pop_back()?;
+        self.bytes -= step.size();
+        for edit in step.edits.iter().rev() {
+            edit.revert(text);
+        }
+        let before = step.before.clone();
+        self.redo.push(step);
+        self.sealed = true;
+        Some(before)
+    }
+
+    /// Re-applies the last undone step on `text`, returning the carets to restore.
+    pub fn redo(&mut self, text: &mut String) -> Option<Vec<CaretState>> {
+        let step = self.redo.pop()?;
+        for edit in &step.edits {
+            edit.apply(text);
+        }
+        let after = step.after.clone();
+        self.bytes += step.size();
+        self.undo.push_back(step);
+        self.sealed = true;
+        Some(after)
+    }
+
+    /// Drops the oldest steps until the history fits its limits again.
+    fn trim(&mut self) {
+        while self.undo.len() > MAX_STEPS || (self.bytes > MAX_BYTES && self.undo.len() > 1) {
+            if let Some(step) = self.undo.pop_front() {
+                self.bytes -= step.size();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn carets(positions: &[usize]) -> Vec<CaretState> {
+        positions.iter().map(|&caret| CaretState { caret, anchor: None }).collect()
+    }
+
+    /// Applies an insertion to `text` and records it.
+    fn insert(history: &mut History, text: &mut String, offset: usize, inserted: &str, kind: EditKind) {
+        let edit = Edit { offset, removed: String::new(), inserted: inserted.to_string() };
+        edit.apply(text);
+        history.record(vec![edit], kind, carets(&[offset]), carets(&[offset + inserted.len()]));
+    }
+
+    #[test]
+    fn coalesces_consecutive_typing() {
+        let (mut history, mut text) = (History::new(), String::new());
+        for (i, ch) in ["a", "b", "c"].into_iter().enumerate() {
+            insert(&mut history, &mut text, i, ch, EditKind::Typing);
+        }
+        assert_eq!(text, "abc");
+        assert_eq!(history.undo(&mut text), Some(carets(&[0])));
+        assert_eq!(text, "");
+        assert_eq!(history.undo(&mut text), None);
+    }
+
+    #[test]
+    fn coalesces_backspaces_but_not_typing_after_them() {
+        let (mut history, mut text) = (History::new(), "abc".to_string());
+        for offset in [2, 1] {
+            let edit = Edit { offset, removed: text[offset..offset + 1].to_string(), inserted: String::new() };
+            edit.apply(&mut text);
+            history.record(vec![edit], EditKind::Deleting, carets(&[offset + 1]), carets(&[offset]));
+        }
+        insert(&mut history, &mut text, 1, "x", EditKind::Typing);
+        assert_eq!(text, "ax");
+        history.undo(&mut text);
+        assert_eq!(text, "a");
+        assert_eq!(history.undo(&mut text), Some(carets(&[3])));
+        assert_eq!(text, "abc");
+    }
+
+    #[test]
+    fn seal_and_other_edits_start_new_steps() {
+        let (mut history, mut text) = (History::new(), String::new());
+        insert(&mut history, &mut text, 0, "a", EditKind::Typing);
+        history.seal();
+        insert(&mut history, &mut text, 1, "b", EditKind::Typing);
+        insert(&mut history, &mut text, 2, "\n", EditKind::Other);
+        insert(&mut history, &mut text, 3, "\n", EditKind::Other);
+        for expected in ["ab\n", "ab", "a", ""] {
+            history.undo(&mut text);
+            assert_eq!(text, expected);
+        }
+    }
+
+    #[test]
+    fn coalesces_typing_at_several_carets() {
+        let (mut history, mut text) = (History::new(), "12".to_string());
+        // Each keystroke inserts at both carets, back to front.
+        let mut positions = [0, 1];
+        for ch in ["a", "b"] {
+            let edits = positions
+                .iter()
+                .rev()
+                .map(|&offset| {
+                    let edit = Edit { offset, removed: String::new(), inserted: ch.to_string() };
+                    edit.apply(&mut text);
+                    edit
+                })
+                .collect();
+            let after = [positions[0] + 1, positions[1] + 2];
+            history.record(edits, EditKind::Typing, carets(&positions), carets(&after));
+            positions = after;
+        }
+        assert_eq!(text, "ab1ab2");
+        assert_eq!(history.undo(&mut text), Some(carets(&[0, 1])));
+        assert_eq!(text, "12");
+        assert_eq!(history.redo(&mut text), Some(carets(&[2, 5])));
+        assert_eq!(text, "ab1ab2");
+    }
+
+    #[test]
+    fn new_edits_clear_redo() {
+        let (mut history, mut text) = (History::new(), String::new());
+        insert(&mut history, &mut text, 0, "a", EditKind::Typing);
+        history.undo(&mut text);
+        insert(&mut history, &mut text, 0, "b", EditKind::Typing);
+        assert_eq!(history.redo(&mut text), None);
+        assert_eq!(text, "b");
+    }
+}
@@ -0,0 +1,82 @@
+use std::ops::RangeInclusive;
+
+/// The text split into lines, with conversions between byte offsets and line/column.
+pub(crate) struct Lines(Vec<String>);
+
+impl Lines {
+    pub(crate) fn new(text: &str) -> Self {
+        Lines(text.split('\n').map(str::to_string).collect())
+    }
+
+    /// The number of lines; there is always at least one.
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Line `i`, without its newline.
+    pub(crate) fn line(&self, i: usize) -> &str {
+        &self.0[i]
+    }
+
+    pub(crate) fn replace(&mut self, i: usize, line: String) {
+        self.0[i] = line;
+    }
+
+    pub(crate) fn insert(&mut self, i: usize, line: String) {
+        self.0.insert(i, line);
+    }
+
+    /// Replaces the lines in `range` with `lines`.
+    pub(crate) fn splice(&mut self, range: RangeInclusive<usize>, lines: impl IntoIterator<Item = String>) {
+        self.0.splice(range, lines);
+    }
+
+    /// The line and column (in bytes) of `offset`.
+    pub(crate) fn position(&self, offset: usize) -> (usize, usize) {
+        let mut start = 0;
+        for (i, line) in self.0.iter().enumerate() {
+            if offset <= start + line.len() {
+                return (i, offset - start);
+            }
+            start += line.len() + 1;
+        }
+        (self.0.len() - 1, self.0.last().map_or(0, String::len))
+    }
+
+    pub(crate) fn offset(&self, line: usize, column: usize) -> usize {
+        self.0[..line].iter().map(|line| line.len() + 1).sum::<usize>() + column.min(self.0[line].len())
+    }
+
+    pub(crate) fn join(&self) -> String {
+        self.0.join("\n")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn converts_between_offsets_and_positions() {
+        let lines = Lines::new("one\ntwo\n");
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines.position(0), (0, 0));
+        assert_eq!(lines.position(3), (0, 3));
+        assert_eq!(lines.position(4), (1, 0));
+        assert_eq!(lines.position(8), (2, 0));
+        assert_eq!(lines.position(100), (2, 0));
+        assert_eq!(lines.offset(1, 2), 6);
+        // Columns past the end of a line stay on it.
+        assert_eq!(lines.offset(1, usize::MAX), 7);
+    }
+
+    #[test]
+    fn edits_lines() {
+        let mut lines = Lines::new("a\nb\nc");
+        lines.replace(0, "A".to_string());
+        lines.insert(1, "new".to_string());
+        assert_eq!(lines.line(1), "new");
+        lines.splice(2..=3, ["B".to_string()]);
+        assert_eq!(lines.join(), "A\nnew\nB");
+    }
+}
@@ -0,0 +1,412 @@
+use std::ops::Range;
+
+use pulldown_cmark::{Event, Options, Parser, Tag};
+
+use crate::lines::Lines;
+
+/// A list item's marker.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Marker {
+    /// `-`, `*` or `+`.
+    Bullet(char),
+    /// `1.` or `1)`.
+    Ordered { number: u64, delimiter: char },
+}
+
+impl Marker {
+    /// The marker as written, with its trailing space.
+    fn text(self) -> String {
+        match self {
+            Marker::Bullet(ch) => format!("{ch} "),
+            Marker::Ordered { number, delimiter } => format!("{number}{delimiter} "),
+        }
+    }
+}
+
+/// The Markdown syntax at the start of a line: blockquote markers, indentation, a list
+/// marker and a task box, e.g. `> - [ ] `.
+#[derive(Debug, Clone, PartialEq)]
+struct Prefix<'a> {
+    /// Blockquote markers, including the whitespace around them.
+    quote: &'a str,
+    /// Indentation after the blockquote markers, which decides list nesting.
+    indent: &'a str,
+    marker: Option<Marker>,
+    /// Length of the marker as written.
+    marker_len: usize,
+    /// Whether the item starts with a task box (checked or not).
+    task: bool,
+}
+
+impl Prefix<'_> {
+    fn parse(line: &str) -> Prefix<'_> {
+        let mut quote_len = 0;
+        while let Some(after) = line[quote_len..].trim_start_matches(' ').strip_prefix('>') {
+            let after = after.strip_prefix(' ').unwrap_or(after);
+            quote_len = line.len() - after.len();
+        }
+        let after_quote = &line[quote_len..];
+        let rest = after_quote.trim_start_matches([' ', '\t']);
+        let (marker, marker_len) = match parse_marker(rest) {
+            Some((marker, len)) => (Some(marker), len),
+            None => (None, 0),
+        };
+        let after_marker = &rest[marker_len..];
+        let task = marker.is_some()
+            && ["[ ]", "[x]", "[X]"].iter().any(|task| {
+                after_marker
+                    .strip_prefix(task)
+                    .is_some_and(|after| after.is_empty() || after.starts_with(' '))
+            });
+        Prefix {
+            quote: &line[..quote_len],
+            indent: &after_quote[..after_quote.len() - rest.len()],
+            marker,
+            marker_len,
+            task,
+        }
+    }
+
+    /// Where the item's text starts, after the marker but before any task box.
+    fn content_start(&self) -> usize {
+        self.quote.len() + self.indent.len() + self.marker_len
+    }
+
+    /// Length of the whole prefix, including a task box and its space.
+    fn len(&self, line: &str) -> usize {
+        let start = self.content_start();
+        match self.task {
+            true => (start + 4).min(line.len()),
+            false if self.marker.is_some() => start,
+            false => self.quote.len(),
+        }
+    }
+
+    /// Whether `self` is an item in the same list level as `other`.
+    fn is_sibling(&self, other: &Prefix) -> bool {
+        self.marker.is_some() && self.quote == other.quote && self.indent.len() == other.indent.len()
+    }
+}
+
+/// A list marker at the start of `text`, and its length including the following space.
+fn parse_marker(text: &str) -> Option<(Marker, usize)> {
+    let followed_by_space = |len: usize| text[len..].is_empty() || text[len..].starts_with(' ');
+    let first = text.chars().next()?;
+    if matches!(first, '-' | '*' | '+') && followed_by_space(1) {
+        return Some((Marker::Bullet(first), 2.min(text.len())));
+    }
+    let digits = text.len() - text.trim_start_matches(|ch: char| ch.is_ascii_digit()).len();
+    let delimiter = text[digits..].chars().next()?;
+    if (1..=9).contains(&digits) && matches!(delimiter, '.' | ')') && followed_by_space(digits + 1) {
+        let number = text[..digits].parse().ok()?;
+        return Some((Marker::Ordered { number, delimiter }, (digits + 2).min(text.len())));
+    }
+    None
+}
+
+impl Lines {
+    fn prefix(&self, i: usize) -> Prefix<'_> {
+        Prefix::parse(self.line(i))
+    }
+
+    /// Rewrites the indentation and marker of item `i`, keeping the rest of the line.
+    fn set_marker(&mut self, i: usize, indent: &str, marker: Marker) {
+        let prefix = self.prefix(i);
+        let line = format!("{}{indent}{}{}", prefix.quote, marker.text(), &self.line(i)[prefix.content_start()..]);
+        self.replace(i, line);
+    }
+
+    /// The items of the list level that line `i` is an item of, skipping nested and blank
+    /// lines in between.
+    fn siblings(&self, i: usize) -> Vec<usize> {
+        let prefix = self.prefix(i);
+        let belongs = |line: &str| {
+            let other = Prefix::parse(line);
+            if other.is_sibling(&prefix) {
+                Some(true)
+            } else if line.trim().is_empty() || (other.quote == prefix.quote && other.indent.len() > prefix.indent.len()) {
+                Some(false)
+            } else {
+                None
+            }
+        };
+        let mut siblings = (0..i)
+            .rev()
+            .map(|j| (j, belongs(self.line(j))))
+            .take_while(|(_, belongs)| belongs.is_some())
+            .filter_map(|(j, belongs)| belongs?.then_some(j))
+            .collect::<Vec<_>>();
+        siblings.reverse();
+        siblings.push(i);
+        siblings.extend(
+            (i + 1..self.len())
+                .map(|j| (j, belongs(self.line(j))))
+                .take_while(|(_, belongs)| belongs.is_some())
+                .filter_map(|(j, belongs)| belongs?.then_some(j)),
+        );
+        siblings
+    }
+
+    /// Renumbers the ordered list that line `i` is an item of, counting up from its first item.
+    fn renumber(&mut self, i: usize) {
+        let items = self.siblings(i);
+        let Some(Marker::Ordered { number: start, .. }) = self.prefix(items[0]).marker else {
+            return;
+        };
+        for (number, item) in (start..).zip(items) {
+            let prefix = self.prefix(item);
+            let Some(Marker::Ordered { delimiter, .. }) = prefix.marker else {
+                continue;
+            };
+            let indent = prefix.indent.to_string();
+            self.set_marker(item, &indent, Marker::Ordered { number, delimiter });
+        }
+    }
+}
+
+/// Whether the parser puts `pos` in a list item or blockquote, and not in a code block
+/// inside one, where list markers are just text.
+fn in_list_or_quote(text: &str, pos: usize, options: Options) -> bool {
+    let mut in_list_or_quote = false;
+    for (event, range) in Parser::new_ext(text, options).into_offset_iter() {
+        match event {
+            Event::Start(Tag::CodeBlock(_) | Tag::HtmlBlock) if range.start <= pos && pos < range.end => return false,
+            Event::Start(Tag::Item | Tag::BlockQuote(_)) if range.start <= pos && pos <= range.end => {
+                in_list_or_quote = true;
+            }
+            _ => {}
+        }
+    }
+    in_list_or_quote
+}
+
+/// Enter inside a list item or blockquote: continues it on a new line with the next marker,
+/// or ends it when the item is empty. Returns the new text and caret, or `None` if the caret
+/// isn't in a list item or blockquote.
+pub fn continue_list(text: &str, caret: usize, options: Options) -> Option<(String, Range<usize>)> {
+    if !in_list_or_quote(text, caret, options) {
+        return None;
+    }
+    let mut lines = Lines::new(text);
+    let (line, column) = lines.position(caret);
+    let current = lines.line(line).to_string();
+    let prefix = Prefix::parse(&current);
+    let prefix_len = prefix.len(&current);
+    if (prefix.marker.is_none() && prefix.quote.is_empty()) || column < prefix_len {
+        return None;
+    }
+
+    if current[prefix_len..].trim().is_empty() {
+        // An empty item ends the list but stays in the quote; an empty quote line ends the quote.
+        let kept = match prefix.marker {
+            Some(_) => prefix.quote.to_string(),
+            None => String::new(),
+        };
+        lines.replace(line, kept.clone());
+        let caret = lines.offset(line, kept.len());
+        return Some((lines.join(), caret..caret));
+    }
+
+    let marker = match prefix.marker {
+        Some(Marker::Ordered { number, delimiter }) => Some(Marker::Ordered {
+            number: number + 1,
+            delimiter,
+        }),
+        marker => marker,
+    };
+    let next = format!(
+        "{}{}{}{}",
+        prefix.quote,
+        prefix.indent,
+        marker.map(Marker::text).unwrap_or_default(),
+        if prefix.task { "[ ] " } else { "" },
+    );
+    let (head, tail) = current.split_at(column);
+    lines.replace(line, head.to_string());
+    lines.insert(line + 1, format!("{next}{tail}"));
+    if prefix.marker.is_some() {
+        lines.renumber(line + 1);
+    }
+
+    let new_line = lines.line(line + 1);
+    let caret = lines.offset(line + 1, Prefix::parse(new_line).len(new_line));
+    Some((lines.join(), caret..caret))
+}
+
+/// Tab/Shift+Tab: indents (or outdents) the list items in `selection` by one level and
+/// renumbers the ordered lists they left and joined. Returns the new text and selection, or
+/// `None` if the selection doesn't start on a list item.
+pub fn indent_items(text: &str, selection: Range<usize>, outdent: bool, options: Options) -> Option<(String, Range<usize>)> {
+    if !in_list_or_quote(text, selection.start, options) {
+        return None;
+    }
+    let mut lines = Lines::new(text);
+    let (first, start_column) = lines.position(selection.start);
+    let (last, end_column) = lines.position(selection.end);
+    lines.prefix(first).marker?;
+
+    // Keep the selection on the same text, relative to where each line's content starts.
+    let relative = |lines: &Lines, line: usize, column: usize| {
+        column as isize - lines.prefix(line).content_start() as isize
+    };
+    let (start_relative, end_relative) = (relative(&lines, first, start_column), relative(&lines, last, end_column));
+
+    let mut renumber = Vec::new();
+    for i in first..=last {
+        let prefix = lines.prefix(i);
+        let Some(marker) = prefix.marker else {
+            continue;
+        };
+        let quote = prefix.quote.to_string();
+        let old_indent = prefix.indent.to_string();
+        let previous = lines.siblings(i).into_iter().take_while(|&j| j < i).last();
+        let new_indent = if outdent {
+            // Back to the indentation of the parent item, if there is one.
+            let parent = (0..i).rev().map(|j| lines.prefix(j)).find(|other| {
+                other.marker.is_some() && other.quote == quote && other.indent.len() < old_indent.len()
+            });
+            match parent {
+                Some(parent) => parent.indent.to_string(),
+                None if old_indent.is_empty() => continue,
+                None => String::new(),
+            }
+        } else {
+            // Nest under the previous item, lined up with its text.
+            let width = match previous {
+                Some(j) => lines.prefix(j).marker_len,
+                None => prefix.marker_len,
+            };
+            format!("{old_indent}{}", " ".repeat(width))
+        };
+
+        lines.set_marker(i, &new_indent, marker);
+        if let (false, Marker::Ordered { delimiter, .. }) = (outdent, marker) {
+            // An item that starts a new nested list counts from 1.
+            if lines.siblings(i)[0] == i {
+                lines.set_marker(i, &new_indent, Marker::Ordered { number: 1, delimiter });
+            }
+        }
+        // The list it joined, and the one it left (via a former sibling above or below).
+        renumber.push(i);
+        renumber.extend(previous);
+        renumber.extend((i + 1..lines.len()).find(|&j| {
+            let other = lines.prefix(j);
+            other.marker.is_some() && other.quote == quote && other.indent == old_indent
+        }));
+    }
+    for i in renumber {
+        lines.renumber(i);
+    }
+
+    let to_offset = |line: usize, relative: isize| {
+        let start = lines.prefix(line).content_start() as isize;
+        lines.offset(line, (start + relative).max(0) as usize)
+    };
+    Some((lines.join(), to_offset(first, start_relative)..to_offset(last, end_relative)))
+}
+
+/// Checks or unchecks the task box (`[ ]`, `[x]`) starting at `offset`, e.g. a task list
+/// marker's source offset. Returns the edit, or `None` if there is no task box there.
+pub fn toggle_task_at(text: &str, offset: usize) -> Option<(Range<usize>, String)> {
+    let mark = match text.get(offset..offset + 3)? {
+        "[ ]" => "x",
+        "[x]" | "[X]" => " ",
+        _ => return None,
+    };
+    Some((offset + 1..offset + 2, mark.to_string()))
+}
+
+/// Checks or unchecks the task on the line of `caret`, if it is one.
+pub fn toggle_task_on_line(text: &str, caret: usize) -> Option<(Range<usize>, String)> {
+    let lines = Lines::new(text);
+    let (line, _) = lines.position(caret);
+    let prefix = lines.prefix(line);
+    if !prefix.task {
+        return None;
+    }
+    toggle_task_at(text, lines.offset(line, prefix.content_start()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `edit` with the caret at `|` in `text`, and shows the result's caret as `|`.
+    fn with_caret(text: &str, edit: impl FnOnce(&str, usize) -> Option<(String, Range<usize>)>) -> Option<String> {
+        let caret = text.find('|').unwrap();
+        let text = text.replacen('|', "", 1);
+        edit(&text, caret).map(|(mut text, selection)| {
+            text.insert(selection.start, '|');
+            text
+        })
+    }
+
+    fn enter(text: &str) -> Option<String> {
+        with_caret(text, |text, caret| continue_list(text, caret, Options::ENABLE_TASKLISTS))
+    }
+
+    fn tab(text: &str, outdent: bool) -> Option<String> {
+        with_caret(text, |text, caret| indent_items(text, caret..caret, outdent, Options::empty()))
+    }
+
+    #[test]
+    fn continues_bullets_and_tasks() {
+        assert_eq!(enter("- a|").as_deref(), Some("- a\n- |"));
+        assert_eq!(enter("* [x] done|").as_deref(), Some("* [x] done\n* [ ] |"));
+        assert_eq!(enter("- sp|lit").as_deref(), Some("- sp\n- |lit"));
+    }
+
+    #[test]
+    fn continues_and_renumbers_ordered_lists() {
+        assert_eq!(enter("1. a|\n2. b").as_deref(), Some("1. a\n2. |\n3. b"));
+        assert_eq!(enter("3) a|").as_deref(), Some("3) a\n4) |"));
+    }
+
+    #[test]
+    fn empty_items_end_the_list() {
+        assert_eq!(enter("- a\n- |").as_deref(), Some("- a\n|"));
+        assert_eq!(enter("> - a\n> - |").as_deref(), Some("> - a\n> |"));
+        assert_eq!(enter("> a\n> |").as_deref(), Some("> a\n|"));
+    }
+
+    #[test]
+    fn continues_quotes() {
+        assert_eq!(enter("> a|").as_deref(), Some("> a\n> |"));
+    }
+
+    #[test]
+    fn ignores_markers_that_arent_lists() {
+        assert_eq!(enter("plain|"), None);
+        assert_eq!(enter("```\n- a|\n```"), None);
+        assert_eq!(enter("* * *|"), None);
+        assert_eq!(enter("    - a|"), None);
+    }
+
+    #[test]
+    fn indents_under_the_previous_item() {
+        assert_eq!(tab("- a\n- |b", false).as_deref(), Some("- a\n  - |b"));
+        assert_eq!(tab("1. a\n2. |b\n3. c", false).as_deref(), Some("1. a\n   1. |b\n2. c"));
+    }
+
+    #[test]
+    fn outdents_to_the_parent() {
+        assert_eq!(tab("- a\n  - |b", true).as_deref(), Some("- a\n- |b"));
+        assert_eq!(tab("1. a\n   1. |b\n2. c", true).as_deref(), Some("1. a\n2. |b\n3. c"));
+        assert_eq!(tab("- |a", true).as_deref(), Some("- |a"));
+    }
+
+    #[test]
+    fn doesnt_indent_outside_lists() {
+        assert_eq!(tab("pl|ain", false), None);
+        assert_eq!(tab("```\n- |a\n```", false), None);
+    }
+
+    #[test]
+    fn toggles_task_boxes() {
+        assert_eq!(toggle_task_at("- [ ] a", 2), Some((3..4, "x".to_string())));
+        assert_eq!(toggle_task_at("- [X] a", 2), Some((3..4, " ".to_string())));
+        assert_eq!(toggle_task_at("- a", 2), None);
+        assert_eq!(toggle_task_on_line("x\n> - [x] a", 7), Some((7..8, " ".to_string())));
+        assert_eq!(toggle_task_on_line("- a", 1), None);
+    }
+}
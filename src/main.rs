@@ -1,648 +1,824 @@
+mod diagram;
+mod editor;
+mod export;
+mod file;
+mod find_bar;
+mod format;
+mod highlight;
+mod history;
+mod lines;
+mod lists;
 mod markdown;
+mod math;
+mod metadata;
+mod paste;
+mod properties;
+mod render_cache;
+mod search;
+mod source_map;
+mod table_bar;
+mod tables;
 
 use dioxus::prelude::*;
+use dioxus::desktop::{tao::event::Event, use_wry_event_handler, window, Config, WindowCloseBehaviour, WindowEvent};
 use dioxus::events::{Key, KeyboardEvent};
-use markdown::MarkdownRenderer;
-use tokio;
-use std::time::Duration;
+use dioxus::html::HasFileData;
+use editor::{EditorBuffer, Motion};
+use export::html_fragment;
+use find_bar::FindBar;
+use format::{toggle_heading, toggle_inline, Inline};
+use highlight::CodeTheme;
+use lists::{continue_list, indent_items, toggle_task_at, toggle_task_on_line};
+use file::{confirm_unsaved, pick_export_path, pick_open_path, pick_save_path, show_error, Document, UnsavedChoice};
+use markdown::{MarkdownRenderer, RenderMode, RendererOptions};
+use metadata::Metadata;
+use paste::{clipboard_html, encode_png, image_markdown, is_image, markdown_from_html};
+use properties::PropertiesPanel;
+use render_cache::RenderCache;
+use search::Search;
+use source_map::DomPosition;
+use table_bar::TableBar;
+use tables::{continue_table, in_table, next_cell};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use arboard::Clipboard;
-use serde_json::Value;
+
+/// Editor styles: syntax markers collapse unless the caret is inside their element.
+const EDITOR_CSS: &str = r#"
+#editor .src-marker { font-size: 0; }
+#editor .caret-inside > .src-marker { font-size: inherit; color: #888; }
+#editor .math:not(.caret-inside) > .src-text { font-size: 0; }
+#editor .math.caret-inside > .math-rendered { display: none; }
+#editor .front-matter { color: #888; }
+#editor .footnotes .footnote-definition > p:last-of-type { display: inline; }
+#editor .front-matter:not(.caret-inside) { font-size: 0; }
+#editor:not(.show-rendered-source) .rendered-code:not(.caret-inside):not(.rendered-code-failed) > pre { font-size: 0; padding: 0; margin: 0; }
+.rendered-code-output svg { max-width: 100%; height: auto; }
+.rendered-code-error { padding: 4px 8px; border: 1px solid #cf222e; border-radius: 4px; background: #ffebe9; color: #cf222e; white-space: normal; }
+::highlight(search) { background-color: #fff3a0; }
+::highlight(search-current) { background-color: #ffb74d; }
+::highlight(extra-selection) { background-color: #b4d5fe; }
+.extra-caret { position: fixed; width: 1px; background: currentColor; pointer-events: none; }
+"#;
+
+/// JS helper shared by the DOM scripts: the text node and offset for a source map position.
+const DOM_POINT_JS: &str = r#"
+    // Positions are `[block, run, offset]`: the run's block key, its index in the block, and
+    // an offset in UTF-16 code units within the run's text node.
+    function point(el, [block, run, offset]) {
+        const host = el.querySelector('[data-block="' + block + '"][data-run="' + run + '"]');
+        if (!host) return null;
+        const node = host.firstChild ?? host;
+        return [node, Math.min(offset, node.length ?? 0)];
+    }
+"#;
+
+/// JS helper shared by the DOM scripts: the `[block, run, UTF-16 offset]` of a DOM point in the editor.
+const DOM_POSITION_JS: &str = r#"
+    function position(el, node, offset) {
+        const isText = node.nodeType === Node.TEXT_NODE;
+        const host = (isText ? node.parentElement : node).closest('[data-run]');
+        if (host && el.contains(host)) {
+            return [host.dataset.block, Number(host.dataset.run), isText ? offset : 0];
+        }
+
+        // Between runs: use the end of the last run that starts before the point.
+        const at = document.createRange();
+        at.setStart(node, offset);
+        let best = null;
+        for (const run of el.querySelectorAll('[data-run]')) {
+            if (at.comparePoint(run, 0) > 0) break;
+            best = run;
+        }
+        if (!best) {
+            const first = el.querySelector('[data-run]');
+            return first && [first.dataset.block, Number(first.dataset.run), 0];
+        }
+        return [best.dataset.block, Number(best.dataset.run), best.textContent.length];
+    }
+"#;
+
+/// The document opened at startup and its text.
+#[derive(Clone)]
+struct Startup {
+    document: Document,
+    text: String,
+}
 
 fn main() {
-    launch(App);
+    // The file to edit can be passed as the first argument.
+    let (document, text) = match std::env::args_os().nth(1) {
+        Some(path) => Document::open(PathBuf::from(path)).unwrap_or_else(|err| {
+            eprintln!("{err:#}");
+            (Document::untitled(), String::new())
+        }),
+        None => (Document::untitled(), String::new()),
+    };
+
+    // Closing the window only hides it, so App can ask about unsaved changes first.
+    LaunchBuilder::desktop()
+        .with_cfg(Config::new().with_close_behaviour(WindowCloseBehaviour::LastWindowHides))
+        .with_context(Startup { document, text })
+        .launch(App);
 }
 
 #[component]
 fn App() -> Element {
-    // A single source of truth for the raw Markdown text.
-    let raw_text = use_signal(|| "# First line\n\n# Second line\nTest\n".to_string());
-    // State to track the caret (cursor) position as a global offset.
-    let mut caret_pos = use_signal(|| None::<usize>);
-    
-    let undo_stack = use_signal(|| Vec::<String>::new());
-
-    // State to track a selection range (anchor, active). When None, no highlighting.
-    let selection_range = use_signal(|| None::<(usize, usize)>);
+    // The file being edited, and the text it was last loaded or saved with.
+    let mut document = use_signal(|| consume_context::<Startup>().document);
+    // The editing core: text, caret, selection and undo history.
+    let mut buffer = use_signal(|| EditorBuffer::new(consume_context::<Startup>().text));
+    // A single source of truth for the raw Markdown text, derived from the buffer.
+    let raw_text = use_memo(move || buffer.read().text().to_string());
+    // Whether the caret has been placed in the editor yet (keys are ignored until then).
+    let mut caret_placed = use_signal(|| false);
 
-    let mut editor_clicked = use_signal(|| false);
-    let prev_raw_text = use_signal(|| String::new());
-
-    let sync_editor_content = {
-        move || {
+    // Resolve unsaved changes before really closing the window.
+    use_wry_event_handler(move |event, _| {
+        if let Event::WindowEvent { event: WindowEvent::CloseRequested, .. } = event {
             spawn(async move {
-                let js_code = r#"
-                    // Copy preview HTML to editor
-                    const preview = document.getElementById('preview');
-                    const editor = document.getElementById('editor');
-                    editor.innerHTML = preview.innerHTML;
-                "#;
-                document::eval(js_code).await.ok();
+                let window = window();
+                window.set_visible(true);
+                if resolve_unsaved(document, buffer).await {
+                    window.close();
+                }
             });
         }
-    };
+    });
 
+    // Editing shows syntax markers around the caret; reading is a clean, read-only preview.
+    let mut render_mode = use_signal(RenderMode::default);
+    // Whether diagrams and other rendered code blocks keep showing their source while editing.
+    let mut show_rendered_source = use_signal(|| false);
 
+    // Create a preview by re-parsing the raw markdown via your MarkdownRenderer.
+    let render_options = use_memo(move || document.read().options);
+    // Clicking a task checkbox checks or unchecks it in the source, as an undoable edit.
+    let toggle_task = use_callback(move |offset: usize| {
+        let edit = toggle_task_at(buffer.read().text(), offset);
+        if let Some(edit) = edit {
+            buffer.write().replace_ranges(&[edit]);
+        }
+    });
+    // Highlighted code and drawn diagrams, kept for the blocks an edit doesn't touch.
+    let render_cache = use_hook(RenderCache::default);
+    let rendered = use_memo(move || match render_mode() {
+        RenderMode::Editing => MarkdownRenderer::editing(raw_text.read().clone(), render_options(), toggle_task, &render_cache),
+        mode => MarkdownRenderer::with_mode(raw_text.read().clone(), render_options(), mode),
+    });
+    // The document's front matter, shown in the properties panel.
+    let metadata = use_memo(move || rendered.read().metadata.clone());
+    // Whether the caret is in a pipe table, for Tab/Enter and the table bar.
+    let caret_in_table = use_memo(move || {
+        let buffer = buffer.read();
+        render_mode() == RenderMode::Editing && in_table(buffer.text(), buffer.caret(), render_options().to_parser_options())
+    });
 
+    // Show the title and file name in the title bar, marked while there are unsaved changes.
+    use_effect(move || {
+        let title = document.read().window_title(&raw_text.read(), metadata.read().as_ref().and_then(Metadata::title));
+        window().set_title(&title);
+    });
 
-    // Create a preview by re-parsing the raw markdown via your MarkdownRenderer.
-    let preview_nodes = use_memo( move || {
-        let mut renderer = MarkdownRenderer::new(raw_text.read().clone());
-        renderer.render_to_elements();
-        renderer.nodes
+    // Find/replace: whether the bar is open, and the query. Matches are byte ranges in the raw text.
+    let mut find_open = use_signal(|| false);
+    let mut show_replace = use_signal(|| false);
+    let mut search = use_signal(Search::default);
+    let matches = use_memo(move || {
+        search
+            .read()
+            .find_all(&raw_text.read())
+            .map_err(|err| err.to_string())
     });
 
-   
-  
+    // Highlight all matches (and the selected one) in the editor, without touching its DOM.
+    use_effect(move || {
+        let rendered = rendered.read();
+        let matches = match (find_open(), &*matches.read()) {
+            (true, Ok(found)) => found.clone(),
+            _ => Vec::new(),
+        };
+        let to_dom = |range: &Range<usize>| {
+            let start = rendered.source_map.to_dom(range.start)?;
+            let end = rendered.source_map.to_dom(range.end)?;
+            Some([dom_value(&start), dom_value(&end)])
+        };
+        let current = buffer
+            .read()
+            .selection_bounds()
+            .map(|(start, end)| start..end)
+            .filter(|range| matches.contains(range));
+        let all = matches.iter().filter_map(to_dom).collect::<Vec<_>>();
+        let current = current.iter().filter_map(to_dom).collect::<Vec<_>>();
+        let js_code = format!(
+            r#"
+            (function() {{
+                const el = document.getElementById("editor");
+                if (!el || !window.CSS?.highlights) return;
+                {DOM_POINT_JS}
 
-    // Add this near your state declarations
-    let caret_queue = use_coroutine(|mut rx: UnboundedReceiver<usize>| async move {
-        while let Ok(Some(pos)) = rx.try_next() {
-            let js_code = format!(
-                r#"
-                (function() {{
-                    const el = document.getElementById("editor");
-                    if (!el) return;
+                function highlight(points) {{
+                    const ranges = [];
+                    for (const [startPosition, endPosition] of points) {{
+                        const start = point(el, startPosition);
+                        const end = point(el, endPosition);
+                        if (!start || !end) continue;
+                        const range = new Range();
+                        range.setStart(...start);
+                        range.setEnd(...end);
+                        ranges.push(range);
+                    }}
+                    return new Highlight(...ranges);
+                }}
 
-                    el.focus();
-                    const sel = window.getSelection();
-                    sel.removeAllRanges();
+                CSS.highlights.set("search", highlight({all}));
+                CSS.highlights.set("search-current", highlight({current}));
+            }})();
+            "#,
+            all = serde_json::to_string(&all).unwrap_or_default(),
+            current = serde_json::to_string(&current).unwrap_or_default(),
+        );
+        spawn(async move {
+            document::eval(&js_code).await.ok();
+        });
+    });
 
+    // Draw the extra carets and their selections; the DOM itself only has one selection.
+    use_effect(move || {
+        let buffer = buffer.read();
+        let rendered = rendered.read();
+        let to_dom = |pos: usize| rendered.source_map.to_dom(pos).map(|pos| dom_value(&pos));
+        let carets = buffer
+            .extra_carets()
+            .iter()
+            .filter_map(|cursor| to_dom(cursor.caret))
+            .collect::<Vec<_>>();
+        let selections = buffer
+            .extra_carets()
+            .iter()
+            .filter_map(|cursor| {
+                let anchor = cursor.anchor?;
+                let (start, end) = (anchor.min(cursor.caret), anchor.max(cursor.caret));
+                Some([to_dom(start)?, to_dom(end)?])
+            })
+            .collect::<Vec<_>>();
+        let js_code = format!(
+            r#"
+            (function() {{
+                const el = document.getElementById("editor");
+                if (!el) return;
+                {DOM_POINT_JS}
+
+                let layer = document.getElementById("extra-carets");
+                if (!layer) {{
+                    layer = document.createElement("div");
+                    layer.id = "extra-carets";
+                    document.body.appendChild(layer);
+                }}
+                layer.replaceChildren();
+                for (const position of {carets}) {{
+                    const at = point(el, position);
+                    if (!at) continue;
                     const range = document.createRange();
-                    let position = {pos}; // Position to move caret
-
-                    // Function to traverse text nodes
-                    function findTextNodeAndOffset(node, pos) {{
-                        let stack = [node];
-                        let offset = 0;
-
-                        while (stack.length > 0) {{
-                            let current = stack.shift();
-
-                            if (current.nodeType === Node.TEXT_NODE) {{
-                                if (offset + current.length >= pos) {{
-                                    return {{ node: current, offset: pos - offset }};
-                                }}
-                                offset += current.length;
-                            }} else {{
-                                for (let i = 0; i < current.childNodes.length; i++) {{
-                                    stack.push(current.childNodes[i]);
-                                }}
-                            }}
-                        }}
-                        return {{ node: el, offset: 0 }};
-                    }}
+                    range.setStart(...at);
+                    const rect = range.getClientRects()[0] ?? range.startContainer.parentElement.getBoundingClientRect();
+                    const caret = document.createElement("div");
+                    caret.className = "extra-caret";
+                    caret.style.left = rect.left + "px";
+                    caret.style.top = rect.top + "px";
+                    caret.style.height = rect.height + "px";
+                    layer.appendChild(caret);
+                }}
 
-                    let result = findTextNodeAndOffset(el, position);
-                    range.setStart(result.node, result.offset);
-                    range.collapse(true);
-                    sel.addRange(range);
-                }})();
-                "#,
-                pos = pos
-            );
-            tokio::time::sleep(Duration::from_millis(100)).await; // Increased delay
+                if (!window.CSS?.highlights) return;
+                const ranges = [];
+                for (const [startPosition, endPosition] of {selections}) {{
+                    const start = point(el, startPosition);
+                    const end = point(el, endPosition);
+                    if (!start || !end) continue;
+                    const range = new Range();
+                    range.setStart(...start);
+                    range.setEnd(...end);
+                    ranges.push(range);
+                }}
+                CSS.highlights.set("extra-selection", new Highlight(...ranges));
+            }})();
+            "#,
+            carets = serde_json::to_string(&carets).unwrap_or_default(),
+            selections = serde_json::to_string(&selections).unwrap_or_default(),
+        );
+        spawn(async move {
             document::eval(&js_code).await.ok();
+        });
+    });
+
+    // Mirror the buffer's caret and selection into the DOM whenever either (or the
+    // rendered document) changes. Effects run after the render has been applied.
+    use_effect(move || {
+        if !caret_placed() {
+            return;
+        }
+        let buffer = buffer.read();
+        let rendered = rendered.read();
+        if rendered.mode != RenderMode::Editing {
+            return;
         }
+        // Also run when the find bar opens or closes, to hand focus back to the editor.
+        let _ = find_open();
+        let (sel_start, sel_end) = buffer.selection_bounds().unwrap_or((buffer.caret(), buffer.caret()));
+        let Some(focus) = rendered.source_map.to_dom(buffer.caret()) else {
+            return;
+        };
+        let anchor = buffer
+            .selection()
+            .and_then(|(anchor, _)| rendered.source_map.to_dom(anchor))
+            .unwrap_or_else(|| focus.clone());
+        let js_code = format!(
+            r#"
+            (function() {{
+                const el = document.getElementById("editor");
+                if (!el) return;
+
+                {DOM_POINT_JS}
+
+                // Reveal the markers of every element the caret or selection touches.
+                for (const node of el.querySelectorAll('.caret-inside')) node.classList.remove('caret-inside');
+                for (const node of el.querySelectorAll('[data-src-start]:not(.src-text)')) {{
+                    if (Number(node.dataset.srcStart) <= {sel_end} && {sel_start} <= Number(node.dataset.srcEnd)) {{
+                        node.classList.add('caret-inside');
+                    }}
+                }}
+
+                // Leave focus (and the DOM selection) with the find bar while it is in use.
+                if (document.activeElement?.closest('#find-bar')) return;
+
+                const focus = point(el, {focus});
+                const anchor = point(el, {anchor}) ?? focus;
+                if (!focus) return;
+
+                if (document.activeElement !== el) el.focus();
+                const selection = window.getSelection();
+                selection.setBaseAndExtent(anchor[0], anchor[1], focus[0], focus[1]);
+                // So the selectionchange listener knows this selection came from the buffer.
+                window.editorSelection = [selection.anchorNode, selection.anchorOffset, selection.focusNode, selection.focusOffset];
+            }})();
+            "#,
+            focus = dom_value(&focus),
+            anchor = dom_value(&anchor),
+            sel_start = sel_start,
+            sel_end = sel_end,
+        );
+        spawn(async move {
+            document::eval(&js_code).await.ok();
+        });
     });
 
-    // Modified set_caret to use the queue
-    let set_caret = {
-        let caret_queue = caret_queue.clone();
-        move || {
-            if let Some(pos) = *caret_pos.read() {
-                caret_queue.send(pos);
+    // File shortcuts work anywhere in the window, also before the caret has been placed and
+    // in the reading view, so they are handled for the whole window rather than the editor.
+    use_future(move || async move {
+        let mut shortcuts = document::eval(
+            r#"
+            window.addEventListener("keydown", (e) => {
+                const key = e.key.toLowerCase();
+                if (e.ctrlKey && !e.altKey && (key === "o" || key === "s" || (key === "e" && e.shiftKey))) {
+                    e.preventDefault();
+                    dioxus.send({ key, shift: e.shiftKey });
+                }
+            });
+            "#,
+        );
+        while let Ok(message) = shortcuts.recv::<serde_json::Value>().await {
+            let shift = message["shift"].as_bool().unwrap_or_default();
+            match message["key"].as_str() {
+                Some("o") => {
+                    spawn(open_document(document, buffer));
+                }
+                Some("s") => {
+                    spawn(async move {
+                        save_document(document, buffer, shift).await;
+                    });
+                }
+                Some("e") => {
+                    spawn(export_document(document, buffer));
+                }
+                _ => {}
             }
         }
-    };
+    });
 
-    // Modify the sync task to use the LATEST caret position
-    let _sync_task = use_coroutine(move |_rx: UnboundedReceiver<()>| async move {
-        loop {
-            // Get the position RIGHT BEFORE syncing
-            let current_pos = *caret_pos.read();
-            let was_clicked = *editor_clicked.read();
-            
-
-
-            // First update the editor content
-            let js_sync = r#"
-                const preview = document.getElementById('preview');
-                const editor = document.getElementById('editor');
-                editor.innerHTML = preview.innerHTML;
-            "#;
-            document::eval(js_sync).await.ok();
-   
-            // Then set caret position if needed
-            if let Some(pos) = current_pos{
-
-                if was_clicked {
-                    caret_pos.with_mut(|cp| *cp = Some(pos));
-                    caret_queue.send(pos);
-                    editor_clicked.set(false);
-                    println!("pos {:?}", pos);
+    // Mouse selections (dragging, Shift+click, double and triple click) are reported by the
+    // DOM and mapped back into the buffer, so copying and deleting act on them too.
+    use_future(move || async move {
+        let js_code = format!(
+            r#"
+            {DOM_POSITION_JS}
+            const el = document.getElementById("editor");
+            // Double and triple click select a word and a paragraph the way the buffer sees them.
+            el.addEventListener("mousedown", (e) => {{
+                window.editorClicks = e.detail;
+                if (e.button === 0 && e.detail > 1 && el.isContentEditable) {{
+                    e.preventDefault();
+                    const at = document.caretPositionFromPoint?.(e.clientX, e.clientY);
+                    const range = at ? null : document.caretRangeFromPoint?.(e.clientX, e.clientY);
+                    const [node, offset] = at ? [at.offsetNode, at.offset] : [range?.startContainer, range?.startOffset];
+                    if (node && el.contains(node)) dioxus.send({{ clicks: e.detail, at: position(el, node, offset) }});
+                }}
+            }});
+            document.addEventListener("selectionchange", () => {{
+                const selection = window.getSelection();
+                if (!selection.rangeCount || selection.isCollapsed) return;
+                if (!el.contains(selection.anchorNode) || !el.contains(selection.focusNode)) return;
+                const own = window.editorSelection ?? [];
+                if (own[0] === selection.anchorNode && own[1] === selection.anchorOffset
+                    && own[2] === selection.focusNode && own[3] === selection.focusOffset) return;
+                dioxus.send({{
+                    anchor: position(el, selection.anchorNode, selection.anchorOffset),
+                    focus: position(el, selection.focusNode, selection.focusOffset),
+                }});
+            }});
+            "#
+        );
+        let mut selections = document::eval(&js_code);
+        while let Ok(message) = selections.recv::<serde_json::Value>().await {
+            if render_mode() != RenderMode::Editing {
+                continue;
+            }
+            let to_source = |value: &serde_json::Value| {
+                dom_position(value).and_then(|pos| rendered.read().source_map.to_source(&pos))
+            };
+            let selection = match message["clicks"].as_u64() {
+                Some(clicks) => {
+                    let Some(pos) = to_source(&message["at"]) else { continue };
+                    let range = match clicks {
+                        2 => buffer.read().word_at(pos),
+                        _ => buffer.read().paragraph_at(pos),
+                    };
+                    range.unwrap_or(pos..pos)
                 }
-            } else {
+                None => {
+                    let (Some(anchor), Some(focus)) = (to_source(&message["anchor"]), to_source(&message["focus"])) else {
+                        continue;
+                    };
+                    anchor..focus
+                }
+            };
+            let current = buffer.read().selection().map(|(anchor, caret)| anchor..caret);
+            if current != Some(selection.clone()) {
+                buffer.write().select(selection);
+                caret_placed.set(true);
+            }
+        }
+    });
 
-                if !was_clicked {
-                    caret_pos.with_mut(|cp| *cp = None);
+    // Text the keydown handler can't see: IME compositions, dead-key accents, spelling
+    // corrections and context-menu pastes. The DOM is never edited in place; the text is
+    // inserted at the buffer's caret and the editor re-rendered from the buffer.
+    use_future(move || async move {
+        let mut inputs = document::eval(
+            r#"
+            const el = document.getElementById("editor");
+            // A composition has to edit the DOM to show its preedit text. Those edits are
+            // recorded and undone when it ends, so the DOM matches the buffer again.
+            const changes = [];
+            const observer = new MutationObserver((records) => changes.push(...records));
+            el.addEventListener("compositionstart", () => {
+                changes.length = 0;
+                observer.observe(el, { subtree: true, childList: true, characterData: true, characterDataOldValue: true });
+            });
+            el.addEventListener("compositionend", (e) => {
+                changes.push(...observer.takeRecords());
+                observer.disconnect();
+                for (const change of changes.reverse()) {
+                    if (change.type === "characterData") {
+                        change.target.data = change.oldValue;
+                    } else {
+                        for (const node of change.addedNodes) node.remove();
+                        for (const node of change.removedNodes) change.target.insertBefore(node, change.nextSibling);
+                    }
                 }
-                // println!("pos {:?}", pos);
+                changes.length = 0;
+                if (e.data) dioxus.send(e.data);
+            });
+            el.addEventListener("beforeinput", (e) => {
+                if (e.isComposing || e.inputType === "insertCompositionText") return;
+                e.preventDefault();
+                const text = e.inputType === "insertParagraph" || e.inputType === "insertLineBreak"
+                    ? "\n"
+                    : e.data ?? e.dataTransfer?.getData("text/plain");
+                if (text && e.inputType.startsWith("insert")) dioxus.send(text);
+            });
+            "#,
+        );
+        while let Ok(text) = inputs.recv::<String>().await {
+            if render_mode() == RenderMode::Editing && caret_placed() {
+                buffer.write().insert(&text);
             }
-            tokio::time::sleep(Duration::from_millis(1000)).await;
         }
     });
+
     // Helper: Update caret position by executing JS that computes the offset.
+    // With `add` (Alt+click) the click adds a caret instead of moving the caret.
     let update_caret_click = {
-        move || {
+        move |add: bool| {
+            if render_mode() != RenderMode::Editing {
+                return;
+            }
             spawn(
                 async move {
                     tokio::time::sleep(Duration::from_millis(10)).await;
-                    let js_code = r#"
+                    // Report the caret as [run index, UTF-16 offset in that run]. Selections
+                    // (dragging, double and triple click) are left to the selection listener.
+                    let js_code = format!(
+                        r#"
+                    {DOM_POSITION_JS}
                     let el = document.getElementById("editor");
                     let selection = window.getSelection();
-                    if (!selection.rangeCount) return 0;
-
-                    let range = selection.getRangeAt(0);
-                    let preCaretRange = range.cloneRange();
-                    preCaretRange.selectNodeContents(el);
-                    preCaretRange.setEnd(range.endContainer, range.endOffset);
-
-                    // Correctly traverse the text nodes and count characters
-                    let offset = 0;
-                    let treeWalker = document.createTreeWalker(el, NodeFilter.SHOW_TEXT, null, false);
-
-                    while (treeWalker.nextNode()) {
-                        let node = treeWalker.currentNode;
-                        if (node === range.endContainer) {
-                            offset += range.endOffset;
-                            break;
-                        }
-                        offset += node.textContent.length;
-                    }
-                    return offset;
-                "#;
-                    if let Ok(result) = document::eval(js_code).await {
-                        if let Ok(pos) = result.to_string().parse::<usize>() {
-                            println!("click {:?}", pos);
-                            caret_pos.with_mut(|cp| *cp = Some(pos));
-                            caret_queue.send(pos)
+                    if (!selection.rangeCount || !selection.isCollapsed || window.editorClicks > 1) return null;
+                    return position(el, selection.focusNode, selection.focusOffset);
+                "#
+                    );
+                    if let Ok(result) = document::eval(&js_code).await {
+                        let pos = dom_position(&result)
+                            .and_then(|dom_pos| rendered.read().source_map.to_source(&dom_pos))
+                            // An empty document has no text runs to click into.
+                            .or_else(|| raw_text.read().is_empty().then_some(0));
+                        if let Some(pos) = pos {
+                            if add && caret_placed() {
+                                buffer.write().add_caret(pos);
+                            } else {
+                                buffer.write().set_caret(pos);
+                            }
+                            caret_placed.set(true);
                         }
                     }
                 }
             );
         }
     };
-    
 
-    // Key handler: intercept key events and update our raw_text and caret.
+    // Key handler: translate key events into EditorBuffer commands.
     let handle_keydown = {
-        let mut raw_text = raw_text.clone();
-        let mut caret_pos = caret_pos.clone();
-        let mut selection_range = selection_range.clone();
-        let mut undo_stack = undo_stack.clone();
         move |evt: KeyboardEvent| {
-            let text = raw_text.read().clone();
+            if !*caret_placed.read() {
+                return;
+            }
+
+            // Keys that go to an IME composition, such as the Enter confirming it, are its own.
+            if evt.data().is_composing() {
+                return;
+            }
+
+            let key = evt.data().key();
+            let modifiers = evt.data().modifiers();
 
-            let pos = match *caret_pos.read() {
-                Some(pos) => pos,
-                None => return,
+            // Alt+Shift+Up/Down add a caret in the line above or below.
+            if modifiers.alt() && modifiers.shift() && matches!(key, Key::ArrowUp | Key::ArrowDown) {
+                evt.prevent_default();
+                buffer.write().add_caret_vertical(key == Key::ArrowUp);
+                return;
+            }
+            if key == Key::Escape && !buffer.read().extra_carets().is_empty() {
+                evt.prevent_default();
+                buffer.write().clear_extra_carets();
+                return;
+            }
+
+            // With Ctrl, arrows jump by word or paragraph and Home/End go to the document's ends.
+            let motion = match (&key, modifiers.ctrl()) {
+                (Key::ArrowLeft, false) => Some(Motion::Left),
+                (Key::ArrowRight, false) => Some(Motion::Right),
+                (Key::ArrowUp, false) => Some(Motion::Up),
+                (Key::ArrowDown, false) => Some(Motion::Down),
+                (Key::Home, false) => Some(Motion::LineStart),
+                (Key::End, false) => Some(Motion::LineEnd),
+                (Key::ArrowLeft, true) => Some(Motion::WordLeft),
+                (Key::ArrowRight, true) => Some(Motion::WordRight),
+                (Key::ArrowUp, true) => Some(Motion::ParagraphUp),
+                (Key::ArrowDown, true) => Some(Motion::ParagraphDown),
+                (Key::Home, true) => Some(Motion::DocumentStart),
+                (Key::End, true) => Some(Motion::DocumentEnd),
+                (Key::PageUp, _) => Some(Motion::PageUp),
+                (Key::PageDown, _) => Some(Motion::PageDown),
+                _ => None,
             };
 
-            println!{"handle keydown {:?}", pos}
-            // update_caret();
+            match (motion, &key) {
+                // If Shift is pressed, movement extends the selection.
+                (Some(motion), _) if modifiers.shift() => {
+                    evt.prevent_default();
+                    buffer.write().extend_selection(motion);
+                    return;
+                }
+                (Some(motion), _) => {
+                    evt.prevent_default();
+                    buffer.write().move_caret(motion);
+                    return;
+                }
+                (None, Key::Backspace) if modifiers.ctrl() => {
+                    evt.prevent_default();
+                    buffer.write().delete_motion(Motion::WordLeft);
+                    return;
+                }
+                (None, Key::Delete) if modifiers.ctrl() => {
+                    evt.prevent_default();
+                    buffer.write().delete_motion(Motion::WordRight);
+                    return;
+                }
+                _ => {}
+            }
 
-            // If CTRL is pressed, handle CTRL shortcuts first.
-            // If CTRL is pressed, handle CTRL shortcuts first.
-            if evt.data().modifiers().ctrl() {
-                let key_lower = evt.data().key().to_string();
-                match key_lower.as_str() {
+            // Other CTRL shortcuts.
+            if modifiers.ctrl() {
+                match key.to_string().to_lowercase().as_str() {
+                    "z" if modifiers.shift() => {
+                        evt.prevent_default();
+                        buffer.write().redo();
+                    }
                     "z" => {
                         evt.prevent_default();
-                        if let Some(previous) = undo_stack.write().pop() {
-                            raw_text.set(previous);
-                        }
+                        buffer.write().undo();
                     }
-                    "c" => {
+                    "y" => {
                         evt.prevent_default();
-                        if let Some((sel_start, sel_end)) = *selection_range.read() {
-                            let s = sel_start.min(sel_end);
-                            let e = sel_start.max(sel_end);
-                            let selected_text = &raw_text.read()[s..e];
-
-                            let mut clipboard = Clipboard::new().expect("Failed to open clipboard");
-                            clipboard.set_text(selected_text.to_string()).expect("Failed to copy to clipboard");
-                        }
+                        buffer.write().redo();
                     }
-                    "v" => {
+                    // Handled for the whole window, with Ctrl+S and Ctrl+Shift+E.
+                    "o" | "s" => {}
+                    "e" if modifiers.shift() => {}
+                    // Ctrl+Enter checks or unchecks the task under the caret.
+                    "enter" => {
                         evt.prevent_default();
-                        let mut clipboard = Clipboard::new().expect("Failed to open clipboard");
-                        if let Ok(paste_text) = clipboard.get_text() {
-                            let current = raw_text.read().clone();
-                            let pos = caret_pos.read().unwrap_or(0);
-                            undo_stack.write().push(current.clone());
-                            let (left, right) = current.split_at(pos);
-                            let new_text = format!("{}{}{}", left, paste_text, right);
-                            raw_text.set(new_text);
-
-                            caret_pos.with_mut(|cp| *cp = Some(pos + paste_text.len()));
-                            caret_queue.send(pos + paste_text.len());
+                        let edit = toggle_task_on_line(buffer.read().text(), buffer.read().caret());
+                        if let Some(edit) = edit {
+                            buffer.write().replace_ranges(&[edit]);
                         }
                     }
-                    "x" => {
+                    "d" => {
                         evt.prevent_default();
-                        let sel_range = *selection_range.read();
-                        if let Some((sel_start, sel_end)) = sel_range {
-                            let s = sel_start.min(sel_end);
-                            let e = sel_start.max(sel_end);
-                            let selected_text = &raw_text.read().clone()[s..e];
-
-                            let mut clipboard = Clipboard::new().expect("Failed to open clipboard");
-                            clipboard.set_text(selected_text.to_string()).expect("Failed to copy to clipboard");
-
-                            // Delete selected text after copying
-                            undo_stack.write().push(raw_text.read().clone());
-                            let current = raw_text.read().clone();
-                            let new_text = format!("{}{}", &current[0..s], &current[e..]);
-                            raw_text.set(new_text);
-                            caret_pos.with_mut(|cp| *cp = Some(s));
-                            caret_queue.send(s);
-                            selection_range.set(None);
-                        }
+                        buffer.write().select_next_occurrence();
                     }
-                    _ => {}
-                }
-                set_caret();
-                // caret_queue.send();
-                return;
-            }
-
-            // If Shift is pressed, handle selection highlighting.
-            if evt.data().modifiers().shift() && matches!(evt.data().key(), Key::ArrowLeft | Key::ArrowRight | Key::ArrowUp | Key::ArrowDown | Key::Home | Key::End) {
-                match evt.data().key() {
-                    Key::ArrowLeft => {
+                    "f" | "h" => {
                         evt.prevent_default();
-                        if pos > 0 {
-                            let new_pos = pos - 1;
-                            caret_pos.with_mut(|cp| *cp = Some(new_pos));
-                            
-                            caret_queue.send(new_pos);
-                            selection_range.with_mut(|sel_range| {
-                                if let Some((anchor, _)) = *sel_range {
-                                    *sel_range = Some((anchor, new_pos));
-                                } else {
-                                    *sel_range = Some((pos, new_pos));
-                                }
-                            });
+                        // Search for the selection, if it is a short single line.
+                        let selected = buffer.read().selected_text().map(str::to_string);
+                        if let Some(selected) = selected.filter(|text| !text.contains('\n')) {
+                            search.write().query = selected;
                         }
+                        if key.to_string().to_lowercase() == "h" {
+                            show_replace.set(true);
+                        }
+                        find_open.set(true);
+                        spawn(async move {
+                            document::eval("document.getElementById('find-query')?.select()").await.ok();
+                        });
                     }
-                    Key::ArrowRight => {
+                    "e" => {
                         evt.prevent_default();
-                        if pos < text.len() {
-                            let new_pos = pos + 1;
-                            caret_pos.with_mut(|cp| *cp = Some(new_pos));
-                            
-                            caret_queue.send(new_pos);
-                            selection_range.with_mut(|sel_range| {
-                                if let Some((anchor, _)) = *sel_range {
-                                    *sel_range = Some((anchor, new_pos));
-                                } else {
-                                    *sel_range = Some((pos, new_pos));
-                                }
-                            });
-                        }
+                        toggle_format(document, buffer, Inline::Code);
                     }
-                    Key::ArrowUp => {
+                    "b" => {
                         evt.prevent_default();
-                        let lines: Vec<&str> = text.split('\n').collect();
-                        let mut cumulative = 0;
-                        let mut current_line = 0;
-                        for (i, line) in lines.iter().enumerate() {
-                            if pos >= cumulative && pos <= cumulative + line.len() {
-                                current_line = i;
-                                break;
-                            }
-                            cumulative += line.len() + 1;
-                        }
-                        if current_line > 0 {
-                            let col = pos - cumulative;
-                            let prev_line = lines[current_line - 1];
-                            let new_col = col.min(prev_line.len());
-                            let mut new_pos = 0;
-                            for i in 0..(current_line - 1) {
-                                new_pos += lines[i].len() + 1;
-                            }
-                            new_pos += new_col;
-                            caret_pos.with_mut(|cp| *cp = Some(new_pos));
-                            
-                            caret_queue.send(new_pos);
-                            selection_range.with_mut(|sel_range| {
-                                if let Some((anchor, _)) = *sel_range {
-                                    *sel_range = Some((anchor, new_pos));
-                                } else {
-                                    *sel_range = Some((pos, new_pos));
-                                }
-                            });
-                        }
+                        toggle_format(document, buffer, Inline::Strong);
                     }
-                    Key::ArrowDown => {
+                    "i" => {
                         evt.prevent_default();
-                        let lines: Vec<&str> = text.split('\n').collect();
-                        let mut cumulative = 0;
-                        let mut current_line = 0;
-                        for (i, line) in lines.iter().enumerate() {
-                            if pos >= cumulative && pos <= cumulative + line.len() {
-                                current_line = i;
-                                break;
-                            }
-                            cumulative += line.len() + 1;
-                        }
-                        if current_line < lines.len() - 1 {
-                            let col = pos - cumulative;
-                            let next_line = lines[current_line + 1];
-                            let new_col = col.min(next_line.len());
-                            let mut new_pos = cumulative + lines[current_line].len() + 1;
-                            new_pos += new_col;
-                            caret_pos.with_mut(|cp| *cp = Some(new_pos));
-                            
-                            caret_queue.send(new_pos);
-                            selection_range.with_mut(|sel_range| {
-                                if let Some((anchor, _)) = *sel_range {
-                                    *sel_range = Some((anchor, new_pos));
-                                } else {
-                                    *sel_range = Some((pos, new_pos));
-                                }
-                            });
-                        }
+                        toggle_format(document, buffer, Inline::Emphasis);
+                    }
+                    "k" => {
+                        evt.prevent_default();
+                        toggle_format(document, buffer, Inline::Link);
                     }
-                    Key::Home => {
+                    "x" if modifiers.shift() => {
                         evt.prevent_default();
-                        let lines: Vec<&str> = text.split('\n').collect();
-                        let mut cumulative = 0;
-                        for line in &lines {
-                            if pos >= cumulative && pos <= cumulative + line.len() {
-                                caret_pos.with_mut(|cp| *cp = Some(cumulative));
-                                
-                                caret_queue.send(cumulative);
-                                selection_range.with_mut(|sel_range| {
-                                    if let Some((anchor, _)) = *sel_range {
-                                        *sel_range = Some((anchor, cumulative));
-                                    } else {
-                                        *sel_range = Some((pos, cumulative));
-                                    }
-                                });
-                                break;
+                        toggle_format(document, buffer, Inline::Strikethrough);
+                    }
+                    level @ ("1" | "2" | "3" | "4" | "5" | "6") => {
+                        evt.prevent_default();
+                        let level = level.parse().unwrap_or(1);
+                        let options = document.read().options.to_parser_options();
+                        let mut buffer = buffer.write();
+                        let toggle = toggle_heading(buffer.text(), buffer.caret(), level, options);
+                        buffer.replace_ranges_and_select(&toggle.edits, Some(toggle.selection));
+                    }
+                    "c" => {
+                        evt.prevent_default();
+                        copy_selection(document, buffer);
+                    }
+                    "v" => {
+                        evt.prevent_default();
+                        let mut clipboard = match Clipboard::new() {
+                            Ok(clipboard) => clipboard,
+                            Err(err) => {
+                                let err = anyhow::Error::new(err).context("Failed to paste from the clipboard");
+                                spawn(async move { show_error(&err).await });
+                                return;
                             }
-                            cumulative += line.len() + 1;
+                        };
+                        let plain_text = clipboard.get_text().ok().filter(|text| !text.is_empty());
+                        // An image without text alongside it is saved as an asset file.
+                        if let (None, Ok(image)) = (&plain_text, clipboard.get_image()) {
+                            match encode_png(image) {
+                                Ok(png) => {
+                                    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |time| time.as_secs());
+                                    spawn(insert_images(document, buffer, vec![(format!("image-{seconds}.png"), png)]));
+                                }
+                                Err(err) => {
+                                    spawn(async move { show_error(&err).await });
+                                }
+                            }
+                            return;
+                        }
+                        // Rich text is pasted as Markdown; Ctrl+Shift+V pastes it as plain text.
+                        let markdown = match modifiers.shift() {
+                            true => None,
+                            false => clipboard.get().html().ok().and_then(|html| markdown_from_html(&html)),
+                        };
+                        if let Some(paste_text) = markdown.or(plain_text) {
+                            buffer.write().paste(&paste_text);
                         }
                     }
-                    Key::End => {
+                    "x" => {
                         evt.prevent_default();
-                        let lines: Vec<&str> = text.split('\n').collect();
-                        let mut cumulative = 0;
-                        for line in &lines {
-                            if pos >= cumulative && pos <= cumulative + line.len() {
-                                let new_pos = cumulative + line.len();
-                                caret_pos.with_mut(|cp| *cp = Some(new_pos));
-                                
-                                caret_queue.send(new_pos);
-                                selection_range.with_mut(|sel_range| {
-                                    if let Some((anchor, _)) = *sel_range {
-                                        *sel_range = Some((anchor, new_pos));
-                                    } else {
-                                        *sel_range = Some((pos, new_pos));
-                                    }
-                                });
-                                break;
-                            }
-                            cumulative += line.len() + 1;
+                        if copy_selection(document, buffer) {
+                            // Delete selected text after copying
+                            buffer.write().delete_selection();
                         }
                     }
                     _ => {}
                 }
-                set_caret();
                 return;
             }
 
-
-            // Normal key handling (non-CTRL, non-SHIFT)
-            match evt.data.key() {
-                // Insert newline at the caret when Enter is pressed.
-                Key::Enter => {
-                    evt.prevent_default();
-                    let (left, right) = text.split_at(pos);
-                    undo_stack.write().push(text.clone());
-                    let new_text = format!("{}\n{}", left, right);
-                    raw_text.set(new_text);
-
-                    let new_pos = pos + 1;
-                    caret_pos.with_mut(|cp| {*cp = Some(new_pos);});
-                    
-                    caret_queue.send(new_pos);
-                }
-                // Remove character before caret for Backspace.
-                // Remove character before caret for Backspace.
-                Key::Backspace => {
-                    evt.prevent_default();
-                    let sel_range = *selection_range.read();
-                    if let Some((sel_start, sel_end)) = sel_range {
-                        let s = sel_start.min(sel_end);
-                        let e = sel_start.max(sel_end);
-                        undo_stack.write().push(text.clone());
-                        let new_text = format!("{}{}", &text[0..s], &text[e..]);
-                        raw_text.set(new_text);
-                        caret_pos.with_mut(|cp| *cp = Some(s));
-                        caret_queue.send(s);
-                        selection_range.set(None);
-                    } else if pos > 0 {
-                        let mut chars: Vec<char> = text.chars().collect();
-                        undo_stack.write().push(text.clone());
-                        chars.remove(pos - 1);
-                        let new_text: String = chars.into_iter().collect();
-                        raw_text.set(new_text);
-                        caret_pos.with_mut(|cp| {
-                            *cp = Some(pos - 1);
-                        });
-                        caret_queue.send(pos - 1);
-                    }
-                },
-                // Delete key: remove character after caret.
-                Key::Delete => {
+            match key {
+                // In tables, Enter adds a row (or ends the table on an empty last row).
+                Key::Enter if !modifiers.shift() && buffer.read().extra_carets().is_empty() && caret_in_table() => {
                     evt.prevent_default();
-                    let sel_range = *selection_range.read();
-                    if let Some((sel_start, sel_end)) = sel_range {
-                        let s = sel_start.min(sel_end);
-                        let e = sel_start.max(sel_end);
-                        undo_stack.write().push(text.clone());
-                        let new_text = format!("{}{}", &text[0..s], &text[e..]);
-                        raw_text.set(new_text);
-                        caret_pos.with_mut(|cp| *cp = Some(s));
-                        caret_queue.send(s);
-
-                        selection_range.set(None);
-                    } else if pos < text.len() {
-                        let mut chars: Vec<char> = text.chars().collect();
-                        undo_stack.write().push(text.clone());
-                        chars.remove(pos);
-                        let new_text: String = chars.into_iter().collect();
-                        raw_text.set(new_text);
-                        caret_pos.with_mut(|cp| *cp = Some(pos));
-                        
-                        caret_queue.send(pos);
-                    }
-                },
-                // Move caret left.
-                Key::ArrowLeft => {
-                    evt.prevent_default();
-                    if pos > 0 {
-                        caret_pos.with_mut(|cp| *cp = Some(pos - 1));
-                        caret_queue.send(pos - 1);
+                    let options = document.read().options.to_parser_options();
+                    let continued = continue_table(buffer.read().text(), buffer.read().caret(), options);
+                    if let Some((text, selection)) = continued {
+                        buffer.write().replace_text_and_select(text, selection);
                     }
                 }
-                // Move caret right.
-                Key::ArrowRight => {
+                // In lists and blockquotes, Enter continues (or ends) them.
+                Key::Enter if !modifiers.shift() && buffer.read().selection().is_none() && buffer.read().extra_carets().is_empty() => {
                     evt.prevent_default();
-                    if pos < text.len() {
-                        caret_pos.with_mut(|cp| *cp = Some(pos + 1));
-                        caret_queue.send(pos + 1);
+                    let options = document.read().options.to_parser_options();
+                    let continued = continue_list(buffer.read().text(), buffer.read().caret(), options);
+                    match continued {
+                        Some((text, selection)) => buffer.write().replace_text_and_select(text, selection),
+                        None => buffer.write().insert("\n"),
                     }
                 }
-                // Move caret up.
-                Key::ArrowUp => {
+                Key::Enter => {
                     evt.prevent_default();
-                    let lines: Vec<&str> = text.split('\n').collect();
-                    let mut cumulative = 0;
-                    let mut current_line = 0;
-                    for (i, line) in lines.iter().enumerate() {
-                        if pos >= cumulative && pos <= cumulative + line.len() {
-                            current_line = i;
-                            break;
-                        }
-                        cumulative += line.len() + 1;
-                    }
-                    if current_line > 0 {
-                        let col = pos - cumulative;
-                        let prev_line = lines[current_line - 1];
-                        let new_col = col.min(prev_line.len());
-                        let mut new_pos = 0;
-                        for i in 0..(current_line - 1) {
-                            new_pos += lines[i].len() + 1;
-                        }
-                        new_pos += new_col;
-                        caret_pos.with_mut(|cp| *cp = Some(new_pos));
-                        
-                        caret_queue.send(new_pos);
-                    }
+                    buffer.write().insert("\n");
                 }
-                // Move caret down.
-                Key::ArrowDown => {
+                // Tab and Shift+Tab move between table cells, and indent and outdent list items.
+                Key::Tab if caret_in_table() => {
                     evt.prevent_default();
-                    let lines: Vec<&str> = text.split('\n').collect();
-                    let mut cumulative = 0;
-                    let mut current_line = 0;
-                    for (i, line) in lines.iter().enumerate() {
-                        if pos >= cumulative && pos <= cumulative + line.len() {
-                            current_line = i;
-                            break;
-                        }
-                        cumulative += line.len() + 1;
+                    let options = document.read().options.to_parser_options();
+                    let moved = next_cell(buffer.read().text(), buffer.read().caret(), modifiers.shift(), options);
+                    if let Some((text, selection)) = moved {
+                        buffer.write().replace_text_and_select(text, selection);
                     }
-                    if current_line < lines.len() - 1 {
-                        let col = pos - cumulative;
-                        let next_line = lines[current_line + 1];
-                        let new_col = col.min(next_line.len());
-                        let mut new_pos = cumulative + lines[current_line].len() + 1;
-                        new_pos += new_col;
-                        caret_pos.with_mut(|cp| *cp = Some(new_pos));
-                        
-                        caret_queue.send(new_pos);
+                }
+                Key::Tab => {
+                    let selection = buffer.read().selection_bounds().unwrap_or((buffer.read().caret(), buffer.read().caret()));
+                    let options = document.read().options.to_parser_options();
+                    let indented = indent_items(buffer.read().text(), selection.0..selection.1, modifiers.shift(), options);
+                    if let Some((text, selection)) = indented {
+                        evt.prevent_default();
+                        buffer.write().replace_text_and_select(text, selection);
                     }
                 }
-                // Home: move caret to beginning of current line.
-                Key::Home => {
+                Key::Backspace => {
                     evt.prevent_default();
-                    let lines: Vec<&str> = text.split('\n').collect();
-                    let mut cumulative = 0;
-                    for line in &lines {
-                        if pos >= cumulative && pos <= cumulative + line.len() {
-                            caret_pos.with_mut(|cp| *cp = Some(cumulative));
-                            
-                            caret_queue.send(cumulative);
-                            break;
-                        }
-                        cumulative += line.len() + 1;
-                    }
+                    buffer.write().delete_backward();
                 }
-                // End: move caret to end of current line.
-                Key::End => {
+                Key::Delete => {
                     evt.prevent_default();
-                    let lines: Vec<&str> = text.split('\n').collect();
-                    let mut cumulative = 0;
-                    for line in &lines {
-                        if pos >= cumulative && pos <= cumulative + line.len() {
-                            caret_pos.with_mut(|cp| *cp = Some(cumulative + line.len()));
-                            
-                            caret_queue.send(cumulative + line.len());
-                            break;
-                        }
-                        cumulative += line.len() + 1;
-                    }
+                    buffer.write().delete_forward();
                 }
                 // For printable characters, insert them at the current position.
-                _ => {
-                    if let Key::Character(ch) = &evt.data.key() {
-                        undo_stack.with_mut(|stack| stack.push(text.clone()));
-                        let (left, right) = text.split_at(pos);
-                        let new_text = format!("{}{}{}", left, ch, right);
-                        raw_text.set(new_text);
-                        
-                        let new_pos = pos + ch.len();
-                        caret_pos.with_mut(|cp| *cp = Some(new_pos));
-                        caret_queue.send(new_pos);
-                    }
+                Key::Character(ch) => {
+                    evt.prevent_default();
+                    buffer.write().insert(&ch);
                 }
+                _ => {}
             }
-            selection_range.with_mut(|sel_range| {
-                    
-                *sel_range = None;
-                
-            });            
-        
-            return;
-
-
-
         }
     };
 
-    let handle_input = {
-        let mut raw_text = raw_text.clone();
-        let mut prev_raw_text = prev_raw_text.clone();
-        move |_| {
-            spawn(async move {
-                let js_code = r#"
-                    (function() {
-                        const editor = document.getElementById('editor');
-                        return editor?.innerText ?? '';
-                    })();
-                "#;
-                
-                if let Ok(js_result) = document::eval(js_code).await {
-                    let new_text = js_result.as_str()
-                        .unwrap_or_default()
-                        .replace('\u{200b}', "");  // Remove zero-width spaces
-                    
-                    if !new_text.is_empty() && new_text != *raw_text.read() {
-                        prev_raw_text.set(new_text.to_string());
-                        raw_text.set(new_text.to_string());
-                        set_caret();
-                    }
-                }
-            });
-        }
-    };
+    let presets = [
+        ("CommonMark", RendererOptions::commonmark()),
+        ("GFM", RendererOptions::gfm()),
+        ("Everything", RendererOptions::everything()),
+    ];
 
     rsx! {
+        style { {EDITOR_CSS} }
         div {
             style: "display: flex; gap: 20px; padding: 20px;",
 
@@ -650,31 +826,120 @@ fn App() -> Element {
             div {
                 style: "flex: 1; border: 1px solid #ccc; padding: 8px;",
                 h3 { "Editor (Content Editable)" }
+                button {
+                    onclick: move |_| { spawn(export_document(document, buffer)); },
+                    "Export HTML"
+                }
+                button {
+                    title: "Copy the selection, or the whole document, as Markdown source",
+                    onclick: move |_| copy_as(document, buffer, false),
+                    "Copy as Markdown"
+                }
+                button {
+                    title: "Copy the selection, or the whole document, as HTML source",
+                    onclick: move |_| copy_as(document, buffer, true),
+                    "Copy as HTML"
+                }
+                button {
+                    onclick: move |_| {
+                        let mode = match render_mode() {
+                            RenderMode::Editing => RenderMode::Reading,
+                            RenderMode::Reading => RenderMode::Editing,
+                        };
+                        // The caret has to be placed again after reading.
+                        caret_placed.set(false);
+                        render_mode.set(mode);
+                    },
+                    if render_mode() == RenderMode::Editing { "Reading View" } else { "Edit" }
+                }
+                // Markdown flavour for this document.
+                select {
+                    onchange: move |evt| {
+                        if let Some((_, options)) = presets.iter().find(|(name, _)| *name == evt.value()) {
+                            let code_theme = document.read().options.code_theme;
+                            document.write().options = RendererOptions { code_theme, ..*options };
+                        }
+                    },
+                    for (name, options) in presets {
+                        option {
+                            value: name,
+                            selected: options.to_parser_options() == render_options().to_parser_options(),
+                            "{name}"
+                        }
+                    }
+                }
+                // Code block highlighting theme.
+                select {
+                    onchange: move |evt| {
+                        if let Some(theme) = CodeTheme::ALL.into_iter().find(|theme| theme.name() == evt.value()) {
+                            document.write().options.code_theme = theme;
+                        }
+                    },
+                    for theme in CodeTheme::ALL {
+                        option { value: theme.name(), selected: theme == render_options().code_theme, "{theme.name()}" }
+                    }
+                }
+                label {
+                    title: "Show the source of diagrams even when the caret isn't in them",
+                    input {
+                        r#type: "checkbox",
+                        checked: show_rendered_source(),
+                        onchange: move |evt| show_rendered_source.set(evt.checked()),
+                    }
+                    "Diagram source"
+                }
+                label {
+                    title: "Folder next to the document that pasted and dropped images are saved in",
+                    " Images: "
+                    input {
+                        size: 10,
+                        value: "{document.read().assets_dir}",
+                        oninput: move |evt| document.write().assets_dir = evt.value(),
+                    }
+                }
+                if render_options().metadata_blocks {
+                    PropertiesPanel { buffer, metadata }
+                }
+                if caret_in_table() {
+                    TableBar { buffer, options: render_options }
+                }
+                if find_open() {
+                    FindBar { buffer, search, matches, open: find_open, show_replace }
+                }
                 // The contenteditable div uses an id ("editor") for JS interop.
                 div {
                     id: "editor",
-                    contenteditable: "true",
+                    class: if show_rendered_source() { "show-rendered-source" } else { "" },
+                    contenteditable: if render_mode() == RenderMode::Editing { "true" } else { "false" },
                     style: "height: 200px; overflow-y: auto; white-space: pre-wrap; border: 1px solid #aaa; padding: 8px;",
                     onkeydown: handle_keydown,
-                    oninput: handle_input,
-                    onmouseup: move |e| { update_caret_click(); },
-                    onclick: move |e| { update_caret_click(); },
+                    onmouseup: move |e| { update_caret_click(e.modifiers().alt()); },
+                    // Dropped image files are copied to the assets folder.
+                    ondragover: move |evt| evt.prevent_default(),
+                    ondrop: move |evt| async move {
+                        evt.prevent_default();
+                        let Some(files) = evt.files().filter(|_| render_mode() == RenderMode::Editing) else {
+                            return;
+                        };
+                        let mut images = Vec::new();
+                        for name in files.files().into_iter().filter(|name| is_image(name)) {
+                            if let Some(bytes) = files.read_file(&name).await {
+                                images.push((name, bytes));
+                            }
+                        }
+                        insert_images(document, buffer, images).await;
+                    },
+                    onclick: move |e| { update_caret_click(e.modifiers().alt()); },
                     // Here we simply display the raw text.
                     // In a more advanced version you might run a syntax highlighter
                     // to wrap tokens in spans for color/styling.
 
-                    { preview_nodes().clone().into_iter() }
-          
+                    // Keyed per block, so Dioxus only patches the blocks an edit touched.
+                    for (key, node) in rendered.read().block_keys.iter().zip(rendered.read().nodes.iter()) {
+                        Fragment { key: "{key}", {node.clone()} }
+                    }
                 }
             },
-
-            // Preview Pane: A read-only live preview rendered using MarkdownRenderer.
-            div {
-                id: "preview",
-                
-                style: "display: none; flex: 1; border: 1px solid #ccc; padding: 8px;",
-                { preview_nodes().clone().into_iter() }
-            }
         },
         // Debug info: show raw state and caret position.
         div {
@@ -683,20 +948,169 @@ fn App() -> Element {
             pre { "{raw_text}" },
             "Caret Position: ",
             {
-            if let Some(pos) = *caret_pos.read() {
-                rsx!{ "{pos}" }
+            if *caret_placed.read() {
+                rsx!{ "{buffer.read().caret()}" }
             } else {
                 rsx!{ "None" }
             }}
             br {},
             "Selection Range: ",
             {
-                if let Some((start, end)) = *selection_range.read() {
+                if let Some((start, end)) = buffer.read().selection() {
                     rsx! { "Start: {start}, End: {end}" }
                 } else {
                     rsx! { "None" }
                 }
-            }            
+            }
+        }
+    }
+}
+
+/// Saves the document, asking for a path first if it has none or `save_as` is set.
+/// Returns whether the text was written.
+async fn save_document(mut document: Signal<Document>, buffer: Signal<EditorBuffer>, save_as: bool) -> bool {
+    let path = match document.read().path() {
+        Some(path) if !save_as => Some(path.to_path_buf()),
+        _ => None,
+    };
+    let result = match path {
+        Some(_) => document.write().save(buffer.read().text()),
+        None => {
+            let current = document.read().clone();
+            let Some(path) = pick_save_path(&current).await else {
+                return false;
+            };
+            document.write().save_as(path, buffer.read().text())
+        }
+    };
+    match result {
+        Ok(()) => true,
+        Err(err) => {
+            show_error(&err).await;
+            false
+        }
+    }
+}
+
+/// Asks what to do with unsaved changes, saving them if requested.
+/// Returns whether it is fine to throw the current text away.
+async fn resolve_unsaved(document: Signal<Document>, buffer: Signal<EditorBuffer>) -> bool {
+    if !document.read().is_dirty(buffer.read().text()) {
+        return true;
+    }
+    let current = document.read().clone();
+    match confirm_unsaved(&current).await {
+        UnsavedChoice::Save => save_document(document, buffer, false).await,
+        UnsavedChoice::Discard => true,
+        UnsavedChoice::Cancel => false,
+    }
+}
+
+/// Replaces the editor contents with a file picked by the user.
+async fn open_document(mut document: Signal<Document>, mut buffer: Signal<EditorBuffer>) {
+    if !resolve_unsaved(document, buffer).await {
+        return;
+    }
+    let Some(path) = pick_open_path().await else {
+        return;
+    };
+    match Document::open(path) {
+        Ok((opened, text)) => {
+            document.set(opened);
+            buffer.set(EditorBuffer::new(text));
+        }
+        Err(err) => show_error(&err).await,
+    }
+}
+
+/// Exports the editor contents as a standalone HTML file picked by the user.
+async fn export_document(document: Signal<Document>, buffer: Signal<EditorBuffer>) {
+    let current = document.read().clone();
+    let Some(path) = pick_export_path(&current).await else {
+        return;
+    };
+    if let Err(err) = current.export_html(&path, buffer.read().text()) {
+        show_error(&err).await;
+    }
+}
+
+/// Ctrl+C/Ctrl+X: puts the selected Markdown on the clipboard, with its rendering as the
+/// HTML flavour so rich text editors keep the formatting. Returns whether anything was copied.
+fn copy_selection(document: Signal<Document>, buffer: Signal<EditorBuffer>) -> bool {
+    let Some(markdown) = buffer.read().copy_text() else {
+        return false;
+    };
+    let html = clipboard_html(&html_fragment(&markdown, document.read().options));
+    set_clipboard(|clipboard| clipboard.set().html(html, Some(markdown)))
+}
+
+/// Puts the selection (or the whole document) on the clipboard as plain text: either the
+/// Markdown source or the HTML it renders to.
+fn copy_as(document: Signal<Document>, buffer: Signal<EditorBuffer>, as_html: bool) {
+    let markdown = buffer.read().copy_text().unwrap_or_else(|| buffer.read().text().to_string());
+    let text = match as_html {
+        true => html_fragment(&markdown, document.read().options),
+        false => markdown,
+    };
+    set_clipboard(|clipboard| clipboard.set_text(text));
+}
+
+/// Runs `set` on the clipboard, reporting failures. Returns whether it succeeded.
+fn set_clipboard(set: impl FnOnce(&mut Clipboard) -> Result<(), arboard::Error>) -> bool {
+    match Clipboard::new().and_then(|mut clipboard| set(&mut clipboard)) {
+        Ok(()) => true,
+        Err(err) => {
+            let err = anyhow::Error::new(err).context("Failed to copy to the clipboard");
+            spawn(async move { show_error(&err).await });
+            false
         }
     }
 }
+
+/// A `[block, run, offset]` position reported by the DOM scripts.
+fn dom_position(value: &serde_json::Value) -> Option<DomPosition> {
+    let pos = value.as_array()?;
+    Some(DomPosition {
+        block: pos.first()?.as_str()?.to_string(),
+        run: pos.get(1)?.as_u64()? as usize,
+        offset: pos.get(2)?.as_u64()? as usize,
+    })
+}
+
+/// `pos` as the `[block, run, offset]` the DOM scripts take.
+fn dom_value(pos: &DomPosition) -> serde_json::Value {
+    serde_json::json!([pos.block, pos.run, pos.offset])
+}
+
+/// Stores images (file names and contents) in the document's assets folder and inserts
+/// references to them at the caret.
+async fn insert_images(document: Signal<Document>, mut buffer: Signal<EditorBuffer>, images: Vec<(String, Vec<u8>)>) {
+    let mut references = Vec::new();
+    for (name, bytes) in images {
+        let added = document.read().add_asset(&name, &bytes);
+        match added {
+            Ok(path) => references.push(image_markdown(&path)),
+            Err(err) => {
+                show_error(&err).await;
+                break;
+            }
+        }
+    }
+    if !references.is_empty() {
+        buffer.write().paste(&references.join("\n"));
+    }
+}
+
+/// Toggles `kind` around the selection, or the word at the caret.
+fn toggle_format(document: Signal<Document>, mut buffer: Signal<EditorBuffer>, kind: Inline) {
+    let options = document.read().options.to_parser_options();
+    let mut buffer = buffer.write();
+    let caret = buffer.caret();
+    let target = buffer
+        .selection_bounds()
+        .map(|(start, end)| start..end)
+        .or_else(|| buffer.word_at(caret))
+        .unwrap_or(caret..caret);
+    let toggle = toggle_inline(buffer.text(), target, kind, options);
+    buffer.replace_ranges_and_select(&toggle.edits, Some(toggle.selection));
+}
@@ -1,14 +1,22 @@
 use std::{
     collections::HashMap,
     fmt::{self, Display},
+    ops::Range,
 };
 
 use dioxus::prelude::*;
 use pulldown_cmark::{
-    Alignment, BlockQuoteKind, CodeBlockKind, CowStr, Event, HeadingLevel, LinkType, Parser, Tag,
-    TagEnd,
+    Alignment, BlockQuoteKind, CodeBlockKind, Event, HeadingLevel, LinkType, OffsetIter,
+    Options, Parser, Tag, TagEnd,
 };
 
+use crate::diagram::DIAGRAMS;
+use crate::highlight::{CodeHighlighter, CodeTheme, Tokens};
+use crate::math::to_mathml;
+use crate::metadata::Metadata;
+use crate::render_cache::{cache_key, RenderCache};
+use crate::source_map::SourceMap;
+
 fn join_strings<T>(mut strings: impl Iterator<Item = T>, separator: impl Display) -> Option<String>
 where
     T: Display,
@@ -28,7 +36,7 @@ where
     Some(result)
 }
 
-struct HtmlEscaped<S>(S);
+pub(crate) struct HtmlEscaped<S>(S);
 
 impl<S> fmt::Display for HtmlEscaped<S>
 where
@@ -48,11 +56,11 @@ where
     }
 }
 
-fn escape_html<S>(s: S) -> HtmlEscaped<S> {
+pub(crate) fn escape_html<S>(s: S) -> HtmlEscaped<S> {
     HtmlEscaped(s)
 }
 
-struct HtmlBodyEscaped<S>(S);
+pub(crate) struct HtmlBodyEscaped<S>(S);
 
 impl<S> fmt::Display for HtmlBodyEscaped<S>
 where
@@ -75,11 +83,11 @@ where
     }
 }
 
-fn escape_html_body<S>(s: S) -> HtmlBodyEscaped<S> {
+pub(crate) fn escape_html_body<S>(s: S) -> HtmlBodyEscaped<S> {
     HtmlBodyEscaped(s)
 }
 
-struct HrefEscaped<S>(S);
+pub(crate) struct HrefEscaped<S>(S);
 
 impl<S> fmt::Display for HrefEscaped<S>
 where
@@ -99,184 +107,506 @@ where
     }
 }
 
-fn escape_href<S>(s: S) -> HrefEscaped<S> {
+pub(crate) fn escape_href<S>(s: S) -> HrefEscaped<S> {
     HrefEscaped(s)
 }
 
+/// Turns heading text into an `id`, GitHub style: lowercase, spaces to dashes, punctuation dropped.
+fn slugify(text: &str) -> String {
+    text.trim()
+        .chars()
+        .filter_map(|ch| match ch {
+            ch if ch.is_alphanumeric() => Some(ch.to_lowercase().next().unwrap_or(ch)),
+            ' ' | '-' | '_' => Some(if ch == '_' { '_' } else { '-' }),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Highlighting of the fenced code block being rendered, one text event at a time.
+struct HighlightedCode {
+    /// Cache key for the block's tokens, from its language, theme and source.
+    key: u64,
+    /// Tokens of each text event: all of them if they were cached, else those so far.
+    tokens: Vec<Tokens>,
+    /// How many text events have been rendered.
+    next: usize,
+    /// Set while highlighting a block that wasn't cached.
+    highlighter: Option<CodeHighlighter>,
+}
+
 enum TableState {
     Head,
     Body,
 }
 
 struct Context<'a> {
-    numbers: HashMap<CowStr<'a>, usize>,
+    source: &'a str,
+    mode: RenderMode,
+    source_map: SourceMap,
+    /// How often each heading id has been used, to keep generated ids unique.
+    heading_ids: HashMap<String, usize>,
+    /// Called with a task box's source offset when its checkbox is clicked.
+    on_task_toggle: Option<Callback<usize>>,
+    code_theme: CodeTheme,
+    /// Highlighting for the fenced code block being rendered, if its language is known.
+    highlighted_code: Option<HighlightedCode>,
+    cache: &'a RenderCache,
+    code_block_renderers: &'static [(&'static str, CodeBlockRenderer)],
+    /// Source of the code block being rendered by one of `code_block_renderers`, collected
+    /// as its text is pulled.
+    rendered_code: Option<String>,
+    /// The document's front matter, once it has been pulled.
+    metadata: Option<Metadata>,
+    /// Footnote numbers by label, in the order the footnotes are first referenced.
+    footnote_numbers: HashMap<String, usize>,
+    /// How many references each footnote number has in the document.
+    footnote_references: HashMap<usize, usize>,
+    /// How many references to each footnote number have been rendered so far.
+    footnote_references_seen: HashMap<usize, usize>,
+    /// Footnote definitions collected for the footnotes section at the end, while reading.
+    footnotes: Vec<(usize, Element)>,
     table_state: TableState,
     table_alignments: Vec<Alignment>,
     table_cell_index: usize,
     in_non_writing_block: bool,
+    /// Key of the top-level block being rendered, until it's taken once the block is done.
+    block_key: Option<String>,
+    /// How many blocks with each source text there have been so far.
+    block_occurrences: HashMap<u64, usize>,
 }
 
 impl<'a> Context<'a> {
-    pub fn new() -> Self {
+    pub fn new(
+        source: &'a str,
+        mode: RenderMode,
+        options: &RendererOptions,
+        on_task_toggle: Option<Callback<usize>>,
+        cache: &'a RenderCache,
+    ) -> Self {
+        let (footnote_numbers, footnote_references) = number_footnotes(source, options);
         Context {
+            source,
+            mode,
+            source_map: SourceMap::new(),
+            heading_ids: HashMap::new(),
+            on_task_toggle,
+            code_theme: options.code_theme,
+            highlighted_code: None,
+            cache,
+            code_block_renderers: options.code_block_renderers,
+            rendered_code: None,
+            metadata: None,
             table_state: TableState::Head,
             table_alignments: vec![],
             table_cell_index: 0,
-            numbers: HashMap::new(),
+            footnote_numbers,
+            footnote_references,
+            footnote_references_seen: HashMap::new(),
+            footnotes: Vec::new(),
             in_non_writing_block: false,
+            block_key: None,
+            block_occurrences: HashMap::new(),
+        }
+    }
+
+    /// Starts the top-level block parsed from `range`, keyed by its source text so the key
+    /// stays the same as long as the block does.
+    fn start_block(&mut self, range: Range<usize>) {
+        let hash = cache_key(&self.source[range]);
+        // Identical blocks are told apart by how many came before them.
+        let occurrence = self.block_occurrences.entry(hash).or_default();
+        *occurrence += 1;
+        let key = format!("{hash:x}-{occurrence}");
+        self.source_map.start_block(key.clone());
+        self.block_key = Some(key);
+    }
+
+    /// The key of the top-level block being rendered.
+    fn block_key(&self) -> String {
+        self.block_key.clone().unwrap_or_default()
+    }
+
+    /// Renders a run of text that maps back to `src`, recording it in the source map.
+    fn text_run(&mut self, text: &str, src: Range<usize>) -> Element {
+        let run = self.source_map.push(text, src.clone(), self.source);
+        if self.mode == RenderMode::Reading {
+            return rsx! { {text} };
+        }
+        rsx! {
+            span {
+                class: "src-text",
+                "data-block": self.block_key(),
+                "data-run": run,
+                "data-src-start": src.start,
+                "data-src-end": src.end,
+                {text}
+            }
+        }
+    }
+
+    /// Renders a syntax marker (`# `, `**`, ...) as it appears in the source at `src`.
+    /// Markers are only shown while editing.
+    fn marker(&mut self, src: Range<usize>) -> Element {
+        if self.mode == RenderMode::Reading {
+            return rsx!();
+        }
+        let text = self.source[src.clone()].to_string();
+        let run = self.source_map.push(&text, src.clone(), self.source);
+        rsx! {
+            span {
+                class: "src-text src-marker",
+                "data-block": self.block_key(),
+                "data-run": run,
+                "data-src-start": src.start,
+                "data-src-end": src.end,
+                {text}
+            }
+        }
+    }
+
+    /// A unique `id` for a heading with the given text.
+    fn heading_id(&mut self, text: &str) -> String {
+        let slug = slugify(text);
+        let count = self.heading_ids.entry(slug.clone()).or_default();
+        *count += 1;
+        match *count {
+            1 => slug,
+            n => format!("{slug}-{}", n - 1),
+        }
+    }
+
+    /// Starts highlighting the fenced code block in `language` at `src`, with the tokens of
+    /// the last render if the block hasn't changed since.
+    fn start_highlighting(&mut self, language: &str, src: Range<usize>) {
+        let key = cache_key((language, self.code_theme.name(), &self.source[src]));
+        self.highlighted_code = match self.cache.highlighted(key) {
+            Some(tokens) => Some(HighlightedCode { key, tokens, next: 0, highlighter: None }),
+            None => CodeHighlighter::new(language, self.code_theme).map(|highlighter| HighlightedCode {
+                key,
+                tokens: Vec::new(),
+                next: 0,
+                highlighter: Some(highlighter),
+            }),
+        };
+    }
+
+    /// Highlighted tokens of `text` (at `src`) inside a fenced code block with a known language.
+    fn highlight(&mut self, text: &str, src: &Range<usize>) -> Option<Tokens> {
+        // Tokens are mapped back by offset, so the text has to be a plain slice of the source.
+        if self.source.get(src.clone()) != Some(text) {
+            return None;
+        }
+        let code = self.highlighted_code.as_mut()?;
+        let tokens = match code.highlighter.as_mut() {
+            Some(highlighter) => {
+                let tokens = highlighter.highlight(text);
+                code.tokens.push(tokens.clone());
+                tokens
+            }
+            None => code.tokens.get(code.next)?.clone(),
+        };
+        code.next += 1;
+        Some(tokens)
+    }
+
+    /// Ends the fenced code block being highlighted, caching its tokens for the next render.
+    fn end_highlighting(&mut self) {
+        if let Some(HighlightedCode { key, tokens, highlighter: Some(_), .. }) = self.highlighted_code.take() {
+            self.cache.insert_highlighted(key, tokens);
+        }
+    }
+
+    /// The number of the footnote labelled `name`.
+    fn footnote_number(&mut self, name: &str) -> usize {
+        let next = self.footnote_numbers.len() + 1;
+        *self.footnote_numbers.entry(name.to_lowercase()).or_insert(next)
+    }
+
+    /// The footnotes section closing a document being read: every definition in order,
+    /// each linking back to its references.
+    fn footnotes_section(&mut self) -> Option<Element> {
+        let mut footnotes = std::mem::take(&mut self.footnotes);
+        if footnotes.is_empty() {
+            return None;
+        }
+        footnotes.sort_by_key(|(number, _)| *number);
+        // Only the first definition of a label counts.
+        footnotes.dedup_by_key(|(number, _)| *number);
+        Some(rsx! {
+            section {
+                class: "footnotes",
+                ol {
+                    for (number, definition) in footnotes {
+                        li { key: "{number}", id: "fn-{number}", value: number, {definition} }
+                    }
+                }
+            }
+        })
+    }
+
+    /// The renderer registered for code blocks in `language`, if any.
+    fn code_block_renderer(&self, language: &str) -> Option<CodeBlockRenderer> {
+        self.code_block_renderers
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(language))
+            .map(|(_, render)| *render)
+    }
+
+    /// Math at `range` with TeX `tex`: its source (delimiters and TeX, which the editor shows
+    /// while the caret is inside) and its typeset MathML.
+    fn math(&mut self, tex: &str, range: &Range<usize>, display: bool) -> (Element, String) {
+        let mathml = to_mathml(tex, display);
+        if self.mode == RenderMode::Reading {
+            return (rsx!(), mathml);
+        }
+        let src = self.inner_range(range, tex);
+        let open = self.marker(range.start..src.start);
+        let text = self.text_run(tex, src.clone());
+        let close = self.marker(src.end..range.end);
+        (rsx! { {open} {text} {close} }, mathml)
+    }
+
+    /// The source range of `inner` inside `range`, e.g. a code span without its backticks.
+    fn inner_range(&self, range: &Range<usize>, inner: &str) -> Range<usize> {
+        match self.source[range.clone()].find(inner) {
+            Some(i) => range.start + i..range.start + i + inner.len(),
+            None => range.clone(),
         }
     }
 }
 
 
 /// Pull events from parser until the end of end tag.
-/// Pull events from parser until the end of end tag.
+///
+/// Every element carries `data-src-start`/`data-src-end` with the source range it was
+/// parsed from, and every text run is also recorded in `cx.source_map`.
 fn pull_elements<'a, 'b: 'a>(
-    iter: &'a mut Parser<'b>,
+    iter: &'a mut OffsetIter<'b>,
     cx: &'a mut Context<'b>,
 ) -> impl Iterator<Item = Element> + use<'a, 'b> {
     std::iter::from_fn(move || {
-        Some(match iter.next()? {
+        let (event, range) = iter.next()?;
+        let (start, end) = (range.start, range.end);
+        if cx.block_key.is_none() {
+            cx.start_block(range.clone());
+        }
+        Some(match event {
             Event::Start(tag) => match tag {
                 Tag::Paragraph => {
-                    rsx! { p { {pull_elements(iter, cx)} } }
+                    rsx! { p { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::Heading {
                     level,
                     id,
                     classes,
-                    attrs,
+                    attrs: _,
                 } => {
-                    let id = id.map(|id| escape_html(id).to_string());
-                    let classes = join_strings(classes.iter().map(|class| escape_html(class)), ' ');
-
-                    let heading_prefix = match level {
-                        HeadingLevel::H1 => "# ",
-                        HeadingLevel::H2 => "## ",
-                        HeadingLevel::H3 => "### ",
-                        HeadingLevel::H4 => "#### ",
-                        HeadingLevel::H5 => "##### ",
-                        HeadingLevel::H6 => "###### ",
+                    let classes = join_strings(classes.iter(), ' ');
+
+                    // ATX headings show their `#`s before the text, setext headings their underline after it.
+                    let source = &cx.source[range.clone()];
+                    let open = if source.starts_with('#') {
+                        let hashes = source.trim_start_matches('#');
+                        let marker_len = range.len() - hashes.trim_start_matches([' ', '\t']).len();
+                        cx.marker(start..start + marker_len)
+                    } else {
+                        rsx!()
+                    };
+                    // Headings without an explicit `{#id}` get one from their text, for anchors and exports.
+                    let first_run = cx.source_map.len();
+                    let children = pull_elements(iter, cx).collect::<Vec<_>>();
+                    let id = match id {
+                        Some(id) => id.to_string(),
+                        None => {
+                            let text = cx.source_map.text_from(first_run);
+                            cx.heading_id(&text)
+                        }
+                    };
+                    let close = if source.starts_with('#') {
+                        rsx!()
+                    } else {
+                        // The underline, with the line break before it.
+                        let source = source.trim_end_matches(['\r', '\n']);
+                        let underline = source.rfind('\n').map_or(start, |i| start + i);
+                        cx.marker(underline..start + source.len())
                     };
 
                     match level {
                         HeadingLevel::H1 => rsx! { h1 {
                             id: id,
                             class: classes,
-                            {heading_prefix}
-                            {pull_elements(iter, cx)}
+                            "data-src-start": start,
+                            "data-src-end": end,
+                            {open}
+                            {children.into_iter()}
+                            {close}
                         } },
                         HeadingLevel::H2 => rsx! { h2 {
                             id: id,
                             class: classes,
-                            {heading_prefix}
-                            {pull_elements(iter, cx)}
+                            "data-src-start": start,
+                            "data-src-end": end,
+                            {open}
+                            {children.into_iter()}
+                            {close}
                         } },
                         HeadingLevel::H3 => rsx! { h3 {
                             id: id,
                             class: classes,
-                            {heading_prefix}
-                            {pull_elements(iter, cx)}
+                            "data-src-start": start,
+                            "data-src-end": end,
+                            {open}
+                            {children.into_iter()}
+                            {close}
                         } },
                         HeadingLevel::H4 => rsx! { h4 {
                             id: id,
                             class: classes,
-                            {heading_prefix}
-                            {pull_elements(iter, cx)}
+                            "data-src-start": start,
+                            "data-src-end": end,
+                            {open}
+                            {children.into_iter()}
+                            {close}
                         } },
                         HeadingLevel::H5 => rsx! { h5 {
                             id: id,
                             class: classes,
-                            {heading_prefix}
-                            {pull_elements(iter, cx)}
+                            "data-src-start": start,
+                            "data-src-end": end,
+                            {open}
+                            {children.into_iter()}
+                            {close}
                         } },
                         HeadingLevel::H6 => rsx! { h6 {
                             id: id,
                             class: classes,
-                            {heading_prefix}
-                            {pull_elements(iter, cx)}
+                            "data-src-start": start,
+                            "data-src-end": end,
+                            {open}
+                            {children.into_iter()}
+                            {close}
                         } },
                     }
                 }
                 Tag::BlockQuote(None) => {
-                    rsx! { blockquote { {pull_elements(iter, cx)} } }
+                    rsx! { blockquote { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::BlockQuote(Some(kind)) => {
                     let class_str = match kind {
-                        BlockQuoteKind::Note => " class=\"markdown-alert-note\"",
-                        BlockQuoteKind::Tip => " class=\"markdown-alert-tip\"",
-                        BlockQuoteKind::Important => " class=\"markdown-alert-important\"",
-                        BlockQuoteKind::Warning => " class=\"markdown-alert-warning\"",
-                        BlockQuoteKind::Caution => " class=\"markdown-alert-caution\"",
+                        BlockQuoteKind::Note => "markdown-alert-note",
+                        BlockQuoteKind::Tip => "markdown-alert-tip",
+                        BlockQuoteKind::Important => "markdown-alert-important",
+                        BlockQuoteKind::Warning => "markdown-alert-warning",
+                        BlockQuoteKind::Caution => "markdown-alert-caution",
                     };
-                    rsx! { blockquote { class: class_str, {pull_elements(iter, cx)} } }
+                    rsx! { blockquote { class: class_str, "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
-                Tag::CodeBlock(kind) => match kind {
-                    CodeBlockKind::Indented => {
-                        rsx! { pre { code { {pull_elements(iter, cx)} } } }
-                    }
-                    CodeBlockKind::Fenced(info) => {
-                        let lang = info.split(' ').next().unwrap();
-                        if lang.is_empty() {
-                            rsx! { pre { code { {pull_elements(iter, cx)} } } }
-                        } else {
-                            let class_str = format!(" class=\"language-{}\"", escape_html(lang));
-                            rsx! { pre { code { class: class_str, {pull_elements(iter, cx)} } } }
+                Tag::CodeBlock(kind) => {
+                    let style_str = cx.code_theme.block_style();
+                    match kind {
+                        CodeBlockKind::Indented => {
+                            rsx! { pre { style: style_str, "data-src-start": start, "data-src-end": end, code { {pull_elements(iter, cx)} } } }
+                        }
+                        CodeBlockKind::Fenced(info) => {
+                            let lang = info.split(' ').next().unwrap();
+                            if lang.is_empty() {
+                                rsx! { pre { style: style_str, "data-src-start": start, "data-src-end": end, code { {pull_elements(iter, cx)} } } }
+                            } else if let Some(render) = cx.code_block_renderer(lang) {
+                                cx.start_highlighting(lang, start..end);
+                                cx.rendered_code = Some(String::new());
+                                let class_str = format!("language-{lang}");
+                                let children = pull_elements(iter, cx).collect::<Vec<_>>();
+                                let code = rsx! { pre { style: style_str, code { class: class_str, {children.into_iter()} } } };
+                                let source = cx.rendered_code.take().unwrap_or_default();
+                                let rendered = cx.cache.rendered(cache_key((lang, &source)), || render(&source));
+                                rendered_code_block(lang, rendered, code, cx.mode, start..end)
+                            } else {
+                                // Unknown languages fall back to the theme's plain colors.
+                                cx.start_highlighting(lang, start..end);
+                                let class_str = format!("language-{lang}");
+                                rsx! { pre { style: style_str, "data-src-start": start, "data-src-end": end, code { class: class_str, {pull_elements(iter, cx)} } } }
+                            }
                         }
                     }
-                },
+                }
                 Tag::HtmlBlock => {
                     rsx! { {pull_elements(iter, cx)} }
                 }
                 Tag::List(None) => {
-                    rsx! { ul { {pull_elements(iter, cx)} } }
+                    rsx! { ul { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::List(Some(1)) => {
-                    rsx! { ol { {pull_elements(iter, cx)} } }
+                    rsx! { ol { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
-                Tag::List(Some(start)) => {
-                    rsx! { ol { start: start, {pull_elements(iter, cx)} } }
+                Tag::List(Some(first_number)) => {
+                    rsx! { ol { start: first_number, "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::Item => {
-                    rsx! { li { {pull_elements(iter, cx)} } }
+                    rsx! { li { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::FootnoteDefinition(name) => {
-                    let id = escape_html(&name).to_string();
-                    let len = cx.numbers.len() + 1;
-                    let number = *cx.numbers.entry(name).or_insert(len);
-                    rsx! {
-                        div {
-                            id: id,
-                            class: "footnote-definition",
-                            sup {
-                                class: "footnote-definition-label",
-                                {number.to_string()}
+                    let number = cx.footnote_number(&name);
+                    let children = pull_elements(iter, cx).collect::<Vec<_>>();
+                    if cx.mode == RenderMode::Reading {
+                        // Moved to the footnotes section, with a ↩ back to each reference.
+                        let references = cx.footnote_references.get(&number).copied().unwrap_or_default();
+                        let back_links = (1..=references).map(|nth| {
+                            let (href, label) = match nth {
+                                1 => (format!("#fnref-{number}"), "↩".to_string()),
+                                nth => (format!("#fnref-{number}-{nth}"), format!("↩{nth}")),
+                            };
+                            rsx! { a { class: "footnote-backref", href: href, title: "Back to reference {nth}", {label} } }
+                        });
+                        let definition = rsx! {
+                            div {
+                                class: "footnote-definition",
+                                "data-src-start": start,
+                                "data-src-end": end,
+                                {children.into_iter()}
+                                {back_links}
+                            }
+                        };
+                        cx.footnotes.push((number, definition));
+                        rsx!()
+                    } else {
+                        // While editing, definitions stay where they're written.
+                        rsx! {
+                            div {
+                                id: "fn-{number}",
+                                class: "footnote-definition",
+                                "data-src-start": start,
+                                "data-src-end": end,
+                                sup {
+                                    class: "footnote-definition-label",
+                                    {number.to_string()}
+                                }
+                                {children.into_iter()}
                             }
-                            {pull_elements(iter, cx)}
                         }
                     }
                 }
                 Tag::DefinitionList => {
-                    rsx! { dl { {pull_elements(iter, cx)} } }
+                    rsx! { dl { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::DefinitionListTitle => {
-                    rsx! { dt { {pull_elements(iter, cx)} } }
+                    rsx! { dt { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::DefinitionListDefinition => {
-                    rsx! { dd { {pull_elements(iter, cx)} } }
+                    rsx! { dd { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::Table(alignments) => {
                     cx.table_alignments = alignments;
-                    rsx! { table { {pull_elements(iter, cx)} } }
+                    rsx! { table { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::TableHead => {
                     cx.table_state = TableState::Head;
                     cx.table_cell_index = 0;
-                    rsx! { thead { tr { {pull_elements(iter, cx)} } } }
+                    rsx! { thead { "data-src-start": start, "data-src-end": end, tr { {pull_elements(iter, cx)} } } }
                 }
                 Tag::TableRow => {
                     cx.table_cell_index = 0;
-                    rsx! { tr { {pull_elements(iter, cx)} } }
+                    rsx! { tr { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                 }
                 Tag::TableCell => {
                     let style_str = match cx.table_alignments.get(cx.table_cell_index) {
@@ -288,16 +618,33 @@ fn pull_elements<'a, 'b: 'a>(
 
                     match cx.table_state {
                         TableState::Head => {
-                            rsx! { th { style: style_str, {pull_elements(iter, cx)} } }
+                            rsx! { th { style: style_str, "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                         }
                         TableState::Body => {
-                            rsx! { td { style: style_str, {pull_elements(iter, cx)} } }
+                            rsx! { td { style: style_str, "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } }
                         }
                     }
                 }
-                Tag::Emphasis => rsx! { em { "*", {pull_elements(iter, cx)}, "*" } },
-                Tag::Strong => rsx! { strong { "**", {pull_elements(iter, cx)}, "**" } },
-                Tag::Strikethrough => rsx! { del { "~~", {pull_elements(iter, cx)}, "~~" } },
+                Tag::Emphasis => {
+                    let open = cx.marker(start..start + 1);
+                    let children = pull_elements(iter, cx).collect::<Vec<_>>();
+                    let close = cx.marker(end - 1..end);
+                    rsx! { em { "data-src-start": start, "data-src-end": end, {open}, {children.into_iter()}, {close} } }
+                }
+                Tag::Strong => {
+                    let open = cx.marker(start..start + 2);
+                    let children = pull_elements(iter, cx).collect::<Vec<_>>();
+                    let close = cx.marker(end - 2..end);
+                    rsx! { strong { "data-src-start": start, "data-src-end": end, {open}, {children.into_iter()}, {close} } }
+                }
+                Tag::Strikethrough => {
+                    // GFM allows both `~text~` and `~~text~~`.
+                    let tildes = cx.source[range.clone()].len() - cx.source[range.clone()].trim_start_matches('~').len();
+                    let open = cx.marker(start..start + tildes);
+                    let children = pull_elements(iter, cx).collect::<Vec<_>>();
+                    let close = cx.marker(end - tildes..end);
+                    rsx! { del { "data-src-start": start, "data-src-end": end, {open}, {children.into_iter()}, {close} } }
+                }
 
                 Tag::Link {
                     link_type: LinkType::Email,
@@ -305,12 +652,14 @@ fn pull_elements<'a, 'b: 'a>(
                     title,
                     id: _,
                 } => {
-                    let link = format!("mailto:{}", escape_href(dest_url));
+                    let link = format!("mailto:{dest_url}");
 
                     rsx! {
-                        Link {
-                            to: link,
-                            title: if title.is_empty() { &*title },
+                        a {
+                            href: link,
+                            title: if !title.is_empty() { &*title },
+                            "data-src-start": start,
+                            "data-src-end": end,
                             {pull_elements(iter, cx)}
                         }
                     }
@@ -322,36 +671,57 @@ fn pull_elements<'a, 'b: 'a>(
                     title,
                     id: _,
                 } => {
-                    let link = escape_href(dest_url).to_string();
+                    let link = dest_url.to_string();
 
                     rsx! {
-                        Link {
-                            to: link,
-                            title: if title.is_empty() { &*title },
+                        a {
+                            href: link,
+                            title: if !title.is_empty() { &*title },
+                            "data-src-start": start,
+                            "data-src-end": end,
                             {pull_elements(iter, cx)}
                         }
                     }
                 }
 
-                Tag::Subscript => rsx! { sub { {pull_elements(iter, cx)} } },
-                Tag::Superscript => rsx! { sup { {pull_elements(iter, cx)} } },
+                Tag::Subscript => rsx! { sub { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } },
+                Tag::Superscript => rsx! { sup { "data-src-start": start, "data-src-end": end, {pull_elements(iter, cx)} } },
                 Tag::Image {
-                    link_type,
+                    link_type: _,
                     dest_url,
                     title,
-                    id,
+                    id: _,
                 } => {
+                    let first_run = cx.source_map.len();
+                    let children = pull_elements(iter, cx).collect::<Vec<_>>();
+                    let alt = cx.source_map.text_from(first_run);
+
                     rsx! {
                         img {
-                            src: escape_href(dest_url).to_string(),
+                            src: dest_url.to_string(),
+                            alt: alt,
                             title: if !title.is_empty() { &*title },
-                            {pull_elements(iter, cx)}
+                            "data-src-start": start,
+                            "data-src-end": end,
+                            {children.into_iter()}
                         }
                     }
                 }
-                Tag::MetadataBlock(_) => {
-                    cx.in_non_writing_block = true;
-                    rsx! { {pull_elements(iter, cx)} }
+                Tag::MetadataBlock(kind) => {
+                    let metadata = Metadata::parse(kind, cx.source, range.clone());
+                    let fields = metadata.range.clone();
+                    cx.metadata = Some(metadata);
+                    if cx.mode == RenderMode::Reading {
+                        // Shown in the properties panel, and used by exports, rather than rendered.
+                        cx.in_non_writing_block = true;
+                        rsx! { {pull_elements(iter, cx)} }
+                    } else {
+                        // The source stays editable, collapsed unless the caret is inside.
+                        let open = cx.marker(start..fields.start);
+                        let children = pull_elements(iter, cx).collect::<Vec<_>>();
+                        let close = cx.marker(fields.end..end);
+                        rsx! { div { class: "front-matter", "data-src-start": start, "data-src-end": end, {open}, {children.into_iter()}, {close} } }
+                    }
                 }
             },
             Event::End(tag) => {
@@ -365,97 +735,493 @@ fn pull_elements<'a, 'b: 'a>(
                     TagEnd::MetadataBlock(_) => {
                         cx.in_non_writing_block = false;
                     }
+                    TagEnd::CodeBlock => {
+                        cx.end_highlighting();
+                    }
                     _ => {}
                 }
                 return None;
             }
-            // Modify the Text event handler
+            // Text is rendered as-is (Dioxus escapes it); newlines show through `white-space: pre-wrap`.
             Event::Text(text) => {
-                if !cx.in_non_writing_block {
-                    let escaped = escape_html_body(text).to_string();
-                    let processed = escaped.replace("\n", "<br />\n"); // **Convert all newlines to `<br />`**
-                    rsx! { {processed} }
-                } else {
+                if let Some(code) = cx.rendered_code.as_mut() {
+                    code.push_str(&text);
+                }
+                if cx.in_non_writing_block {
                     rsx!()
+                } else if let Some(tokens) = cx.highlight(&text, &range) {
+                    let runs = tokens
+                        .into_iter()
+                        .map(|(token, style_str)| {
+                            let run = cx.text_run(&text[token.clone()], start + token.start..start + token.end);
+                            match style_str {
+                                Some(style_str) => rsx! { span { style: style_str, {run} } },
+                                None => run,
+                            }
+                        })
+                        .collect::<Vec<_>>();
+                    rsx! { {runs.into_iter()} }
+                } else {
+                    cx.text_run(&text, range)
                 }
             }
             Event::Code(code) => {
-                rsx! { code { {escape_html_body(code).to_string()} } }
+                let src = cx.inner_range(&range, &code);
+                rsx! { code { "data-src-start": start, "data-src-end": end, {cx.text_run(&code, src)} } }
             }
             Event::InlineMath(math) => {
-                rsx! { span { class: "math inline-math", {escape_html(math).to_string()} } }
+                let (source, mathml) = cx.math(&math, &range, false);
+                rsx! {
+                    span {
+                        class: "math inline-math",
+                        "data-src-start": start,
+                        "data-src-end": end,
+                        {source}
+                        span { class: "math-rendered", contenteditable: "false", dangerous_inner_html: mathml }
+                    }
+                }
             }
             Event::DisplayMath(math) => {
-                rsx! { div { class: "math display-math", {escape_html(math).to_string()} } }
+                let (source, mathml) = cx.math(&math, &range, true);
+                rsx! {
+                    div {
+                        class: "math display-math",
+                        "data-src-start": start,
+                        "data-src-end": end,
+                        {source}
+                        div { class: "math-rendered", contenteditable: "false", dangerous_inner_html: mathml }
+                    }
+                }
             }
             Event::Html(html) => {
-                rsx! { {html} }
+                cx.text_run(&html, range)
             }
             Event::InlineHtml(html) => {
-                rsx! { {html} }
+                cx.text_run(&html, range)
             }
             Event::FootnoteReference(name) => {
-                let len = cx.numbers.len() + 1;
+                let number = cx.footnote_number(&name);
+                let seen = cx.footnote_references_seen.entry(number).or_default();
+                *seen += 1;
+                // Definitions link back to each reference by these ids.
+                let id = match *seen {
+                    1 => format!("fnref-{number}"),
+                    nth => format!("fnref-{number}-{nth}"),
+                };
                 rsx! {
                     sup {
                         class: "footnote-reference",
-                        Link {
-                            to: format!("#{}", escape_href(name)),
-                            {len.to_string()}
+                        id: id,
+                        "data-src-start": start,
+                        "data-src-end": end,
+                        a {
+                            href: "#fn-{number}",
+                            {number.to_string()}
                         }
                     }
                 }
             }
+            // While reading, soft breaks wrap like any other whitespace.
+            Event::SoftBreak if cx.mode == RenderMode::Reading => rsx! { "\n" },
             Event::SoftBreak => {
-                rsx! { br {} }
+                rsx! { br { "data-src-start": start, "data-src-end": end } }
             }
             Event::HardBreak => {
-                rsx! { br {} }
+                rsx! { br { "data-src-start": start, "data-src-end": end } }
             }
             Event::Rule => {
-                rsx! { hr {} }
-            }
-            Event::TaskListMarker(true) => {
-                rsx! { input { r#type: "checkbox", checked: true, disabled: "" } }
-            }
-            Event::TaskListMarker(false) => {
-                rsx! { input { r#type: "checkbox", disabled: "" } }
+                rsx! { hr { "data-src-start": start, "data-src-end": end } }
             }
+            // Checkboxes toggle their task in the source when the renderer has a handler for it.
+            Event::TaskListMarker(checked) => match cx.on_task_toggle {
+                Some(on_task_toggle) => rsx! {
+                    input {
+                        r#type: "checkbox",
+                        checked: checked,
+                        contenteditable: "false",
+                        "data-src-start": start,
+                        "data-src-end": end,
+                        onclick: move |evt| {
+                            evt.prevent_default();
+                            evt.stop_propagation();
+                            on_task_toggle.call(start);
+                        },
+                    }
+                },
+                None => rsx! {
+                    input { r#type: "checkbox", checked: checked, disabled: true, "data-src-start": start, "data-src-end": end }
+                },
+            },
         })
     })
 }
 
+/// Numbers footnotes in the order they're first referenced, followed by those that never
+/// are in the order they're defined, and counts the references to each.
+fn number_footnotes(source: &str, options: &RendererOptions) -> (HashMap<String, usize>, HashMap<usize, usize>) {
+    let mut numbers = HashMap::new();
+    let mut references = HashMap::new();
+    if !options.footnotes {
+        return (numbers, references);
+    }
+    let mut unreferenced = Vec::new();
+    for event in Parser::new_ext(source, options.to_parser_options()) {
+        match event {
+            Event::FootnoteReference(name) => {
+                let next = numbers.len() + 1;
+                let number = *numbers.entry(name.to_lowercase()).or_insert(next);
+                *references.entry(number).or_default() += 1;
+            }
+            Event::Start(Tag::FootnoteDefinition(name)) => unreferenced.push(name.to_lowercase()),
+            _ => {}
+        }
+    }
+    for name in unreferenced {
+        let next = numbers.len() + 1;
+        numbers.entry(name).or_insert(next);
+    }
+    (numbers, references)
+}
+
+/// A code block replaced by what its renderer made of it. While editing, the source is shown
+/// above the result when the caret is inside; for reading, it's folded away under it.
+/// If rendering failed the error is shown with the source.
+fn rendered_code_block(
+    lang: &str,
+    rendered: Result<String, String>,
+    code: Element,
+    mode: RenderMode,
+    src: Range<usize>,
+) -> Element {
+    let (start, end) = (src.start, src.end);
+    match (rendered, mode) {
+        (Err(err), _) => rsx! {
+            div {
+                class: "rendered-code rendered-code-failed",
+                "data-src-start": start,
+                "data-src-end": end,
+                {code}
+                div { class: "rendered-code-error", contenteditable: "false", "Couldn't render this {lang} block: {err}" }
+            }
+        },
+        (Ok(html), RenderMode::Editing) => rsx! {
+            div {
+                class: "rendered-code",
+                "data-src-start": start,
+                "data-src-end": end,
+                {code}
+                div { class: "rendered-code-output", contenteditable: "false", dangerous_inner_html: html }
+            }
+        },
+        (Ok(html), RenderMode::Reading) => rsx! {
+            figure {
+                class: "rendered-code",
+                "data-src-start": start,
+                "data-src-end": end,
+                div { class: "rendered-code-output", dangerous_inner_html: html }
+                details { summary { "Source" } {code} }
+            }
+        },
+    }
+}
+
+/// Whether the output is the editable surface or a clean rendering for reading and export.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RenderMode {
+    /// Syntax markers (`# `, `**`, ...) are emitted as `span.src-marker`, which the editor
+    /// only reveals inside the element holding the caret, and every text run is a `span`
+    /// carrying its source range so the caret can be mapped.
+    #[default]
+    Editing,
+    /// Markers are hidden and text is emitted as-is.
+    Reading,
+}
+
+/// Renders the source of a fenced code block to HTML (such as an SVG drawing), or says
+/// why it couldn't.
+pub type CodeBlockRenderer = fn(&str) -> Result<String, String>;
+
+/// Which Markdown extensions the renderer parses, on top of CommonMark, and how it colors code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RendererOptions {
+    pub tables: bool,
+    pub footnotes: bool,
+    pub strikethrough: bool,
+    pub task_lists: bool,
+    /// `> [!NOTE]`-style alerts.
+    pub gfm_alerts: bool,
+    /// `{#id .class}` after a heading.
+    pub heading_attributes: bool,
+    /// `$inline$` and `$$display$$` math.
+    pub math: bool,
+    pub definition_lists: bool,
+    pub superscript: bool,
+    pub subscript: bool,
+    /// YAML (`---`) and TOML (`+++`) front matter.
+    pub metadata_blocks: bool,
+    /// Curly quotes and dashes. This changes the rendered text, so carets inside
+    /// affected runs only land on run boundaries.
+    pub smart_punctuation: bool,
+    /// Syntax highlighting theme for code blocks.
+    pub code_theme: CodeTheme,
+    /// Languages whose fenced code blocks are rendered, e.g. diagrams drawn as SVG.
+    pub code_block_renderers: &'static [(&'static str, CodeBlockRenderer)],
+}
+
+impl RendererOptions {
+    /// Plain CommonMark, no extensions.
+    pub fn commonmark() -> Self {
+        Self {
+            tables: false,
+            footnotes: false,
+            strikethrough: false,
+            task_lists: false,
+            gfm_alerts: false,
+            heading_attributes: false,
+            math: false,
+            definition_lists: false,
+            superscript: false,
+            subscript: false,
+            metadata_blocks: false,
+            smart_punctuation: false,
+            code_theme: CodeTheme::default(),
+            code_block_renderers: &[],
+        }
+    }
+
+    /// GitHub Flavored Markdown: tables, footnotes, strikethrough, task lists, alerts, math,
+    /// diagrams and front matter.
+    pub fn gfm() -> Self {
+        Self {
+            tables: true,
+            footnotes: true,
+            strikethrough: true,
+            task_lists: true,
+            gfm_alerts: true,
+            math: true,
+            metadata_blocks: true,
+            code_block_renderers: DIAGRAMS,
+            ..Self::commonmark()
+        }
+    }
+
+    /// Every extension the renderer supports, except smart punctuation.
+    pub fn everything() -> Self {
+        Self {
+            heading_attributes: true,
+            definition_lists: true,
+            superscript: true,
+            subscript: true,
+            ..Self::gfm()
+        }
+    }
+
+    pub fn to_parser_options(self) -> Options {
+        let mut options = Options::empty();
+        options.set(Options::ENABLE_TABLES, self.tables);
+        options.set(Options::ENABLE_FOOTNOTES, self.footnotes);
+        options.set(Options::ENABLE_STRIKETHROUGH, self.strikethrough);
+        options.set(Options::ENABLE_TASKLISTS, self.task_lists);
+        options.set(Options::ENABLE_GFM, self.gfm_alerts);
+        options.set(Options::ENABLE_HEADING_ATTRIBUTES, self.heading_attributes);
+        options.set(Options::ENABLE_MATH, self.math);
+        options.set(Options::ENABLE_DEFINITION_LIST, self.definition_lists);
+        options.set(Options::ENABLE_SUPERSCRIPT, self.superscript);
+        options.set(Options::ENABLE_SUBSCRIPT, self.subscript);
+        options.set(Options::ENABLE_YAML_STYLE_METADATA_BLOCKS, self.metadata_blocks);
+        options.set(Options::ENABLE_PLUSES_DELIMITED_METADATA_BLOCKS, self.metadata_blocks);
+        options.set(Options::ENABLE_SMART_PUNCTUATION, self.smart_punctuation);
+        options
+    }
+}
+
+impl Default for RendererOptions {
+    fn default() -> Self {
+        Self::gfm()
+    }
+}
 
 #[derive(Props, Debug, PartialEq, Clone)]
 pub struct MarkdownRenderer {
     pub content: String,
-    pub nodes: Vec<Element>
+    /// Extensions to parse `content` with.
+    pub options: RendererOptions,
+    pub mode: RenderMode,
+    /// Makes task checkboxes clickable, reporting the source offset of the clicked task's box.
+    pub on_task_toggle: Option<Callback<usize>>,
+    pub nodes: Vec<Element>,
+    /// A stable key per top-level node, derived from its source text, so that an edit
+    /// only re-renders the blocks it touched.
+    pub block_keys: Vec<String>,
+    /// Where each rendered text run came from in `content`.
+    pub source_map: SourceMap,
+    /// The YAML or TOML front matter at the top of `content`, if it has any.
+    pub metadata: Option<Metadata>,
 }
 
 impl MarkdownRenderer {
+    pub fn with_mode(content: String, options: RendererOptions, mode: RenderMode) -> Self {
+        Self::build(content, options, mode, None, &RenderCache::default())
+    }
 
-    pub fn new(content: String) -> Self {
+    /// Renders for the editor, with task checkboxes reporting clicks to `on_task_toggle`.
+    /// Code blocks that haven't changed since the last render with `cache` aren't
+    /// highlighted or rendered again.
+    pub fn editing(content: String, options: RendererOptions, on_task_toggle: Callback<usize>, cache: &RenderCache) -> Self {
+        Self::build(content, options, RenderMode::Editing, Some(on_task_toggle), cache)
+    }
+
+    fn build(
+        content: String,
+        options: RendererOptions,
+        mode: RenderMode,
+        on_task_toggle: Option<Callback<usize>>,
+        cache: &RenderCache,
+    ) -> Self {
         let mut renderer = Self {
-            content: content,
-            nodes: Vec::<Element>::new()
+            content,
+            options,
+            mode,
+            on_task_toggle,
+            nodes: Vec::<Element>::new(),
+            block_keys: Vec::new(),
+            source_map: SourceMap::new(),
+            metadata: None,
         };
 
-        renderer.render_to_elements();
+        renderer.render_to_elements(cache);
         renderer
     }
 
-    pub fn update_content(&mut self, content: String) -> () {
-        self.content = content;
-    }
-
     /// Renders the parsed elements into Dioxus-compatible elements.
-    pub fn render_to_elements(&mut self) -> () {
+    pub fn render_to_elements(&mut self, cache: &RenderCache) {
         let content_read = self.content.clone();
 
-        let mut parser = Parser::new(&content_read);
-        let mut cx = Context::new();
-        let elements = pull_elements(&mut parser, &mut cx);
+        cache.start();
+        let mut parser = Parser::new_ext(&content_read, self.options.to_parser_options()).into_offset_iter();
+        let mut cx = Context::new(&content_read, self.mode, &self.options, self.on_task_toggle, cache);
+
+        let mut nodes = Vec::new();
+        let mut block_keys = Vec::new();
+        // Pull one top-level block at a time so its key can be read back from `cx`.
+        loop {
+            let Some(node) = pull_elements(&mut parser, &mut cx).next() else {
+                break;
+            };
+            block_keys.push(cx.block_key.take().unwrap_or_default());
+            nodes.push(node);
+        }
+        if let Some(footnotes) = cx.footnotes_section() {
+            nodes.push(footnotes);
+            block_keys.push("footnotes".to_string());
+        }
+
+        self.nodes = nodes;
+        self.block_keys = block_keys;
+        self.source_map = cx.source_map;
+        self.metadata = cx.metadata;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::export::html_fragment;
+
+    const FOOTNOTES: &str = "Second[^b] first[^A] again[^b].\n\n[^a]: Alpha.\n[^b]: Beta.\n[^c]: Never used.\n";
+
+    #[test]
+    fn numbers_footnotes_by_first_reference() {
+        let (numbers, references) = number_footnotes(FOOTNOTES, &RendererOptions::gfm());
+        // `[^A]` refers to `[^a]`, and `[^c]` comes after every referenced footnote.
+        assert_eq!(numbers, HashMap::from([("b".to_string(), 1), ("a".to_string(), 2), ("c".to_string(), 3)]));
+        assert_eq!(references, HashMap::from([(1, 2), (2, 1)]));
+    }
+
+    #[test]
+    fn links_footnotes_and_their_references_both_ways() {
+        let html = html_fragment(FOOTNOTES, RendererOptions::gfm());
+        assert!(html.contains(r##"Second<sup class="footnote-reference" id="fnref-1"><a href="#fn-1">1</a></sup>"##));
+        assert!(html.contains(r##"first<sup class="footnote-reference" id="fnref-2"><a href="#fn-2">2</a></sup>"##));
+        assert!(html.contains(r##"again<sup class="footnote-reference" id="fnref-1-2"><a href="#fn-1">1</a></sup>"##));
+        let section = &html[html.find("<section").unwrap()..];
+        assert_eq!(
+            section.trim_end(),
+            concat!(
+                r#"<section class="footnotes"><ol>"#,
+                r##"<li id="fn-1" value="1"><div class="footnote-definition"><p>Beta.</p>"##,
+                r##"<a class="footnote-backref" href="#fnref-1" title="Back to reference 1">↩</a>"##,
+                r##"<a class="footnote-backref" href="#fnref-1-2" title="Back to reference 2">↩2</a></div></li>"##,
+                r##"<li id="fn-2" value="2"><div class="footnote-definition"><p>Alpha.</p>"##,
+                r##"<a class="footnote-backref" href="#fnref-2" title="Back to reference 1">↩</a></div></li>"##,
+                r#"<li id="fn-3" value="3"><div class="footnote-definition"><p>Never used.</p></div></li>"#,
+                "</ol></section>",
+            )
+        );
+    }
+
+    #[test]
+    fn leaves_references_to_missing_footnotes_as_text() {
+        let html = html_fragment("See[^missing].\n", RendererOptions::gfm());
+        assert_eq!(html, "<p>See[^missing].</p>\n");
+        assert!(number_footnotes("See[^missing].\n", &RendererOptions::gfm()).0.is_empty());
+    }
 
-        self.nodes = elements.collect();
+    #[test]
+    fn keeps_footnote_definitions_in_place_while_editing() {
+        let renderer = MarkdownRenderer::with_mode(FOOTNOTES.to_string(), RendererOptions::gfm(), RenderMode::Editing);
+        // Paragraph and three definitions, and no footnotes section.
+        assert_eq!(renderer.nodes.len(), 4);
+        assert!(!renderer.block_keys.contains(&"footnotes".to_string()));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn shows_setext_underlines_as_markers() {
+        let text = "Title\n=====\n\nbody";
+        let renderer = MarkdownRenderer::with_mode(text.to_string(), RendererOptions::gfm(), RenderMode::Editing);
+        // Every run maps to the source it shows, so carets can go anywhere in the heading.
+        let round_trip = |offset| renderer.source_map.to_source(&renderer.source_map.to_dom(offset).unwrap());
+        for offset in (0..=11).chain(13..=text.len()) {
+            assert_eq!(round_trip(offset), Some(offset));
+        }
+        // The blank line after the heading isn't rendered.
+        assert_eq!(round_trip(12), Some(11));
+        assert_eq!(renderer.source_map.to_dom(11).map(|pos| pos.run), Some(1));
+
+        let html = html_fragment(text, RendererOptions::gfm());
+        assert_eq!(html, "<h1 id=\"title\">Title</h1>\n<p>body</p>\n");
+    }
+
+    #[test]
+    fn keeps_the_runs_of_untouched_blocks() {
+        let cache = RenderCache::default();
+        let render = |text: &str| MarkdownRenderer::build(text.to_string(), RendererOptions::gfm(), RenderMode::Editing, None, &cache);
+        let before = render("a\n\n*b* c\n");
+        let after = render("a and more\n\n*b* c\n");
+        assert_ne!(before.block_keys[0], after.block_keys[0]);
+        assert_eq!(before.block_keys[1], after.block_keys[1]);
+        // Runs are numbered per block, so those of the second block are the same as before.
+        let (before, after) = (before.source_map.to_dom(7).unwrap(), after.source_map.to_dom(16).unwrap());
+        assert_eq!(before, after);
+        assert_eq!((before.run, before.offset), (3, 1));
+    }
+
+    #[test]
+    fn reuses_rendered_code_blocks_from_the_last_render() {
+        static RENDERS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+        fn count(source: &str) -> Result<String, String> {
+            RENDERS.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            Ok(format!("<svg>{source}</svg>"))
+        }
+        let options = RendererOptions { code_block_renderers: &[("count", count)], ..RendererOptions::gfm() };
+        let cache = RenderCache::default();
+        let render = |text: &str, cache| MarkdownRenderer::build(text.to_string(), options, RenderMode::Editing, None, cache);
+        let text = "```count\nx\n```\n\n```rust\nfn main() {}\n```\n";
+        render(text, &cache);
+        let edited = format!("edited\n\n{text}");
+        let after = render(&edited, &cache);
+        assert_eq!(RENDERS.load(std::sync::atomic::Ordering::Relaxed), 1);
+        // The highlighted code from the cache is split into the same runs as when highlighted afresh.
+        assert_eq!(after.source_map, render(&edited, &RenderCache::default()).source_map);
+    }
+}
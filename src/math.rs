@@ -0,0 +1,805 @@
+use std::fmt::Write as _;
+
+/// Converts TeX math (the inside of `$...$` or `$$...$$`) to MathML, for the browser to
+/// typeset. Covers the common subset: scripts, fractions, roots, Greek letters, operators,
+/// accents, font styles, `\left`/`\right` and matrix-like environments. Unknown commands are
+/// shown as errors rather than failing the whole formula.
+pub fn to_mathml(tex: &str, display: bool) -> String {
+    let mut parser = Parser {
+        chars: tex.chars().collect(),
+        pos: 0,
+        display,
+        variant: None,
+    };
+    let mut items = Vec::new();
+    loop {
+        items.extend(parser.row());
+        if !parser.skip_stray() {
+            break;
+        }
+    }
+    format!(
+        r#"<math xmlns="http://www.w3.org/1998/Math/MathML"{}>{}</math>"#,
+        if display { r#" display="block""# } else { "" },
+        mrow(items),
+    )
+}
+
+/// A font style from `\mathbf` and friends, applied by mapping letters and digits to the
+/// Unicode mathematical alphanumerics (MathML Core only supports `mathvariant="normal"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variant {
+    Normal,
+    Bold,
+    DoubleStruck,
+    Script,
+    Fraktur,
+    SansSerif,
+    Monospace,
+}
+
+/// A parsed piece of a formula, before any scripts are attached.
+struct Atom {
+    markup: String,
+    /// Whether scripts go above and below it in display math, like `\sum` or `\lim`.
+    limits: bool,
+}
+
+impl Atom {
+    fn new(markup: String) -> Self {
+        Atom { markup, limits: false }
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    display: bool,
+    variant: Option<Variant>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn next(&mut self) -> Option<char> {
+        let ch = self.peek()?;
+        self.pos += 1;
+        Some(ch)
+    }
+
+    fn eat(&mut self, ch: char) -> bool {
+        self.skip_whitespace();
+        let found = self.peek() == Some(ch);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.pos += 1;
+        }
+    }
+
+    /// Whether the input continues with the command `\name`.
+    fn at_command(&self, name: &str) -> bool {
+        let name = name.chars().collect::<Vec<_>>();
+        let end = self.pos + 1 + name.len();
+        self.peek() == Some('\\')
+            && self.chars.get(self.pos + 1..end) == Some(&name[..])
+            && !(name[0].is_alphabetic() && self.chars.get(end).is_some_and(|ch| ch.is_alphabetic()))
+    }
+
+    /// Skips a token that ends a row where nothing expects it, e.g. an unmatched `}`.
+    /// Returns false at the end of the input.
+    fn skip_stray(&mut self) -> bool {
+        if self.peek().is_none() {
+            return false;
+        }
+        if self.at_command("right") || self.at_command("end") {
+            self.command_name();
+            if self.peek() == Some('{') {
+                self.raw_group();
+            } else {
+                self.delimiter();
+            }
+        } else if self.at_command("\\") {
+            self.pos += 2;
+        } else {
+            self.pos += 1;
+        }
+        true
+    }
+
+    /// Items up to the end of the input or of the enclosing group, cell or `\left`.
+    fn row(&mut self) -> Vec<String> {
+        let mut items = Vec::new();
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                None | Some('}' | '&') => break,
+                Some('\\') if self.at_command("\\") || self.at_command("right") || self.at_command("end") => break,
+                _ => {}
+            }
+            let atom = self.atom();
+            items.push(self.scripts(atom));
+        }
+        items
+    }
+
+    fn atom(&mut self) -> Atom {
+        let Some(ch) = self.next() else {
+            return Atom::new(String::new());
+        };
+        match ch {
+            '{' => {
+                let row = self.row();
+                self.eat('}');
+                Atom::new(mrow(row))
+            }
+            '\\' => self.command(),
+            // A script with nothing before it.
+            '^' | '_' => {
+                self.pos -= 1;
+                Atom::new("<mrow></mrow>".to_string())
+            }
+            ch if ch.is_ascii_digit() || (ch == '.' && self.peek().is_some_and(|next| next.is_ascii_digit())) => {
+                let mut number = ch.to_string();
+                while let Some(next) = self.peek().filter(|next| next.is_ascii_digit() || *next == '.') {
+                    number.push(next);
+                    self.pos += 1;
+                }
+                Atom::new(self.number(&number))
+            }
+            ch if ch.is_alphabetic() => Atom::new(self.identifier(ch)),
+            '\'' => Atom::new(mo("′")),
+            '-' => Atom::new(mo("−")),
+            '~' => Atom::new(space("0.25em")),
+            ch => Atom::new(mo(&ch.to_string())),
+        }
+    }
+
+    /// Attaches any `_` and `^` scripts following `base`.
+    fn scripts(&mut self, base: Atom) -> String {
+        let (mut sub, mut sup) = (None, None);
+        loop {
+            self.skip_whitespace();
+            match self.peek() {
+                Some('_') if sub.is_none() => {
+                    self.pos += 1;
+                    sub = Some(self.argument());
+                }
+                Some('^') if sup.is_none() => {
+                    self.pos += 1;
+                    sup = Some(self.argument());
+                }
+                _ => break,
+            }
+        }
+        let limits = base.limits && self.display;
+        let base = base.markup;
+        match (sub, sup) {
+            (None, None) => base,
+            (Some(sub), None) if limits => format!("<munder>{base}{sub}</munder>"),
+            (Some(sub), None) => format!("<msub>{base}{sub}</msub>"),
+            (None, Some(sup)) if limits => format!("<mover>{base}{sup}</mover>"),
+            (None, Some(sup)) => format!("<msup>{base}{sup}</msup>"),
+            (Some(sub), Some(sup)) if limits => format!("<munderover>{base}{sub}{sup}</munderover>"),
+            (Some(sub), Some(sup)) => format!("<msubsup>{base}{sub}{sup}</msubsup>"),
+        }
+    }
+
+    /// A script's or command's argument: a group, or a single token, so `x^10` is `x` to the
+    /// `1`, then `0`.
+    fn argument(&mut self) -> String {
+        self.skip_whitespace();
+        match self.peek() {
+            // A missing argument, as in `x^}`.
+            None | Some('}' | '&') => "<mrow></mrow>".to_string(),
+            Some(ch) if ch.is_ascii_digit() => {
+                self.pos += 1;
+                self.number(&ch.to_string())
+            }
+            _ => self.atom().markup,
+        }
+    }
+
+    /// The text of a `{...}` group as written, for `\text` and environment names.
+    fn raw_group(&mut self) -> String {
+        if !self.eat('{') {
+            return self.next().map(String::from).unwrap_or_default();
+        }
+        let mut depth = 0;
+        let mut text = String::new();
+        while let Some(ch) = self.next() {
+            match ch {
+                '{' => depth += 1,
+                '}' if depth == 0 => break,
+                '}' => depth -= 1,
+                _ => {}
+            }
+            text.push(ch);
+        }
+        text
+    }
+
+    /// The name after a `\`: a run of letters, or a single other character.
+    fn command_name(&mut self) -> String {
+        self.pos += 1;
+        let mut name = String::new();
+        while let Some(ch) = self.peek().filter(|ch| ch.is_ascii_alphabetic()) {
+            name.push(ch);
+            self.pos += 1;
+        }
+        if name.is_empty() {
+            name.extend(self.next());
+        }
+        name
+    }
+
+    /// A delimiter after `\left`, `\right` or `\big`: a character or a command like `\{`.
+    /// `.` is no delimiter at all.
+    fn delimiter(&mut self) -> String {
+        self.skip_whitespace();
+        let delimiter = match self.peek() {
+            Some('\\') => {
+                let name = self.command_name();
+                symbol(&name).map(|(text, _)| text.to_string()).unwrap_or(name)
+            }
+            Some(_) => self.next().map(String::from).unwrap_or_default(),
+            None => String::new(),
+        };
+        match delimiter.as_str() {
+            "." => String::new(),
+            _ => delimiter,
+        }
+    }
+
+    fn command(&mut self) -> Atom {
+        self.pos -= 1;
+        let name = self.command_name();
+        if let Some(letter) = greek(&name) {
+            // Capital Greek letters are upright, like in TeX.
+            return Atom::new(match letter.is_uppercase() {
+                true => format!(r#"<mi mathvariant="normal">{letter}</mi>"#),
+                false => format!("<mi>{letter}</mi>"),
+            });
+        }
+        if let Some((text, kind)) = symbol(&name) {
+            return match kind {
+                Symbol::Operator => Atom::new(mo(text)),
+                Symbol::Identifier => Atom::new(format!("<mi>{text}</mi>")),
+                Symbol::Large => Atom {
+                    markup: mo(text),
+                    limits: true,
+                },
+            };
+        }
+        let markup = match name.as_str() {
+            "frac" | "dfrac" | "tfrac" | "cfrac" => {
+                let numerator = self.argument();
+                let denominator = self.argument();
+                format!("<mfrac>{numerator}{denominator}</mfrac>")
+            }
+            "binom" => {
+                let top = self.argument();
+                let bottom = self.argument();
+                format!(r#"<mrow><mo>(</mo><mfrac linethickness="0">{top}{bottom}</mfrac><mo>)</mo></mrow>"#)
+            }
+            "sqrt" => {
+                let index = match self.eat('[') {
+                    true => {
+                        let mut index = Vec::new();
+                        while !matches!(self.peek(), None | Some(']')) {
+                            let atom = self.atom();
+                            index.push(self.scripts(atom));
+                        }
+                        self.pos += 1;
+                        Some(mrow(index))
+                    }
+                    false => None,
+                };
+                let radicand = self.argument();
+                match index {
+                    Some(index) => format!("<mroot>{radicand}{index}</mroot>"),
+                    None => format!("<msqrt>{radicand}</msqrt>"),
+                }
+            }
+            "text" | "textrm" | "textit" | "textbf" | "mbox" => {
+                format!("<mtext>{}</mtext>", escape(&self.raw_group()))
+            }
+            "operatorname" => format!("<mi>{}</mi>", escape(&self.raw_group())),
+            "mathrm" | "mathbf" | "mathbb" | "mathcal" | "mathscr" | "mathfrak" | "mathsf" | "mathtt" | "boldsymbol"
+            | "bm" | "mathit" => {
+                let variant = match name.as_str() {
+                    "mathrm" => Some(Variant::Normal),
+                    "mathbf" | "boldsymbol" | "bm" => Some(Variant::Bold),
+                    "mathbb" => Some(Variant::DoubleStruck),
+                    "mathcal" | "mathscr" => Some(Variant::Script),
+                    "mathfrak" => Some(Variant::Fraktur),
+                    "mathsf" => Some(Variant::SansSerif),
+                    "mathtt" => Some(Variant::Monospace),
+                    _ => None,
+                };
+                let outer = std::mem::replace(&mut self.variant, variant);
+                let argument = self.argument();
+                self.variant = outer;
+                argument
+            }
+            "hat" | "widehat" | "bar" | "overline" | "vec" | "dot" | "ddot" | "tilde" | "widetilde" | "check"
+            | "breve" | "acute" | "grave" | "overrightarrow" => {
+                let accent = match name.as_str() {
+                    "hat" | "widehat" => "^",
+                    "bar" | "overline" => "‾",
+                    "vec" | "overrightarrow" => "→",
+                    "dot" => "˙",
+                    "ddot" => "¨",
+                    "tilde" | "widetilde" => "~",
+                    "check" => "ˇ",
+                    "breve" => "˘",
+                    "acute" => "´",
+                    _ => "`",
+                };
+                let base = self.argument();
+                format!(r#"<mover accent="true">{base}{}</mover>"#, mo(accent))
+            }
+            "underline" => {
+                let base = self.argument();
+                format!(r#"<munder accentunder="true">{base}{}</munder>"#, mo("_"))
+            }
+            "left" => {
+                let open = self.delimiter();
+                let inner = self.row();
+                let close = match self.at_command("right") {
+                    true => {
+                        self.command_name();
+                        self.delimiter()
+                    }
+                    false => String::new(),
+                };
+                fenced(&open, mrow(inner), &close)
+            }
+            "big" | "Big" | "bigg" | "Bigg" | "bigl" | "bigr" | "Bigl" | "Bigr" | "biggl" | "biggr" | "Biggl"
+            | "Biggr" => mo(&self.delimiter()),
+            "begin" => self.environment(),
+            "not" => {
+                let negated = self.atom().markup;
+                match negated.strip_prefix("<mo>").and_then(|text| text.strip_suffix("</mo>")) {
+                    Some(text) => mo(&format!("{text}\u{338}")),
+                    None => negated,
+                }
+            }
+            "," | "thinspace" => space("0.1667em"),
+            ":" | ">" | "medspace" => space("0.2222em"),
+            ";" | "thickspace" => space("0.2778em"),
+            " " => space("0.25em"),
+            "quad" => space("1em"),
+            "qquad" => space("2em"),
+            "!" | "displaystyle" | "textstyle" | "limits" | "nolimits" => String::new(),
+            "{" | "}" | "%" | "$" | "&" | "#" | "_" => mo(&name),
+            "|" => mo("‖"),
+            _ => format!("<merror><mtext>{}</mtext></merror>", escape(&format!("\\{name}"))),
+        };
+        Atom::new(markup)
+    }
+
+    /// `\begin{name} ... \end{name}`: rows separated by `\\`, cells by `&`.
+    fn environment(&mut self) -> String {
+        let name = self.raw_group();
+        if name == "array" {
+            // Column alignment spec, e.g. `{lcr}`.
+            self.raw_group();
+        }
+        let mut rows = Vec::new();
+        loop {
+            let mut cells = vec![mrow(self.row())];
+            while self.eat('&') {
+                cells.push(mrow(self.row()));
+            }
+            rows.push(cells);
+            if self.at_command("\\") {
+                self.pos += 2;
+                continue;
+            }
+            if self.at_command("end") {
+                self.command_name();
+                self.raw_group();
+            }
+            break;
+        }
+        // A trailing `\\` leaves an empty last row.
+        if rows.len() > 1 && rows.last().is_some_and(|cells| cells.len() == 1 && cells[0] == "<mrow></mrow>") {
+            rows.pop();
+        }
+
+        let aligned = matches!(name.as_str(), "aligned" | "align" | "align*" | "split" | "alignat" | "eqnarray");
+        let mut table = String::from("<mtable>");
+        for cells in rows {
+            table.push_str("<mtr>");
+            for (i, cell) in cells.iter().enumerate() {
+                let align = match (aligned, name.as_str()) {
+                    (true, _) => if i % 2 == 0 { "right" } else { "left" },
+                    (false, "cases") => "left",
+                    _ => "center",
+                };
+                let _ = write!(table, r#"<mtd style="text-align: {align}">{cell}</mtd>"#);
+            }
+            table.push_str("</mtr>");
+        }
+        table.push_str("</mtable>");
+
+        match name.as_str() {
+            "pmatrix" => fenced("(", table, ")"),
+            "bmatrix" => fenced("[", table, "]"),
+            "Bmatrix" => fenced("{", table, "}"),
+            "vmatrix" => fenced("|", table, "|"),
+            "Vmatrix" => fenced("‖", table, "‖"),
+            "cases" => fenced("{", table, ""),
+            _ => table,
+        }
+    }
+
+    fn identifier(&self, ch: char) -> String {
+        match self.variant {
+            Some(Variant::Normal) => format!(r#"<mi mathvariant="normal">{}</mi>"#, escape(&ch.to_string())),
+            Some(variant) => format!("<mi>{}</mi>", styled(ch, variant)),
+            None => format!("<mi>{}</mi>", escape(&ch.to_string())),
+        }
+    }
+
+    fn number(&self, number: &str) -> String {
+        let number = match self.variant {
+            Some(variant) => number.chars().map(|ch| styled(ch, variant)).collect(),
+            None => number.to_string(),
+        };
+        format!("<mn>{number}</mn>")
+    }
+}
+
+/// How a command made of a single symbol is typeset.
+enum Symbol {
+    Operator,
+    Identifier,
+    /// Takes its scripts above and below in display math.
+    Large,
+}
+
+fn symbol(name: &str) -> Option<(&'static str, Symbol)> {
+    use Symbol::*;
+    Some(match name {
+        "cdot" => ("⋅", Operator),
+        "times" => ("×", Operator),
+        "div" => ("÷", Operator),
+        "pm" => ("±", Operator),
+        "mp" => ("∓", Operator),
+        "ast" => ("∗", Operator),
+        "star" => ("⋆", Operator),
+        "circ" => ("∘", Operator),
+        "bullet" => ("∙", Operator),
+        "oplus" => ("⊕", Operator),
+        "otimes" => ("⊗", Operator),
+        "leq" | "le" => ("≤", Operator),
+        "geq" | "ge" => ("≥", Operator),
+        "neq" | "ne" => ("≠", Operator),
+        "ll" => ("≪", Operator),
+        "gg" => ("≫", Operator),
+        "approx" => ("≈", Operator),
+        "equiv" => ("≡", Operator),
+        "sim" => ("∼", Operator),
+        "simeq" => ("≃", Operator),
+        "cong" => ("≅", Operator),
+        "propto" => ("∝", Operator),
+        "to" | "rightarrow" => ("→", Operator),
+        "leftarrow" | "gets" => ("←", Operator),
+        "leftrightarrow" => ("↔", Operator),
+        "Rightarrow" => ("⇒", Operator),
+        "Leftarrow" => ("⇐", Operator),
+        "Leftrightarrow" => ("⇔", Operator),
+        "implies" => ("⟹", Operator),
+        "iff" => ("⟺", Operator),
+        "mapsto" => ("↦", Operator),
+        "uparrow" => ("↑", Operator),
+        "downarrow" => ("↓", Operator),
+        "in" => ("∈", Operator),
+        "notin" => ("∉", Operator),
+        "ni" => ("∋", Operator),
+        "subset" => ("⊂", Operator),
+        "subseteq" => ("⊆", Operator),
+        "supset" => ("⊃", Operator),
+        "supseteq" => ("⊇", Operator),
+        "cup" => ("∪", Operator),
+        "cap" => ("∩", Operator),
+        "setminus" => ("∖", Operator),
+        "forall" => ("∀", Operator),
+        "exists" => ("∃", Operator),
+        "neg" | "lnot" => ("¬", Operator),
+        "land" | "wedge" => ("∧", Operator),
+        "lor" | "vee" => ("∨", Operator),
+        "mid" => ("∣", Operator),
+        "parallel" => ("∥", Operator),
+        "perp" => ("⊥", Operator),
+        "angle" => ("∠", Operator),
+        "colon" => (":", Operator),
+        "ldots" | "dots" => ("…", Operator),
+        "cdots" => ("⋯", Operator),
+        "vdots" => ("⋮", Operator),
+        "ddots" => ("⋱", Operator),
+        "langle" => ("⟨", Operator),
+        "rangle" => ("⟩", Operator),
+        "lfloor" => ("⌊", Operator),
+        "rfloor" => ("⌋", Operator),
+        "lceil" => ("⌈", Operator),
+        "rceil" => ("⌉", Operator),
+        "lvert" | "rvert" | "vert" => ("|", Operator),
+        "lVert" | "rVert" | "Vert" => ("‖", Operator),
+        "prime" => ("′", Operator),
+        "infty" => ("∞", Identifier),
+        "partial" => ("∂", Identifier),
+        "nabla" => ("∇", Identifier),
+        "emptyset" | "varnothing" => ("∅", Identifier),
+        "hbar" => ("ℏ", Identifier),
+        "ell" => ("ℓ", Identifier),
+        "Re" => ("ℜ", Identifier),
+        "Im" => ("ℑ", Identifier),
+        "aleph" => ("ℵ", Identifier),
+        "sin" => ("sin", Identifier),
+        "cos" => ("cos", Identifier),
+        "tan" => ("tan", Identifier),
+        "cot" => ("cot", Identifier),
+        "sec" => ("sec", Identifier),
+        "csc" => ("csc", Identifier),
+        "arcsin" => ("arcsin", Identifier),
+        "arccos" => ("arccos", Identifier),
+        "arctan" => ("arctan", Identifier),
+        "sinh" => ("sinh", Identifier),
+        "cosh" => ("cosh", Identifier),
+        "tanh" => ("tanh", Identifier),
+        "log" => ("log", Identifier),
+        "ln" => ("ln", Identifier),
+        "lg" => ("lg", Identifier),
+        "exp" => ("exp", Identifier),
+        "det" => ("det", Identifier),
+        "dim" => ("dim", Identifier),
+        "ker" => ("ker", Identifier),
+        "deg" => ("deg", Identifier),
+        "arg" => ("arg", Identifier),
+        "gcd" => ("gcd", Identifier),
+        "sum" => ("∑", Large),
+        "prod" => ("∏", Large),
+        "coprod" => ("∐", Large),
+        "bigcup" => ("⋃", Large),
+        "bigcap" => ("⋂", Large),
+        "bigoplus" => ("⨁", Large),
+        "bigotimes" => ("⨂", Large),
+        "lim" => ("lim", Large),
+        "liminf" => ("lim inf", Large),
+        "limsup" => ("lim sup", Large),
+        "max" => ("max", Large),
+        "min" => ("min", Large),
+        "sup" => ("sup", Large),
+        "inf" => ("inf", Large),
+        "int" => ("∫", Operator),
+        "iint" => ("∬", Operator),
+        "iiint" => ("∭", Operator),
+        "oint" => ("∮", Operator),
+        _ => return None,
+    })
+}
+
+fn greek(name: &str) -> Option<char> {
+    Some(match name {
+        "alpha" => 'α',
+        "beta" => 'β',
+        "gamma" => 'γ',
+        "delta" => 'δ',
+        "epsilon" => 'ϵ',
+        "varepsilon" => 'ε',
+        "zeta" => 'ζ',
+        "eta" => 'η',
+        "theta" => 'θ',
+        "vartheta" => 'ϑ',
+        "iota" => 'ι',
+        "kappa" => 'κ',
+        "lambda" => 'λ',
+        "mu" => 'μ',
+        "nu" => 'ν',
+        "xi" => 'ξ',
+        "pi" => 'π',
+        "varpi" => 'ϖ',
+        "rho" => 'ρ',
+        "varrho" => 'ϱ',
+        "sigma" => 'σ',
+        "varsigma" => 'ς',
+        "tau" => 'τ',
+        "upsilon" => 'υ',
+        "phi" => 'ϕ',
+        "varphi" => 'φ',
+        "chi" => 'χ',
+        "psi" => 'ψ',
+        "omega" => 'ω',
+        "Gamma" => 'Γ',
+        "Delta" => 'Δ',
+        "Theta" => 'Θ',
+        "Lambda" => 'Λ',
+        "Xi" => 'Ξ',
+        "Pi" => 'Π',
+        "Sigma" => 'Σ',
+        "Upsilon" => 'Υ',
+        "Phi" => 'Φ',
+        "Psi" => 'Ψ',
+        "Omega" => 'Ω',
+        _ => return None,
+    })
+}
+
+/// `ch` in a mathematical alphanumeric style, e.g. `R` as `ℝ` for [`Variant::DoubleStruck`].
+/// Characters without a styled form are kept.
+fn styled(ch: char, variant: Variant) -> String {
+    // Letters that were in Unicode before the mathematical alphanumerics keep their old code points.
+    let exception = match (variant, ch) {
+        (Variant::DoubleStruck, 'C') => Some('ℂ'),
+        (Variant::DoubleStruck, 'H') => Some('ℍ'),
+        (Variant::DoubleStruck, 'N') => Some('ℕ'),
+        (Variant::DoubleStruck, 'P') => Some('ℙ'),
+        (Variant::DoubleStruck, 'Q') => Some('ℚ'),
+        (Variant::DoubleStruck, 'R') => Some('ℝ'),
+        (Variant::DoubleStruck, 'Z') => Some('ℤ'),
+        (Variant::Script, 'B') => Some('ℬ'),
+        (Variant::Script, 'E') => Some('ℰ'),
+        (Variant::Script, 'F') => Some('ℱ'),
+        (Variant::Script, 'H') => Some('ℋ'),
+        (Variant::Script, 'I') => Some('ℐ'),
+        (Variant::Script, 'L') => Some('ℒ'),
+        (Variant::Script, 'M') => Some('ℳ'),
+        (Variant::Script, 'R') => Some('ℛ'),
+        (Variant::Script, 'e') => Some('ℯ'),
+        (Variant::Script, 'g') => Some('ℊ'),
+        (Variant::Script, 'o') => Some('ℴ'),
+        (Variant::Fraktur, 'C') => Some('ℭ'),
+        (Variant::Fraktur, 'H') => Some('ℌ'),
+        (Variant::Fraktur, 'I') => Some('ℑ'),
+        (Variant::Fraktur, 'R') => Some('ℜ'),
+        (Variant::Fraktur, 'Z') => Some('ℨ'),
+        _ => None,
+    };
+    // Where each style's `A`, `a` and `0` are.
+    let (upper, lower, digit) = match variant {
+        Variant::Normal => (None, None, None),
+        Variant::Bold => (Some(0x1D400), Some(0x1D41A), Some(0x1D7CE)),
+        Variant::DoubleStruck => (Some(0x1D538), Some(0x1D552), Some(0x1D7D8)),
+        Variant::Script => (Some(0x1D49C), Some(0x1D4B6), None),
+        Variant::Fraktur => (Some(0x1D504), Some(0x1D51E), None),
+        Variant::SansSerif => (Some(0x1D5A0), Some(0x1D5BA), Some(0x1D7E2)),
+        Variant::Monospace => (Some(0x1D670), Some(0x1D68A), Some(0x1D7F6)),
+    };
+    let styled = exception.or_else(|| {
+        let (base, first) = match ch {
+            'A'..='Z' => (upper?, 'A'),
+            'a'..='z' => (lower?, 'a'),
+            '0'..='9' => (digit?, '0'),
+            _ => return None,
+        };
+        char::from_u32(base + (ch as u32 - first as u32))
+    });
+    escape(&styled.unwrap_or(ch).to_string())
+}
+
+fn mrow(items: Vec<String>) -> String {
+    match items.len() {
+        1 => items.into_iter().next().unwrap_or_default(),
+        _ => format!("<mrow>{}</mrow>", items.concat()),
+    }
+}
+
+fn mo(text: &str) -> String {
+    format!("<mo>{}</mo>", escape(text))
+}
+
+fn space(width: &str) -> String {
+    format!(r#"<mspace width="{width}"></mspace>"#)
+}
+
+/// `inner` between stretchy delimiters; an empty delimiter is left out.
+fn fenced(open: &str, inner: String, close: &str) -> String {
+    let fence = |text: &str| match text {
+        "" => String::new(),
+        text => format!(r#"<mo fence="true" stretchy="true">{}</mo>"#, escape(text)),
+    };
+    format!("<mrow>{}{inner}{}</mrow>", fence(open), fence(close))
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The MathML inside the `<math>` element for inline `tex`.
+    fn inline(tex: &str) -> String {
+        let mathml = to_mathml(tex, false);
+        let start = mathml.find('>').unwrap() + 1;
+        mathml[start..mathml.len() - "</math>".len()].to_string()
+    }
+
+    #[test]
+    fn converts_fractions_and_roots() {
+        assert_eq!(inline(r"\frac{a}{b}"), "<mfrac><mi>a</mi><mi>b</mi></mfrac>");
+        assert_eq!(inline(r"\sqrt{x}"), "<msqrt><mi>x</mi></msqrt>");
+        assert_eq!(inline(r"\sqrt[3]{x}"), "<mroot><mi>x</mi><mn>3</mn></mroot>");
+    }
+
+    #[test]
+    fn attaches_scripts() {
+        assert_eq!(inline("x^2"), "<msup><mi>x</mi><mn>2</mn></msup>");
+        assert_eq!(inline("x_i"), "<msub><mi>x</mi><mi>i</mi></msub>");
+        assert_eq!(inline("x_i^2"), "<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>");
+        assert_eq!(inline("x^2_i"), "<msubsup><mi>x</mi><mi>i</mi><mn>2</mn></msubsup>");
+    }
+
+    #[test]
+    fn puts_limits_above_and_below_in_display_math() {
+        let scripts = "<mrow><mi>i</mi><mo>=</mo><mn>0</mn></mrow><mi>n</mi>";
+        assert!(to_mathml(r"\sum_{i=0}^n", false).contains(&format!("<msubsup><mo>∑</mo>{scripts}</msubsup>")));
+        let display = to_mathml(r"\sum_{i=0}^n", true);
+        assert!(display.starts_with(r#"<math xmlns="http://www.w3.org/1998/Math/MathML" display="block">"#));
+        assert!(display.contains(&format!("<munderover><mo>∑</mo>{scripts}</munderover>")));
+    }
+
+    #[test]
+    fn converts_matrices_and_cases() {
+        let cell = |tex: &str| format!(r#"<mtd style="text-align: center"><mi>{tex}</mi></mtd>"#);
+        assert_eq!(
+            inline(r"\begin{matrix} a & b \\ c & d \end{matrix}"),
+            format!("<mtable><mtr>{}{}</mtr><mtr>{}{}</mtr></mtable>", cell("a"), cell("b"), cell("c"), cell("d")),
+        );
+        assert_eq!(
+            inline(r"\begin{pmatrix} a \end{pmatrix}"),
+            format!(
+                r#"<mrow><mo fence="true" stretchy="true">(</mo><mtable><mtr>{}</mtr></mtable><mo fence="true" stretchy="true">)</mo></mrow>"#,
+                cell("a")
+            ),
+        );
+        assert_eq!(
+            inline(r"\begin{cases} 1 & x \\ 0 & \text{else} \end{cases}"),
+            concat!(
+                r#"<mrow><mo fence="true" stretchy="true">{</mo><mtable>"#,
+                r#"<mtr><mtd style="text-align: left"><mn>1</mn></mtd><mtd style="text-align: left"><mi>x</mi></mtd></mtr>"#,
+                r#"<mtr><mtd style="text-align: left"><mn>0</mn></mtd><mtd style="text-align: left"><mtext>else</mtext></mtd></mtr>"#,
+                "</mtable></mrow>",
+            ),
+        );
+    }
+
+    #[test]
+    fn maps_font_styles_to_mathematical_alphanumerics() {
+        assert_eq!(inline(r"\mathbb{R}"), "<mi>ℝ</mi>");
+        assert_eq!(inline(r"\mathbf{x1}"), "<mrow><mi>𝐱</mi><mn>𝟏</mn></mrow>");
+        assert_eq!(inline(r"\mathcal{L}"), "<mi>ℒ</mi>");
+        assert_eq!(inline(r"\mathfrak{g}"), "<mi>𝔤</mi>");
+        assert_eq!(inline(r"\mathsf{A}"), "<mi>𝖠</mi>");
+        assert_eq!(inline(r"\mathtt{a}"), "<mi>𝚊</mi>");
+        assert_eq!(inline(r"\mathrm{d}"), r#"<mi mathvariant="normal">d</mi>"#);
+    }
+
+    #[test]
+    fn shows_unknown_commands_as_errors() {
+        assert_eq!(inline(r"\foo x"), "<mrow><merror><mtext>\\foo</mtext></merror><mi>x</mi></mrow>");
+    }
+
+    #[test]
+    fn tolerates_unbalanced_braces_and_empty_input() {
+        assert_eq!(inline("{a"), "<mi>a</mi>");
+        assert_eq!(inline("a}"), "<mi>a</mi>");
+        assert_eq!(inline(""), "<mrow></mrow>");
+    }
+
+    #[test]
+    fn escapes_operators() {
+        assert_eq!(inline("a < b"), "<mrow><mi>a</mi><mo>&lt;</mo><mi>b</mi></mrow>");
+    }
+}
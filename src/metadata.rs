@@ -0,0 +1,255 @@
+use std::ops::Range;
+
+use pulldown_cmark::MetadataBlockKind;
+use serde_json::{Map, Value};
+
+/// Front matter at the top of a document: YAML between `---` lines or TOML between `+++` lines.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metadata {
+    pub kind: MetadataBlockKind,
+    /// Source range of the whole block, fences included.
+    pub block: Range<usize>,
+    /// Source range of the fields between the fences.
+    pub range: Range<usize>,
+    /// The fields in source order, or why they couldn't be parsed.
+    pub fields: Result<Map<String, Value>, String>,
+}
+
+impl Metadata {
+    /// Parses the metadata block at `block` in `source`.
+    pub fn parse(kind: MetadataBlockKind, source: &str, block: Range<usize>) -> Self {
+        let text = &source[block.clone()];
+        // The fields are the lines between the opening and the closing fence.
+        let start = block.start + text.find('\n').map_or(text.len(), |i| i + 1);
+        let end = (block.start + text.rfind('\n').map_or(0, |i| i + 1)).max(start);
+        let fields = parse_fields(kind, &source[start..end]);
+        Self { kind, block, range: start..end, fields }
+    }
+
+    fn field(&self, key: &str) -> Option<&Value> {
+        self.fields.as_ref().ok()?.get(key)
+    }
+
+    pub fn title(&self) -> Option<&str> {
+        self.field("title")?.as_str().filter(|title| !title.trim().is_empty())
+    }
+
+    /// `tags` as either a list or a comma-separated string.
+    pub fn tags(&self) -> Vec<String> {
+        match self.field("tags") {
+            Some(Value::Array(tags)) => tags.iter().map(display_value).collect(),
+            Some(Value::String(tags)) => tags.split(',').map(|tag| tag.trim().to_string()).filter(|tag| !tag.is_empty()).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    pub fn date(&self) -> Option<String> {
+        self.field("date").map(display_value).filter(|date| !date.is_empty())
+    }
+
+    /// The edit to `source` that sets `key` to `value`, or removes it for `None`. The fields
+    /// are written out again, which drops comments and formatting in them; removing the last
+    /// one removes the whole block.
+    pub fn set_field(&self, source: &str, key: &str, value: Option<Value>) -> Result<(Range<usize>, String), String> {
+        let mut fields = self.fields.clone()?;
+        match value {
+            Some(value) => {
+                fields.insert(key.to_string(), value);
+            }
+            None => {
+                fields.shift_remove(key);
+            }
+        }
+        if fields.is_empty() {
+            let rest = &source[self.block.end..];
+            let end = self.block.end + rest.len() - rest.trim_start_matches(['\r', '\n']).len();
+            return Ok((self.block.start..end, String::new()));
+        }
+        Ok((self.range.clone(), write_fields(self.kind, &fields)?))
+    }
+}
+
+/// YAML front matter holding only `key`, to start a document that has none with.
+pub fn new_front_matter(key: &str, value: Value) -> Result<String, String> {
+    let fields = Map::from_iter([(key.to_string(), value)]);
+    Ok(format!("---\n{}---\n\n", write_fields(MetadataBlockKind::YamlStyle, &fields)?))
+}
+
+/// A field's value as shown in the properties panel: lists of plain values are comma
+/// separated, anything else nested is shown as JSON.
+pub fn display_value(value: &Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::String(text) => text.clone(),
+        Value::Array(items) if items.iter().all(|item| !item.is_array() && !item.is_object()) => {
+            items.iter().map(display_value).collect::<Vec<_>>().join(", ")
+        }
+        value => value.to_string(),
+    }
+}
+
+/// Reads a value typed into the properties panel for a field that was `previous`, keeping
+/// its type where the text allows it.
+pub fn parse_value(text: &str, previous: Option<&Value>) -> Value {
+    match previous {
+        Some(Value::String(_)) => Value::String(text.to_string()),
+        Some(Value::Array(items)) => {
+            let strings = items.iter().all(Value::is_string);
+            let items = text.split(',').map(str::trim).filter(|item| !item.is_empty());
+            Value::Array(items.map(|item| if strings { Value::String(item.to_string()) } else { scalar(item) }).collect())
+        }
+        Some(Value::Object(_)) => serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.to_string())),
+        _ => scalar(text),
+    }
+}
+
+fn scalar(text: &str) -> Value {
+    let text = text.trim();
+    if let Ok(flag) = text.parse::<bool>() {
+        Value::Bool(flag)
+    } else if let Ok(number) = text.parse::<i64>() {
+        Value::from(number)
+    } else if let Some(number) = text.parse::<f64>().ok().filter(|number| number.is_finite()) {
+        Value::from(number)
+    } else {
+        Value::String(text.to_string())
+    }
+}
+
+fn parse_fields(kind: MetadataBlockKind, text: &str) -> Result<Map<String, Value>, String> {
+    if text.trim().is_empty() {
+        return Ok(Map::new());
+    }
+    match kind {
+        MetadataBlockKind::YamlStyle => match serde_norway::from_str(text).map_err(|err| err.to_string())? {
+            Value::Object(fields) => Ok(fields),
+            _ => Err("The front matter isn't a list of `key: value` fields.".to_string()),
+        },
+        MetadataBlockKind::PlusesStyle => {
+            let table = text.parse::<toml::Table>().map_err(|err| err.message().to_string())?;
+            Ok(table.into_iter().map(|(key, value)| (key, from_toml(value))).collect())
+        }
+    }
+}
+
+/// TOML dates have no JSON counterpart, so they're kept as strings.
+fn from_toml(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(text) => Value::String(text),
+        toml::Value::Integer(number) => Value::from(number),
+        toml::Value::Float(number) => Value::from(number),
+        toml::Value::Boolean(flag) => Value::Bool(flag),
+        toml::Value::Datetime(date) => Value::String(date.to_string()),
+        toml::Value::Array(items) => Value::Array(items.into_iter().map(from_toml).collect()),
+        toml::Value::Table(table) => Value::Object(table.into_iter().map(|(key, value)| (key, from_toml(value))).collect()),
+    }
+}
+
+fn write_fields(kind: MetadataBlockKind, fields: &Map<String, Value>) -> Result<String, String> {
+    match kind {
+        MetadataBlockKind::YamlStyle => serde_norway::to_string(fields).map_err(|err| err.to_string()),
+        MetadataBlockKind::PlusesStyle => {
+            let table = fields.iter().map(|(key, value)| Ok((key.clone(), to_toml(value)?))).collect::<Result<toml::Table, String>>()?;
+            toml::to_string(&table).map_err(|err| err.to_string())
+        }
+    }
+}
+
+/// The reverse of [`from_toml`], where strings that read as dates become dates again.
+fn to_toml(value: &Value) -> Result<toml::Value, String> {
+    Ok(match value {
+        Value::Null => return Err("TOML has no empty values.".to_string()),
+        Value::String(text) => match text.parse::<toml::value::Datetime>() {
+            Ok(date) => toml::Value::Datetime(date),
+            Err(_) => toml::Value::String(text.clone()),
+        },
+        Value::Number(number) => match number.as_i64() {
+            Some(number) => toml::Value::Integer(number),
+            None => toml::Value::Float(number.as_f64().unwrap_or_default()),
+        },
+        Value::Bool(flag) => toml::Value::Boolean(*flag),
+        Value::Array(items) => toml::Value::Array(items.iter().map(to_toml).collect::<Result<_, _>>()?),
+        Value::Object(fields) => {
+            toml::Value::Table(fields.iter().map(|(key, value)| Ok((key.clone(), to_toml(value)?))).collect::<Result<_, String>>()?)
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    /// Front matter at the start of `source`, closed by the second fence line.
+    fn front_matter(kind: MetadataBlockKind, source: &str) -> Metadata {
+        let fence = match kind {
+            MetadataBlockKind::YamlStyle => "\n---",
+            MetadataBlockKind::PlusesStyle => "\n+++",
+        };
+        let end = source[3..].find(fence).map(|i| 3 + i + fence.len()).unwrap();
+        Metadata::parse(kind, source, 0..end)
+    }
+
+    #[test]
+    fn reads_yaml_fields() {
+        let metadata = front_matter(MetadataBlockKind::YamlStyle, "---\ntitle: Hi\ntags: [a, b]\ndate: 2024-01-02\n---\n\nbody");
+        assert_eq!(metadata.range, 4..44);
+        assert_eq!(metadata.title(), Some("Hi"));
+        assert_eq!(metadata.tags(), ["a", "b"]);
+        assert_eq!(metadata.date().as_deref(), Some("2024-01-02"));
+    }
+
+    #[test]
+    fn reads_toml_fields_with_dates_as_strings() {
+        let metadata = front_matter(MetadataBlockKind::PlusesStyle, "+++\ntitle = \"Hi\"\ndate = 2024-01-02\ntags = \"a, b\"\n+++\nbody");
+        assert_eq!(metadata.title(), Some("Hi"));
+        assert_eq!(metadata.tags(), ["a", "b"]);
+        assert_eq!(metadata.date().as_deref(), Some("2024-01-02"));
+    }
+
+    #[test]
+    fn reports_fields_that_arent_a_map() {
+        assert!(front_matter(MetadataBlockKind::YamlStyle, "---\n- a\n---").fields.is_err());
+        assert!(front_matter(MetadataBlockKind::YamlStyle, "---\na: [\n---").fields.is_err());
+        assert!(front_matter(MetadataBlockKind::PlusesStyle, "+++\na = \n+++").fields.is_err());
+    }
+
+    #[test]
+    fn sets_and_removes_fields() {
+        let source = "---\ntitle: Hi\nn: 3\n---\n\nbody";
+        let metadata = front_matter(MetadataBlockKind::YamlStyle, source);
+        assert_eq!(metadata.set_field(source, "n", Some(json!(4.5))), Ok((4..19, "title: Hi\nn: 4.5\n".to_string())));
+        assert_eq!(metadata.set_field(source, "title", None), Ok((4..19, "n: 3\n".to_string())));
+    }
+
+    #[test]
+    fn removing_the_last_field_removes_the_block() {
+        let source = "---\na: 1\n---\n\nbody";
+        let metadata = front_matter(MetadataBlockKind::YamlStyle, source);
+        assert_eq!(metadata.set_field(source, "a", None), Ok((0..14, String::new())));
+    }
+
+    #[test]
+    fn writes_toml_dates_back_as_dates() {
+        let source = "+++\ndate = 2024-01-02\n+++";
+        let metadata = front_matter(MetadataBlockKind::PlusesStyle, source);
+        let (_, fields) = metadata.set_field(source, "title", Some(json!("New"))).unwrap();
+        assert_eq!(fields, "date = 2024-01-02\ntitle = \"New\"\n");
+    }
+
+    #[test]
+    fn starts_new_yaml_front_matter() {
+        assert_eq!(new_front_matter("title", json!("x: y")).unwrap(), "---\ntitle: 'x: y'\n---\n\n");
+    }
+
+    #[test]
+    fn parses_panel_values_keeping_their_type() {
+        assert_eq!(parse_value("c, d", Some(&json!(["a"]))), json!(["c", "d"]));
+        assert_eq!(parse_value("1, 2", Some(&json!([0]))), json!([1, 2]));
+        assert_eq!(parse_value("42", Some(&json!("text"))), json!("42"));
+        assert_eq!(parse_value("42", None), json!(42));
+        assert_eq!(parse_value("true", None), json!(true));
+        assert_eq!(display_value(&json!(["a", 1])), "a, 1");
+        assert_eq!(display_value(&json!({"x": 1})), "{\"x\":1}");
+    }
+}
@@ -0,0 +1,594 @@
+use std::io::Cursor;
+use std::path::Path;
+
+use anyhow::Context as _;
+use arboard::ImageData;
+use html5ever::tendril::TendrilSink;
+use image::{ImageFormat, RgbaImage};
+use markup5ever_rcdom::{Handle, NodeData, RcDom};
+use pulldown_cmark::Alignment;
+
+use crate::tables::format_table;
+
+/// Elements that start a new block when they appear among inline content.
+const BLOCK_TAGS: &[&str] = &[
+    "address", "article", "aside", "blockquote", "dd", "details", "div", "dl", "dt", "figcaption", "figure",
+    "footer", "h1", "h2", "h3", "h4", "h5", "h6", "header", "hr", "li", "main", "nav", "ol", "p", "pre",
+    "section", "summary", "table", "ul",
+];
+
+/// Elements whose content is never pasted.
+const SKIPPED_TAGS: &[&str] = &["head", "noscript", "script", "style", "template", "title"];
+
+/// Extensions of image files that can be dropped into the document.
+const IMAGE_EXTENSIONS: &[&str] = &["apng", "avif", "bmp", "gif", "jpeg", "jpg", "png", "svg", "webp"];
+
+/// Marks HTML this editor put on the clipboard, whose plain text flavour is the Markdown it
+/// was rendered from.
+const GENERATOR_META: &str = r#"<meta name="generator" content="unified-markdown-editor">"#;
+
+/// Markdown for a hard line break inside a paragraph.
+const HARD_BREAK: &str = "\\\n";
+
+/// Converts HTML from the clipboard (e.g. copied from a browser or word processor) to
+/// Markdown: headings, lists, links, emphasis, tables and code.
+///
+/// Returns `None` if the HTML has no formatting Markdown can express, in which case the
+/// plain text flavour is the better paste, as it keeps the original whitespace. The same goes
+/// for HTML copied from this editor, as its plain text is the original Markdown.
+pub fn markdown_from_html(html: &str) -> Option<String> {
+    if html.contains(GENERATOR_META) {
+        return None;
+    }
+    let dom = html5ever::parse_document(RcDom::default(), Default::default()).one(html);
+    let mut converter = Converter::default();
+    let mut blocks = Vec::new();
+    converter.blocks(&dom.document, &mut blocks);
+    converter.rich.then(|| join(&blocks, false))
+}
+
+/// The HTML flavour for copying `fragment` (rendered from the copied Markdown) to the clipboard.
+pub fn clipboard_html(fragment: &str) -> String {
+    format!("{GENERATOR_META}{fragment}")
+}
+
+/// Whether the file at `path` is an image, judging by its extension.
+pub fn is_image(path: &str) -> bool {
+    Path::new(path)
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| IMAGE_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()))
+}
+
+/// Encodes an image from the clipboard (RGBA pixels) as a PNG file.
+pub fn encode_png(image: ImageData) -> anyhow::Result<Vec<u8>> {
+    let pixels = RgbaImage::from_raw(image.width as u32, image.height as u32, image.bytes.into_owned())
+        .context("The pasted image has an unexpected size")?;
+    let mut png = Cursor::new(Vec::new());
+    pixels.write_to(&mut png, ImageFormat::Png).context("Failed to encode the pasted image")?;
+    Ok(png.into_inner())
+}
+
+/// An image reference to `path` (relative to the document).
+pub fn image_markdown(path: &str) -> String {
+    format!("![]({})", destination(path, None))
+}
+
+/// A converted block, and whether it is a list, as those nest tightly inside list items.
+struct Block {
+    markdown: String,
+    list: bool,
+}
+
+#[derive(Default)]
+struct Converter {
+    /// Whether anything beyond plain paragraphs was converted.
+    rich: bool,
+}
+
+impl Converter {
+    /// Converts the children of `node` to blocks, gathering inline content into paragraphs.
+    fn blocks(&mut self, node: &Handle, out: &mut Vec<Block>) {
+        let mut inline = String::new();
+        for child in node.children.borrow().iter() {
+            match tag(child) {
+                Some(tag) if SKIPPED_TAGS.contains(&tag) => {}
+                Some(tag) if BLOCK_TAGS.contains(&tag) => {
+                    flush(&mut inline, out);
+                    self.block(child, tag, out);
+                }
+                // e.g. `<html>`, or a `<b>` around whole paragraphs as pasted from Google Docs.
+                Some(_) if contains_block(child) => {
+                    flush(&mut inline, out);
+                    self.blocks(child, out);
+                }
+                _ => self.inline(child, &mut inline),
+            }
+        }
+        flush(&mut inline, out);
+    }
+
+    fn block(&mut self, node: &Handle, tag: &str, out: &mut Vec<Block>) {
+        let markdown = match tag {
+            "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                self.rich = true;
+                let text = self.inline_content(node).replace(HARD_BREAK, " ");
+                if text.is_empty() {
+                    return;
+                }
+                format!("{} {text}", "#".repeat(tag[1..].parse().unwrap_or(1)))
+            }
+            "ul" | "ol" => {
+                self.rich = true;
+                out.push(Block {
+                    markdown: self.list(node, tag == "ol"),
+                    list: true,
+                });
+                return;
+            }
+            "blockquote" => {
+                self.rich = true;
+                let mut inner = Vec::new();
+                self.blocks(node, &mut inner);
+                join(&inner, false)
+                    .lines()
+                    .map(|line| if line.is_empty() { ">".to_string() } else { format!("> {line}") })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            "pre" => {
+                self.rich = true;
+                self.code_block(node)
+            }
+            "table" => {
+                self.rich = true;
+                match self.table(node) {
+                    Some(table) => table,
+                    None => return,
+                }
+            }
+            "hr" => {
+                self.rich = true;
+                "---".to_string()
+            }
+            _ => return self.blocks(node, out),
+        };
+        out.push(Block { markdown, list: false });
+    }
+
+    fn list(&mut self, node: &Handle, ordered: bool) -> String {
+        let mut number = attr(node, "start").and_then(|start| start.parse::<u64>().ok()).unwrap_or(1);
+        let mut items: Vec<String> = Vec::new();
+        let mut width = 2;
+        for child in node.children.borrow().iter() {
+            match tag(child) {
+                Some("li") => {
+                    let marker = match ordered {
+                        true => format!("{number}. "),
+                        false => "- ".to_string(),
+                    };
+                    number += 1;
+                    width = marker.len();
+                    let mut blocks = Vec::new();
+                    self.blocks(child, &mut blocks);
+                    items.push(indent(&join(&blocks, true), &marker));
+                }
+                // A list nested directly in a list, rather than in an item, belongs to the item before.
+                Some(tag @ ("ul" | "ol")) => {
+                    let nested = self.list(child, tag == "ol");
+                    match items.last_mut() {
+                        Some(item) => {
+                            item.push('\n');
+                            item.push_str(&indent(&nested, &" ".repeat(width)));
+                        }
+                        None => items.push(nested),
+                    }
+                }
+                _ => {}
+            }
+        }
+        items.join("\n")
+    }
+
+    fn code_block(&mut self, node: &Handle) -> String {
+        let code_element = node.children.borrow().iter().find(|child| tag(child) == Some("code")).cloned();
+        let language = [code_element.as_ref(), Some(node)]
+            .into_iter()
+            .flatten()
+            .filter_map(|element| attr(element, "class"))
+            .find_map(|class| {
+                class.split_whitespace().find_map(|class| {
+                    let language = class.strip_prefix("language-").or_else(|| class.strip_prefix("lang-"));
+                    language.map(str::to_string)
+                })
+            })
+            .unwrap_or_default();
+        let code = text_content(node);
+        let code = code.strip_suffix('\n').unwrap_or(&code);
+        let fence = "`".repeat(longest_run(code, '`').max(2) + 1);
+        format!("{fence}{language}\n{code}\n{fence}")
+    }
+
+    fn table(&mut self, node: &Handle) -> Option<String> {
+        let mut rows = Vec::new();
+        collect_rows(node, &mut rows);
+        let mut alignments = Vec::new();
+        let mut cells: Vec<Vec<String>> = Vec::new();
+        for row in &rows {
+            let mut row_cells = Vec::new();
+            for cell in row.children.borrow().iter().filter(|cell| matches!(tag(cell), Some("th" | "td"))) {
+                if cells.is_empty() {
+                    let align = attr(cell, "align").or_else(|| style(cell, "text-align"));
+                    alignments.push(match align.unwrap_or_default().to_ascii_lowercase().as_str() {
+                        "left" => Alignment::Left,
+                        "center" => Alignment::Center,
+                        "right" => Alignment::Right,
+                        _ => Alignment::None,
+                    });
+                }
+                let text = self.inline_content(cell).replace(HARD_BREAK, " ").replace('|', "\\|");
+                row_cells.push(text);
+            }
+            cells.push(row_cells);
+        }
+
+        let columns = cells.iter().map(Vec::len).max().filter(|&columns| columns > 0)?;
+        alignments.resize(columns, Alignment::None);
+        Some(format_table(&cells, &alignments))
+    }
+
+    fn inline(&mut self, node: &Handle, out: &mut String) {
+        match &node.data {
+            NodeData::Text { contents } => push_text(out, &contents.borrow()),
+            NodeData::Element { .. } => self.inline_element(node, out),
+            _ => {}
+        }
+    }
+
+    fn inline_children(&mut self, node: &Handle, out: &mut String) {
+        for child in node.children.borrow().iter() {
+            self.inline(child, out);
+        }
+    }
+
+    /// The inline Markdown of `node`'s content, trimmed.
+    fn inline_content(&mut self, node: &Handle) -> String {
+        let mut inline = String::new();
+        self.inline_children(node, &mut inline);
+        trim_inline(&inline).to_string()
+    }
+
+    fn inline_element(&mut self, node: &Handle, out: &mut String) {
+        let tag = tag(node).unwrap_or_default();
+        match tag {
+            _ if SKIPPED_TAGS.contains(&tag) => {}
+            "strong" | "b" if style(node, "font-weight").is_none_or(|weight| is_bold(&weight)) => {
+                self.wrap(node, "**", "**", out)
+            }
+            "em" | "i" => self.wrap(node, "*", "*", out),
+            "s" | "del" | "strike" => self.wrap(node, "~~", "~~", out),
+            "code" | "kbd" | "samp" | "tt" => {
+                self.rich = true;
+                let code = text_content(node).replace('\n', " ");
+                if code.is_empty() {
+                    return;
+                }
+                let ticks = "`".repeat(longest_run(&code, '`') + 1);
+                let pad = if code.starts_with('`') || code.ends_with('`') { " " } else { "" };
+                out.push_str(&format!("{ticks}{pad}{code}{pad}{ticks}"));
+            }
+            "a" => match attr(node, "href").filter(|href| !href.is_empty() && !href.starts_with("javascript:")) {
+                Some(href) => {
+                    let close = format!("]({})", destination(&href, attr(node, "title")));
+                    self.wrap(node, "[", &close, out);
+                }
+                None => self.inline_children(node, out),
+            },
+            "img" => {
+                let Some(src) = attr(node, "src").filter(|src| !src.is_empty()) else {
+                    return;
+                };
+                self.rich = true;
+                let alt = escape_text(&attr(node, "alt").unwrap_or_default());
+                out.push_str(&format!("![{alt}]({})", destination(&src, attr(node, "title"))));
+            }
+            "br" => out.push_str(HARD_BREAK),
+            "input" if attr(node, "type").as_deref() == Some("checkbox") => {
+                self.rich = true;
+                out.push_str(if attr(node, "checked").is_some() { "[x] " } else { "[ ] " });
+            }
+            // Word processors mark up formatting with styles rather than elements.
+            "span" => {
+                let bold = style(node, "font-weight").is_some_and(|weight| is_bold(&weight));
+                let italic = style(node, "font-style").is_some_and(|style| style == "italic");
+                let struck = style(node, "text-decoration").is_some_and(|style| style.contains("line-through"));
+                let open = format!(
+                    "{}{}{}",
+                    if bold { "**" } else { "" },
+                    if italic { "*" } else { "" },
+                    if struck { "~~" } else { "" }
+                );
+                match open.is_empty() {
+                    true => self.inline_children(node, out),
+                    false => {
+                        let close = open.chars().rev().collect::<String>();
+                        self.wrap(node, &open, &close, out);
+                    }
+                }
+            }
+            _ => self.inline_children(node, out),
+        }
+    }
+
+    /// Puts `node`'s content between `open` and `close`, keeping surrounding whitespace
+    /// outside of them, as `** bold **` wouldn't be emphasis.
+    fn wrap(&mut self, node: &Handle, open: &str, close: &str, out: &mut String) {
+        self.rich = true;
+        let mut inner = String::new();
+        self.inline_children(node, &mut inner);
+        let content = trim_inline(&inner);
+        if inner.starts_with(char::is_whitespace) && !ends_with_space(out) {
+            out.push(' ');
+        }
+        if !content.is_empty() {
+            out.push_str(open);
+            out.push_str(content);
+            out.push_str(close);
+            if inner.ends_with(char::is_whitespace) {
+                out.push(' ');
+            }
+        }
+    }
+}
+
+/// Ends the paragraph gathered in `inline`, if there is one.
+fn flush(inline: &mut String, out: &mut Vec<Block>) {
+    let text = trim_inline(inline);
+    if !text.is_empty() {
+        let markdown = text.split('\n').map(escape_line_start).collect::<Vec<_>>().join("\n");
+        out.push(Block { markdown, list: false });
+    }
+    inline.clear();
+}
+
+/// Joins blocks with blank lines. In list items, nested lists follow the text directly so
+/// the list stays tight.
+fn join(blocks: &[Block], tight_lists: bool) -> String {
+    let mut markdown = String::new();
+    for (i, block) in blocks.iter().enumerate() {
+        if i > 0 {
+            markdown.push_str(if tight_lists && block.list { "\n" } else { "\n\n" });
+        }
+        markdown.push_str(&block.markdown);
+    }
+    markdown
+}
+
+/// Prefixes the first line of `content` with `marker` and indents the rest to line up with it.
+fn indent(content: &str, marker: &str) -> String {
+    if content.is_empty() {
+        return marker.trim_end().to_string();
+    }
+    let padding = " ".repeat(marker.chars().count());
+    content
+        .split('\n')
+        .enumerate()
+        .map(|(i, line)| match (i, line.is_empty()) {
+            (0, _) => format!("{marker}{line}"),
+            (_, true) => String::new(),
+            (_, false) => format!("{padding}{line}"),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// The `<tr>` elements of a table, including those in `<thead>`, `<tbody>` and `<tfoot>`.
+fn collect_rows(node: &Handle, rows: &mut Vec<Handle>) {
+    for child in node.children.borrow().iter() {
+        match tag(child) {
+            Some("tr") => rows.push(child.clone()),
+            Some("thead" | "tbody" | "tfoot") => collect_rows(child, rows),
+            _ => {}
+        }
+    }
+}
+
+/// Trims whitespace and trailing hard breaks, which mean nothing at the end of a block.
+fn trim_inline(text: &str) -> &str {
+    let mut text = text.trim();
+    // An odd number of trailing backslashes ends in a hard break rather than an escaped `\`.
+    while (text.len() - text.trim_end_matches('\\').len()) % 2 == 1 {
+        text = text[..text.len() - 1].trim_end();
+    }
+    text
+}
+
+/// Appends a text node with its whitespace collapsed, as the browser would render it.
+fn push_text(out: &mut String, text: &str) {
+    let words = text.split_whitespace().collect::<Vec<_>>();
+    if text.starts_with(char::is_whitespace) && !ends_with_space(out) {
+        out.push(' ');
+    }
+    if words.is_empty() {
+        return;
+    }
+    out.push_str(&escape_text(&words.join(" ")));
+    if text.ends_with(char::is_whitespace) {
+        out.push(' ');
+    }
+}
+
+fn ends_with_space(out: &str) -> bool {
+    out.ends_with([' ', '\n'])
+}
+
+/// Escapes characters that would otherwise be read as inline Markdown.
+fn escape_text(text: &str) -> String {
+    let chars = text.chars().collect::<Vec<_>>();
+    let mut escaped = String::with_capacity(text.len());
+    for (i, &ch) in chars.iter().enumerate() {
+        // `snake_case` can't start emphasis, so it doesn't need escaping.
+        let in_word = ch == '_'
+            && i > 0
+            && chars[i - 1].is_alphanumeric()
+            && chars.get(i + 1).is_some_and(|next| next.is_alphanumeric());
+        if matches!(ch, '\\' | '`' | '*' | '_' | '[' | ']' | '<') && !in_word {
+            escaped.push('\\');
+        }
+        escaped.push(ch);
+    }
+    escaped
+}
+
+/// Escapes a line of a paragraph that would otherwise start a heading, quote or list.
+fn escape_line_start(line: &str) -> String {
+    let digits = line.len() - line.trim_start_matches(|ch: char| ch.is_ascii_digit()).len();
+    if line.starts_with(['#', '>']) || line.starts_with("- ") || line.starts_with("+ ") {
+        format!("\\{line}")
+    } else if digits > 0 && line[digits..].starts_with(['.', ')']) {
+        format!("{}\\{}", &line[..digits], &line[digits..])
+    } else {
+        line.to_string()
+    }
+}
+
+/// A link or image destination, in angle brackets if it has characters that would end it.
+fn destination(url: &str, title: Option<String>) -> String {
+    let url = match url.contains([' ', '(', ')', '<', '>']) {
+        true => format!("<{}>", url.replace('<', "%3C").replace('>', "%3E")),
+        false => url.to_string(),
+    };
+    match title.filter(|title| !title.is_empty()) {
+        Some(title) => format!("{url} \"{}\"", title.replace('"', "\\\"")),
+        None => url,
+    }
+}
+
+fn longest_run(text: &str, ch: char) -> usize {
+    text.split(|other| other != ch).map(str::len).max().unwrap_or(0)
+}
+
+fn is_bold(weight: &str) -> bool {
+    matches!(weight, "bold" | "bolder") || weight.parse::<u32>().is_ok_and(|weight| weight >= 600)
+}
+
+fn tag(node: &Handle) -> Option<&str> {
+    match &node.data {
+        NodeData::Element { name, .. } => Some(&name.local),
+        _ => None,
+    }
+}
+
+fn attr(node: &Handle, name: &str) -> Option<String> {
+    match &node.data {
+        NodeData::Element { attrs, .. } => attrs
+            .borrow()
+            .iter()
+            .find(|attr| &*attr.name.local == name)
+            .map(|attr| attr.value.to_string()),
+        _ => None,
+    }
+}
+
+/// The value of a property in the element's `style` attribute.
+fn style(node: &Handle, property: &str) -> Option<String> {
+    attr(node, "style")?.split(';').find_map(|declaration| {
+        let (name, value) = declaration.split_once(':')?;
+        name.trim()
+            .eq_ignore_ascii_case(property)
+            .then(|| value.trim().to_ascii_lowercase())
+    })
+}
+
+fn contains_block(node: &Handle) -> bool {
+    node.children
+        .borrow()
+        .iter()
+        .any(|child| tag(child).is_some_and(|tag| BLOCK_TAGS.contains(&tag)) || contains_block(child))
+}
+
+/// The text of `node` and its descendants, as in a `<pre>`.
+fn text_content(node: &Handle) -> String {
+    let mut text = String::new();
+    for child in node.children.borrow().iter() {
+        match &child.data {
+            NodeData::Text { contents } => text.push_str(&contents.borrow()),
+            _ if tag(child) == Some("br") => text.push('\n'),
+            _ => text.push_str(&text_content(child)),
+        }
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn convert(html: &str) -> String {
+        markdown_from_html(html).unwrap()
+    }
+
+    #[test]
+    fn converts_headings() {
+        assert_eq!(convert("<h1>Title</h1><h3>Sub <em>part</em></h3>"), "# Title\n\n### Sub *part*");
+        // An empty heading is dropped.
+        assert_eq!(convert("<h2> </h2><p>text</p>"), "text");
+    }
+
+    #[test]
+    fn converts_nested_and_ordered_lists() {
+        assert_eq!(convert("<ul><li>a<ul><li>b</li></ul></li><li>c</li></ul>"), "- a\n  - b\n- c");
+        assert_eq!(convert("<ol start=\"3\"><li>a</li><li>b<ol><li>c</li></ol></li></ol>"), "3. a\n4. b\n   1. c");
+        // A list nested directly in a list belongs to the item before it.
+        assert_eq!(convert("<ol><li>a</li><ul><li>b</li></ul></ol>"), "1. a\n   - b");
+    }
+
+    #[test]
+    fn pads_tables() {
+        let html = "<table><thead><tr><th>Name</th><th align=\"right\">Qty</th></tr></thead>\
+                    <tbody><tr><td>apple</td><td>3</td></tr><tr><td>a|b</td><td>10</td></tr></tbody></table>";
+        assert_eq!(convert(html), "| Name  | Qty |\n| ----- | --: |\n| apple | 3   |\n| a\\|b  | 10  |");
+    }
+
+    #[test]
+    fn fences_preformatted_code() {
+        let html = "<pre><code class=\"language-rust\">fn main() {\n    let x = 1;\n}\n</code></pre>";
+        assert_eq!(convert(html), "```rust\nfn main() {\n    let x = 1;\n}\n```");
+        // The fence is longer than any run of backticks in the code.
+        assert_eq!(convert("<pre>a ``` b</pre>"), "````\na ``` b\n````");
+    }
+
+    #[test]
+    fn escapes_markdown_in_text() {
+        assert_eq!(convert("<p><b>x</b> *not* _em_ snake_case</p>"), "**x** \\*not\\* \\_em\\_ snake_case");
+        assert_eq!(convert("<p><b>x</b></p><p># not a heading</p>"), "**x**\n\n\\# not a heading");
+        assert_eq!(convert("<p><b>x</b></p><p>1. not a list</p>"), "**x**\n\n1\\. not a list");
+        assert_eq!(convert("<p><b>x</b></p><p>- not a list</p>"), "**x**\n\n\\- not a list");
+    }
+
+    #[test]
+    fn reads_formatting_from_styles() {
+        // The way Google Docs marks up bold and italic text.
+        let html = "<meta charset=\"utf-8\"><b style=\"font-weight:normal;\" id=\"docs-internal-guid-1\">\
+                    <p dir=\"ltr\"><span style=\"font-weight:700;\">Bold</span><span> and </span>\
+                    <span style=\"font-style:italic;\">italic</span></p></b>";
+        assert_eq!(convert(html), "**Bold** and *italic*");
+    }
+
+    #[test]
+    fn keeps_plain_text_pastes_plain() {
+        assert_eq!(markdown_from_html("<p>just text</p>"), None);
+    }
+
+    #[test]
+    fn leaves_html_copied_from_the_editor_to_its_plain_text() {
+        assert_eq!(markdown_from_html(&clipboard_html("<h1>Title</h1>")), None);
+    }
+
+    #[test]
+    fn recognises_image_files() {
+        assert!(is_image("photo.JPG"));
+        assert!(!is_image("notes.md"));
+        assert_eq!(image_markdown("assets/my image.png"), "![](<assets/my image.png>)");
+    }
+}
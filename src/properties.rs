@@ -0,0 +1,89 @@
+use dioxus::prelude::*;
+use serde_json::Value;
+
+use crate::editor::EditorBuffer;
+use crate::metadata::{display_value, new_front_matter, parse_value, Metadata};
+
+/// The document's front matter as a list of editable properties, above the editor.
+///
+/// Every change rewrites the front matter in the buffer as one undoable edit, so the panel
+/// always shows what the source says. A document without front matter gets a YAML block
+/// when its first property is added.
+#[component]
+pub fn PropertiesPanel(buffer: Signal<EditorBuffer>, metadata: Memo<Option<Metadata>>) -> Element {
+    let mut new_key = use_signal(String::new);
+    let mut error = use_signal(|| None::<String>);
+
+    let mut set_field = move |key: String, value: Option<Value>| {
+        let text = buffer.read().text().to_string();
+        let edit = match (&*metadata.read(), value) {
+            (Some(metadata), value) => metadata.set_field(&text, &key, value),
+            (None, Some(value)) => new_front_matter(&key, value).map(|front_matter| (0..0, front_matter)),
+            (None, None) => return,
+        };
+        match edit {
+            Ok(edit) => {
+                error.set(None);
+                buffer.write().replace_ranges(&[edit]);
+            }
+            Err(err) => error.set(Some(err)),
+        }
+    };
+
+    let mut add_field = move || {
+        let key = new_key.read().trim().to_string();
+        let exists = metadata.read().as_ref().is_some_and(|metadata| {
+            metadata.fields.as_ref().is_ok_and(|fields| fields.contains_key(&key))
+        });
+        if key.is_empty() || exists {
+            return;
+        }
+        set_field(key, Some(Value::String(String::new())));
+        new_key.set(String::new());
+    };
+
+    let fields = metadata.read().as_ref().map(|metadata| metadata.fields.clone()).unwrap_or(Ok(Default::default()));
+
+    rsx! {
+        div {
+            id: "properties",
+            style: "display: grid; grid-template-columns: max-content 1fr auto; gap: 2px 6px; align-items: center; margin-bottom: 4px;",
+            match fields {
+                Ok(fields) => rsx! {
+                    {fields.into_iter().map(|(key, value)| {
+                        let removed = key.clone();
+                        rsx! {
+                            label { key: "{key}", r#for: "property-{key}", "{key}" }
+                            // Applied when the field is left, so half-typed values aren't rewritten.
+                            input {
+                                id: "property-{key}",
+                                value: display_value(&value),
+                                onchange: move |evt| set_field(key.clone(), Some(parse_value(&evt.value(), Some(&value)))),
+                            }
+                            button { title: "Remove this property", onclick: move |_| set_field(removed.clone(), None), "✕" }
+                        }
+                    })}
+                },
+                Err(err) => rsx! {
+                    span { style: "grid-column: 1 / -1; color: #cf222e;", "The front matter couldn't be read: {err}" }
+                },
+            }
+            input {
+                style: "grid-column: 1 / 3;",
+                placeholder: "New property",
+                value: "{new_key}",
+                oninput: move |evt| new_key.set(evt.value()),
+                onkeydown: move |evt: KeyboardEvent| {
+                    if evt.data().key() == Key::Enter {
+                        evt.prevent_default();
+                        add_field();
+                    }
+                },
+            }
+            button { onclick: move |_| add_field(), "Add" }
+            if let Some(err) = error() {
+                span { style: "grid-column: 1 / -1; color: #cf222e;", "{err}" }
+            }
+        }
+    }
+}
@@ -0,0 +1,122 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::rc::Rc;
+
+use crate::highlight::Tokens;
+
+/// The slow parts of rendering, highlighted code and rendered code blocks such as diagrams,
+/// kept from one render to the next by a hash of what they were made from. An edit then
+/// only redoes the blocks it changed.
+///
+/// Clones share the same cache.
+#[derive(Clone, Default)]
+pub struct RenderCache(Rc<RefCell<Entries>>);
+
+#[derive(Default)]
+struct Entries {
+    /// Tokens of each text event of a fenced code block.
+    highlighted: Generations<Vec<Tokens>>,
+    rendered: Generations<Result<String, String>>,
+}
+
+impl RenderCache {
+    /// Starts a render. Whatever the previous render didn't use is dropped.
+    pub fn start(&self) {
+        let mut entries = self.0.borrow_mut();
+        entries.highlighted.advance();
+        entries.rendered.advance();
+    }
+
+    pub fn highlighted(&self, key: u64) -> Option<Vec<Tokens>> {
+        self.0.borrow_mut().highlighted.get(key)
+    }
+
+    pub fn insert_highlighted(&self, key: u64, tokens: Vec<Tokens>) {
+        self.0.borrow_mut().highlighted.current.insert(key, tokens);
+    }
+
+    /// The output of `render`, run only if nothing is cached under `key`.
+    pub fn rendered(&self, key: u64, render: impl FnOnce() -> Result<String, String>) -> Result<String, String> {
+        if let Some(rendered) = self.0.borrow_mut().rendered.get(key) {
+            return rendered;
+        }
+        let rendered = render();
+        self.0.borrow_mut().rendered.current.insert(key, rendered.clone());
+        rendered
+    }
+}
+
+/// Entries used by the render in progress, and those of the render before it.
+struct Generations<T> {
+    current: HashMap<u64, T>,
+    previous: HashMap<u64, T>,
+}
+
+impl<T> Default for Generations<T> {
+    fn default() -> Self {
+        Self { current: HashMap::new(), previous: HashMap::new() }
+    }
+}
+
+impl<T: Clone> Generations<T> {
+    fn advance(&mut self) {
+        self.previous = std::mem::take(&mut self.current);
+    }
+
+    fn get(&mut self, key: u64) -> Option<T> {
+        if let Some(value) = self.current.get(&key) {
+            return Some(value.clone());
+        }
+        let value = self.previous.remove(&key)?;
+        self.current.insert(key, value.clone());
+        Some(value)
+    }
+}
+
+/// A cache key for `value`.
+pub fn cache_key(value: impl Hash) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_what_the_last_render_used() {
+        let cache = RenderCache::default();
+        let renders = std::cell::Cell::new(0);
+        let render = |key: u64| {
+            cache.rendered(key, || {
+                renders.set(renders.get() + 1);
+                Ok(format!("<svg>{key}</svg>"))
+            })
+        };
+        cache.start();
+        assert_eq!(render(1), Ok("<svg>1</svg>".to_string()));
+        render(2).unwrap();
+        render(1).unwrap();
+        assert_eq!(renders.get(), 2);
+
+        // The next render only uses the first drawing, so the second is gone after that.
+        cache.start();
+        render(1).unwrap();
+        cache.start();
+        render(1).unwrap();
+        render(2).unwrap();
+        assert_eq!(renders.get(), 3);
+    }
+
+    #[test]
+    fn caches_highlighted_code() {
+        let cache = RenderCache::default();
+        cache.start();
+        assert_eq!(cache.highlighted(7), None);
+        cache.insert_highlighted(7, vec![vec![(0..2, Some("color: red;".to_string()))]]);
+        cache.start();
+        assert_eq!(cache.highlighted(7).map(|tokens| tokens.len()), Some(1));
+    }
+}
@@ -0,0 +1,139 @@
+use std::ops::Range;
+
+use regex::{Regex, RegexBuilder};
+
+/// A find/replace query over the raw Markdown text.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Search {
+    pub query: String,
+    /// Replacement text. In regex mode `$1` and `${name}` refer to capture groups.
+    pub replacement: String,
+    pub case_sensitive: bool,
+    pub whole_word: bool,
+    /// Treat `query` as a regular expression rather than plain text.
+    pub regex: bool,
+}
+
+impl Search {
+    fn compile(&self) -> Result<Regex, regex::Error> {
+        let pattern = if self.regex {
+            self.query.clone()
+        } else {
+            regex::escape(&self.query)
+        };
+        let pattern = if self.whole_word {
+            format!(r"\b(?:{pattern})\b")
+        } else {
+            pattern
+        };
+        RegexBuilder::new(&pattern)
+            .case_insensitive(!self.case_sensitive)
+            .multi_line(true)
+            .build()
+    }
+
+    /// Byte ranges of all matches in `text`, in order. Empty matches are skipped.
+    pub fn find_all(&self, text: &str) -> Result<Vec<Range<usize>>, regex::Error> {
+        if self.query.is_empty() {
+            return Ok(Vec::new());
+        }
+        Ok(self
+            .compile()?
+            .find_iter(text)
+            .map(|found| found.range())
+            .filter(|range| !range.is_empty())
+            .collect())
+    }
+
+    /// The replacement text for each of `ranges` (matches found by [`Search::find_all`]).
+    pub fn replacements(&self, text: &str, ranges: &[Range<usize>]) -> Result<Vec<(Range<usize>, String)>, regex::Error> {
+        if !self.regex {
+            return Ok(ranges
+                .iter()
+                .map(|range| (range.clone(), self.replacement.clone()))
+                .collect());
+        }
+        let regex = self.compile()?;
+        Ok(ranges
+            .iter()
+            .map(|range| {
+                let mut replacement = String::new();
+                if let Some(captures) = regex.captures_at(text, range.start) {
+                    captures.expand(&self.replacement, &mut replacement);
+                }
+                (range.clone(), replacement)
+            })
+            .collect())
+    }
+}
+
+/// The first match starting at or after `from`, wrapping around to the first match.
+pub fn next_match(matches: &[Range<usize>], from: usize) -> Option<Range<usize>> {
+    matches
+        .iter()
+        .find(|range| range.start >= from)
+        .or(matches.first())
+        .cloned()
+}
+
+/// The last match ending at or before `before`, wrapping around to the last match.
+pub fn prev_match(matches: &[Range<usize>], before: usize) -> Option<Range<usize>> {
+    matches
+        .iter()
+        .rev()
+        .find(|range| range.end <= before)
+        .or(matches.last())
+        .cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(query: &str) -> Search {
+        Search { query: query.to_string(), ..Default::default() }
+    }
+
+    #[test]
+    fn finds_plain_text_case_insensitively() {
+        assert_eq!(query("a.b").find_all("A.B axb a.b").unwrap(), [0..3, 8..11]);
+        let search = Search { case_sensitive: true, ..query("a.b") };
+        assert_eq!(search.find_all("A.B a.b A.b a.b").unwrap(), [4..7, 12..15]);
+    }
+
+    #[test]
+    fn matches_whole_words() {
+        let search = Search { whole_word: true, ..query("cat") };
+        assert_eq!(search.find_all("cat category bobcat cat.").unwrap(), [0..3, 20..23]);
+    }
+
+    #[test]
+    fn finds_regexes_per_line() {
+        let search = Search { regex: true, ..query("^#+ ") };
+        assert_eq!(search.find_all("# a\ntext\n## b").unwrap(), [0..2, 9..12]);
+        assert!(Search { regex: true, ..query("(") }.find_all("x").is_err());
+        assert!(Search { regex: true, ..query("x*") }.find_all("ab").unwrap().is_empty());
+    }
+
+    #[test]
+    fn expands_capture_groups_in_regex_replacements() {
+        let text = "size: 10px, 2px";
+        let search = Search { regex: true, replacement: "${n}em".to_string(), ..query(r"(?<n>\d+)px") };
+        let ranges = search.find_all(text).unwrap();
+        let replacements = search.replacements(text, &ranges).unwrap();
+        assert_eq!(replacements, [(6..10, "10em".to_string()), (12..15, "2em".to_string())]);
+
+        let plain = Search { replacement: "$1".to_string(), ..query("px") };
+        assert_eq!(plain.replacements(text, &ranges).unwrap()[1], (12..15, "$1".to_string()));
+    }
+
+    #[test]
+    fn steps_through_matches_with_wrap_around() {
+        let matches = [2..4, 6..8];
+        assert_eq!(next_match(&matches, 3), Some(6..8));
+        assert_eq!(next_match(&matches, 7), Some(2..4));
+        assert_eq!(prev_match(&matches, 6), Some(2..4));
+        assert_eq!(prev_match(&matches, 3), Some(6..8));
+        assert_eq!(next_match(&[], 0), None);
+    }
+}
@@ -0,0 +1,224 @@
+use std::ops::Range;
+
+use crate::editor::{byte_to_utf16, utf16_to_byte};
+
+/// A run of rendered text (or a syntax marker) and the source range it came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextRun {
+    pub src: Range<usize>,
+    pub text: String,
+    /// Whether `text` is exactly the source slice, so offsets inside it map one to one.
+    pub exact: bool,
+}
+
+/// A caret location in the rendered editor: the text run (its block's key and its
+/// `data-run` index in that block) and a UTF-16 offset into that run's text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DomPosition {
+    pub block: String,
+    pub run: usize,
+    pub offset: usize,
+}
+
+/// The text runs of one top-level block.
+#[derive(Debug, Clone, PartialEq)]
+struct Block {
+    key: String,
+    runs: Vec<TextRun>,
+}
+
+/// Maps raw Markdown offsets to rendered text runs and back.
+///
+/// Runs are recorded in document order while rendering, and each is emitted as a
+/// `span` carrying `data-block`, `data-run`, `data-src-start` and `data-src-end`. Runs are
+/// numbered per block, so an edit leaves the runs of the other blocks as they were.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap {
+    blocks: Vec<Block>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Starts recording the runs of the top-level block with `key`.
+    pub fn start_block(&mut self, key: String) {
+        self.blocks.push(Block { key, runs: Vec::new() });
+    }
+
+    /// How many runs the current block has so far.
+    pub fn len(&self) -> usize {
+        self.blocks.last().map_or(0, |block| block.runs.len())
+    }
+
+    /// The rendered text of the current block's runs from index `first` on, e.g. a heading's contents.
+    pub fn text_from(&self, first: usize) -> String {
+        let runs = self.blocks.last().map_or(&[][..], |block| &block.runs[first..]);
+        runs.iter().map(|run| run.text.as_str()).collect()
+    }
+
+    /// Records a rendered run in the current block, returning its index there.
+    pub fn push(&mut self, text: &str, src: Range<usize>, source: &str) -> usize {
+        if self.blocks.is_empty() {
+            self.start_block(String::new());
+        }
+        let runs = &mut self.blocks.last_mut().unwrap().runs;
+        let exact = source.get(src.clone()) == Some(text);
+        runs.push(TextRun {
+            src,
+            text: text.to_string(),
+            exact,
+        });
+        runs.len() - 1
+    }
+
+    /// Every run with its block's key and its index in the block.
+    fn runs(&self) -> impl Iterator<Item = (&str, usize, &TextRun)> {
+        self.blocks
+            .iter()
+            .flat_map(|block| block.runs.iter().enumerate().map(|(i, run)| (block.key.as_str(), i, run)))
+    }
+
+    /// Where a raw byte offset should place the caret in the rendered editor.
+    ///
+    /// Offsets that fall between runs (markup that isn't rendered, blank lines)
+    /// land at the end of the closest run before them.
+    pub fn to_dom(&self, offset: usize) -> Option<DomPosition> {
+        let Some((block, run, text_run)) = self
+            .runs()
+            .filter(|(_, _, text_run)| text_run.src.start <= offset)
+            .max_by_key(|(_, _, text_run)| (text_run.src.start, text_run.exact))
+        else {
+            let (block, run, _) = self.runs().next()?;
+            return Some(DomPosition { block: block.to_string(), run, offset: 0 });
+        };
+
+        let offset = if offset >= text_run.src.end {
+            byte_to_utf16(&text_run.text, text_run.text.len())
+        } else if text_run.exact {
+            byte_to_utf16(&text_run.text, offset - text_run.src.start)
+        } else {
+            0
+        };
+        Some(DomPosition { block: block.to_string(), run, offset })
+    }
+
+    /// The raw byte offset for a caret location reported by the DOM.
+    pub fn to_source(&self, pos: &DomPosition) -> Option<usize> {
+        let block = self.blocks.iter().find(|block| block.key == pos.block)?;
+        let text_run = block.runs.get(pos.run)?;
+        Some(if text_run.exact {
+            text_run.src.start + utf16_to_byte(&text_run.text, pos.offset)
+        } else if pos.offset == 0 {
+            text_run.src.start
+        } else {
+            text_run.src.end
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A map of `source`'s runs, each given as its source range and rendered text.
+    fn map(source: &str, runs: &[(Range<usize>, &str)]) -> SourceMap {
+        let mut map = SourceMap::new();
+        map.start_block("a".to_string());
+        for (src, text) in runs {
+            map.push(text, src.clone(), source);
+        }
+        map
+    }
+
+    fn pos(run: usize, offset: usize) -> DomPosition {
+        DomPosition { block: "a".to_string(), run, offset }
+    }
+
+    #[test]
+    fn maps_exact_runs_one_to_one() {
+        // `a *b* c`, with its emphasis markers as runs of their own.
+        let source = "a *b* c";
+        let map = map(source, &[(0..2, "a "), (2..3, "*"), (3..4, "b"), (4..5, "*"), (5..7, " c")]);
+        assert!(map.blocks[0].runs.iter().all(|run| run.exact));
+        assert_eq!(map.to_dom(1), Some(pos(0, 1)));
+        assert_eq!(map.to_dom(3), Some(pos(2, 0)));
+        assert_eq!(map.to_dom(6), Some(pos(4, 1)));
+        assert_eq!(map.to_source(&pos(2, 1)), Some(4));
+        assert_eq!(map.to_source(&pos(4, 2)), Some(7));
+        assert_eq!(map.text_from(1), "*b* c");
+    }
+
+    #[test]
+    fn snaps_inside_inexact_runs_to_their_ends() {
+        // An entity and an escape render as one character for several in the source.
+        let source = r"a &amp; \*";
+        let map = map(source, &[(0..2, "a "), (2..7, "&"), (7..8, " "), (8..10, "*")]);
+        assert!(!map.blocks[0].runs[1].exact && !map.blocks[0].runs[3].exact);
+        assert_eq!(map.to_dom(4), Some(pos(1, 0)));
+        assert_eq!(map.to_dom(9), Some(pos(3, 0)));
+        assert_eq!(map.to_source(&pos(1, 0)), Some(2));
+        assert_eq!(map.to_source(&pos(1, 1)), Some(7));
+        assert_eq!(map.to_source(&pos(3, 1)), Some(10));
+    }
+
+    #[test]
+    fn places_offsets_between_runs_at_the_end_of_the_run_before() {
+        // Blank lines between paragraphs aren't rendered as runs.
+        let source = "ab\n\ncd";
+        let map = map(source, &[(0..2, "ab"), (4..6, "cd")]);
+        assert_eq!(map.to_dom(3), Some(pos(0, 2)));
+        assert_eq!(map.to_dom(4), Some(pos(1, 0)));
+    }
+
+    #[test]
+    fn maps_offsets_inside_markers_into_the_marker() {
+        let source = "**b**";
+        let map = map(source, &[(0..2, "**"), (2..3, "b"), (3..5, "**")]);
+        assert_eq!(map.to_dom(1), Some(pos(0, 1)));
+        assert_eq!(map.to_dom(4), Some(pos(2, 1)));
+    }
+
+    #[test]
+    fn maps_the_ends_of_the_document() {
+        let source = "# ab";
+        let map = map(source, &[(0..2, "# "), (2..4, "ab")]);
+        assert_eq!(map.to_dom(4), Some(pos(1, 2)));
+        assert_eq!(map.to_source(&pos(1, 2)), Some(4));
+        assert_eq!(map.to_source(&pos(2, 0)), None);
+        // Before the first run, e.g. in leading blank lines.
+        let map = self::map("\n\nab", &[(2..4, "ab")]);
+        assert_eq!(map.to_dom(0), Some(pos(0, 0)));
+        assert_eq!(SourceMap::new().to_dom(0), None);
+    }
+
+    #[test]
+    fn counts_dom_offsets_in_utf16() {
+        let source = "é😀x";
+        let map = map(source, &[(0..source.len(), source)]);
+        assert_eq!(map.to_dom(2), Some(pos(0, 1)));
+        assert_eq!(map.to_dom(6), Some(pos(0, 3)));
+        assert_eq!(map.to_dom(7), Some(pos(0, 4)));
+        assert_eq!(map.to_source(&pos(0, 3)), Some(6));
+        assert_eq!(map.to_source(&pos(0, 4)), Some(7));
+    }
+
+    #[test]
+    fn numbers_runs_per_block() {
+        let source = "# a\n\nb *c*";
+        let mut map = SourceMap::new();
+        map.start_block("h".to_string());
+        assert_eq!(map.push("# ", 0..2, source), 0);
+        assert_eq!(map.push("a", 2..3, source), 1);
+        map.start_block("p".to_string());
+        assert_eq!(map.push("b ", 5..7, source), 0);
+        assert_eq!(map.push("*", 7..8, source), 1);
+        assert_eq!(map.text_from(0), "b *");
+        let at = |block: &str, run, offset| DomPosition { block: block.to_string(), run, offset };
+        assert_eq!(map.to_dom(4), Some(at("h", 1, 1)));
+        assert_eq!(map.to_dom(6), Some(at("p", 0, 1)));
+        assert_eq!(map.to_source(&at("p", 1, 1)), Some(8));
+        assert_eq!(map.to_source(&at("x", 0, 0)), None);
+    }
+}
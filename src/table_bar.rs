@@ -0,0 +1,48 @@
+use dioxus::prelude::*;
+use pulldown_cmark::Alignment;
+
+use crate::editor::EditorBuffer;
+use crate::markdown::RendererOptions;
+use crate::tables::{edit_table, TableEdit};
+
+/// Row, column and alignment commands for the pipe table the caret is in, above the editor.
+///
+/// Each command re-pads the whole table and is one undoable edit.
+#[component]
+pub fn TableBar(buffer: Signal<EditorBuffer>, options: Memo<RendererOptions>) -> Element {
+    let apply = move |edit: TableEdit| {
+        move |_| {
+            let edited = edit_table(buffer.read().text(), buffer.read().caret(), edit, options.read().to_parser_options());
+            if let Some((text, selection)) = edited {
+                buffer.write().replace_text_and_select(text, selection);
+            }
+        }
+    };
+
+    rsx! {
+        div {
+            id: "table-bar",
+            style: "display: flex; flex-wrap: wrap; gap: 4px; align-items: center; margin-bottom: 4px;",
+            // Clicking a button mustn't take the focus, and with it the caret, out of the editor.
+            onmousedown: move |evt| evt.prevent_default(),
+            "Row: "
+            button { title: "Insert a row above", onclick: apply(TableEdit::InsertRowAbove), "+ Above" }
+            button { title: "Insert a row below", onclick: apply(TableEdit::InsertRowBelow), "+ Below" }
+            button { title: "Move the row up", onclick: apply(TableEdit::MoveRowUp), "↑" }
+            button { title: "Move the row down", onclick: apply(TableEdit::MoveRowDown), "↓" }
+            button { title: "Delete the row", onclick: apply(TableEdit::DeleteRow), "✕" }
+            " Column: "
+            button { title: "Insert a column to the left", onclick: apply(TableEdit::InsertColumnLeft), "+ Left" }
+            button { title: "Insert a column to the right", onclick: apply(TableEdit::InsertColumnRight), "+ Right" }
+            button { title: "Move the column left", onclick: apply(TableEdit::MoveColumnLeft), "←" }
+            button { title: "Move the column right", onclick: apply(TableEdit::MoveColumnRight), "→" }
+            button { title: "Delete the column", onclick: apply(TableEdit::DeleteColumn), "✕" }
+            " Align: "
+            button { title: "Align the column left", onclick: apply(TableEdit::Align(Alignment::Left)), ":--" }
+            button { title: "Center the column", onclick: apply(TableEdit::Align(Alignment::Center)), ":-:" }
+            button { title: "Align the column right", onclick: apply(TableEdit::Align(Alignment::Right)), "--:" }
+            button { title: "Use the default alignment", onclick: apply(TableEdit::Align(Alignment::None)), "---" }
+            button { title: "Re-pad the table's columns", onclick: apply(TableEdit::Format), "Format" }
+        }
+    }
+}
@@ -0,0 +1,451 @@
+use std::ops::Range;
+
+use pulldown_cmark::{Alignment, Event, Options, Parser, Tag};
+use unicode_width::UnicodeWidthStr;
+
+use crate::lines::Lines;
+
+/// A change to the pipe table under the caret, made from the table bar.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TableEdit {
+    InsertRowAbove,
+    InsertRowBelow,
+    DeleteRow,
+    MoveRowUp,
+    MoveRowDown,
+    InsertColumnLeft,
+    InsertColumnRight,
+    DeleteColumn,
+    MoveColumnLeft,
+    MoveColumnRight,
+    Align(Alignment),
+    /// Only re-pads the table.
+    Format,
+}
+
+/// The pipe table around the caret, as the parser finds it.
+struct Table {
+    lines: Lines,
+    /// The table's first (header) and last line.
+    first: usize,
+    last: usize,
+    /// What's in front of each line of the table: blockquote markers or a list item's indentation.
+    prefixes: Vec<String>,
+    /// The header row, then the body rows, all with the same number of cells.
+    rows: Vec<Vec<String>>,
+    alignments: Vec<Alignment>,
+    /// The caret's cell (the delimiter row counts as the header), and where in its text it is.
+    row: usize,
+    column: usize,
+    offset: usize,
+}
+
+impl Table {
+    fn at(text: &str, caret: usize, options: Options) -> Option<Table> {
+        let (alignments, range) = Parser::new_ext(text, options).into_offset_iter().find_map(|(event, range)| match event {
+            Event::Start(Tag::Table(alignments)) if range.start <= caret && caret <= range.end => Some((alignments, range)),
+            _ => None,
+        })?;
+        let lines = Lines::new(text);
+        let (first, indent) = lines.position(range.start);
+        let (last, _) = lines.position(range.start + text[range].trim_end().len());
+        let (line, column) = lines.position(caret);
+        if line > last {
+            return None;
+        }
+
+        // Rows inside a blockquote or list item start as far in as the header does.
+        let prefixes = (first..=last)
+            .map(|i| lines.line(i).get(..indent).unwrap_or_default().to_string())
+            .collect::<Vec<_>>();
+        let row_cells = |i: usize| cells(&lines.line(i)[prefixes[i - first].len()..]);
+        let row_lines = std::iter::once(first).chain(first + 2..=last);
+        let mut rows = row_lines
+            .map(|i| {
+                let line = &lines.line(i)[prefixes[i - first].len()..];
+                row_cells(i).into_iter().map(|(_, content)| line[content].to_string()).collect::<Vec<_>>()
+            })
+            .collect::<Vec<_>>();
+
+        // The caret's cell is the last one starting before it.
+        let caret_cells = row_cells(line);
+        let column = column.saturating_sub(prefixes[line - first].len());
+        let cell = caret_cells.iter().rposition(|(raw, _)| raw.start <= column).unwrap_or(0);
+        let offset = match line == first + 1 {
+            true => 0,
+            false => column.clamp(caret_cells[cell].1.start, caret_cells[cell].1.end) - caret_cells[cell].1.start,
+        };
+
+        // Ragged rows are filled out to the widest one, so no cell is lost.
+        let columns = rows.iter().map(Vec::len).max().unwrap_or(0).max(alignments.len());
+        for row in &mut rows {
+            row.resize(columns, String::new());
+        }
+        let mut alignments = alignments;
+        alignments.resize(columns, Alignment::None);
+        Some(Table {
+            first,
+            last,
+            prefixes,
+            rows,
+            alignments,
+            row: line.saturating_sub(first + 1),
+            column: cell,
+            offset,
+            lines,
+        })
+    }
+
+    /// What goes in front of the table's `i`th line; new rows get the delimiter row's.
+    fn prefix(&self, i: usize) -> &str {
+        self.prefixes.get(i).unwrap_or(&self.prefixes[1])
+    }
+
+    fn columns(&self) -> usize {
+        self.alignments.len()
+    }
+
+    fn empty_row(&self) -> Vec<String> {
+        vec![String::new(); self.columns()]
+    }
+
+    /// Writes the table back re-padded. Returns the new text and the caret in the current
+    /// cell, or the whole cell's text selected.
+    fn write(mut self, select_cell: bool) -> (String, Range<usize>) {
+        let formatted = format_table(&self.rows, &self.alignments)
+            .lines()
+            .enumerate()
+            .map(|(i, line)| format!("{}{line}", self.prefix(i)))
+            .collect::<Vec<_>>();
+        self.lines.splice(self.first..=self.last, formatted);
+
+        let line = if self.row == 0 { 0 } else { self.row + 1 };
+        let indent = self.prefix(line).len();
+        let line = self.first + line;
+        let start = self.lines.offset(line, indent);
+        let (_, content) = cells(&self.lines.line(line)[indent..]).swap_remove(self.column);
+        let selection = match select_cell {
+            true => content,
+            false => {
+                let caret = content.start + self.offset.min(content.len());
+                caret..caret
+            }
+        };
+        (self.lines.join(), start + selection.start..start + selection.end)
+    }
+}
+
+/// Tab/Shift+Tab in a pipe table: selects the text of the next (or previous) cell, adding a
+/// row after the last one, and re-pads the table. Returns the new text and selection, or
+/// `None` if the caret isn't in a table.
+pub fn next_cell(text: &str, caret: usize, backwards: bool, options: Options) -> Option<(String, Range<usize>)> {
+    let mut table = Table::at(text, caret, options)?;
+    let columns = table.columns();
+    let index = table.row * columns + table.column;
+    let index = match backwards {
+        true => index.saturating_sub(1),
+        false => index + 1,
+    };
+    if index / columns == table.rows.len() {
+        table.rows.push(table.empty_row());
+    }
+    (table.row, table.column, table.offset) = (index / columns, index % columns, 0);
+    Some(table.write(true))
+}
+
+/// Enter in a pipe table: adds a row below the caret's, with the caret in the same column.
+/// On an empty last row, removes it and ends the table instead. Returns the new text and
+/// caret, or `None` if the caret isn't in a table.
+pub fn continue_table(text: &str, caret: usize, options: Options) -> Option<(String, Range<usize>)> {
+    let mut table = Table::at(text, caret, options)?;
+    let last_row = table.row + 1 == table.rows.len();
+    if table.row > 0 && last_row && table.rows[table.row].iter().all(|cell| cell.trim().is_empty()) {
+        table.rows.pop();
+        table.row -= 1;
+        let after = table.last;
+        // The caret goes to a new line after the (now shorter) table, still in its blockquote.
+        let prefix = table.prefix(1).trim_end().to_string();
+        let (text, _) = table.write(false);
+        let mut lines = Lines::new(&text);
+        lines.insert(after, prefix);
+        let caret = lines.offset(after, usize::MAX);
+        return Some((lines.join(), caret..caret));
+    }
+    table.rows.insert(table.row + 1, table.empty_row());
+    (table.row, table.offset) = (table.row + 1, 0);
+    Some(table.write(false))
+}
+
+/// Applies `edit` to the pipe table at `caret`, keeping the caret in the cell it was in
+/// (or the one that took its place). Returns the new text and caret, or `None` if the caret
+/// isn't in a table or the edit isn't possible there, like deleting the only column.
+pub fn edit_table(text: &str, caret: usize, edit: TableEdit, options: Options) -> Option<(String, Range<usize>)> {
+    let mut table = Table::at(text, caret, options)?;
+    let (row, column) = (table.row, table.column);
+    let (rows, columns) = (table.rows.len(), table.columns());
+    match edit {
+        TableEdit::InsertRowAbove | TableEdit::InsertRowBelow => {
+            let at = if edit == TableEdit::InsertRowAbove { row } else { row + 1 };
+            table.rows.insert(at, table.empty_row());
+            (table.row, table.offset) = (at, 0);
+        }
+        TableEdit::DeleteRow if rows > 1 => {
+            table.rows.remove(row);
+            (table.row, table.offset) = (row.min(rows - 2), 0);
+        }
+        TableEdit::MoveRowUp if row > 0 => {
+            table.rows.swap(row, row - 1);
+            table.row -= 1;
+        }
+        TableEdit::MoveRowDown if row + 1 < rows => {
+            table.rows.swap(row, row + 1);
+            table.row += 1;
+        }
+        TableEdit::InsertColumnLeft | TableEdit::InsertColumnRight => {
+            let at = if edit == TableEdit::InsertColumnLeft { column } else { column + 1 };
+            for cells in &mut table.rows {
+                cells.insert(at, String::new());
+            }
+            table.alignments.insert(at, Alignment::None);
+            (table.column, table.offset) = (at, 0);
+        }
+        TableEdit::DeleteColumn if columns > 1 => {
+            for cells in &mut table.rows {
+                cells.remove(column);
+            }
+            table.alignments.remove(column);
+            (table.column, table.offset) = (column.min(columns - 2), 0);
+        }
+        TableEdit::MoveColumnLeft if column > 0 => {
+            swap_columns(&mut table, column, column - 1);
+            table.column -= 1;
+        }
+        TableEdit::MoveColumnRight if column + 1 < columns => {
+            swap_columns(&mut table, column, column + 1);
+            table.column += 1;
+        }
+        TableEdit::Align(alignment) => table.alignments[column] = alignment,
+        TableEdit::Format => {}
+        _ => return None,
+    }
+    Some(table.write(false))
+}
+
+/// Whether `caret` is in a pipe table.
+pub fn in_table(text: &str, caret: usize, options: Options) -> bool {
+    Table::at(text, caret, options).is_some()
+}
+
+/// Lays out a pipe table with each column padded to the display width of its widest cell
+/// (at least 3, so the delimiter row fits any alignment marker), so wide characters like
+/// CJK line up in a monospace font. `rows` starts with the header row.
+pub fn format_table(rows: &[Vec<String>], alignments: &[Alignment]) -> String {
+    let columns = rows.iter().map(Vec::len).max().unwrap_or(0);
+    let widths = (0..columns)
+        .map(|column| {
+            let width = rows.iter().filter_map(|row| row.get(column)).map(|cell| cell.width());
+            width.max().unwrap_or(0).max(3)
+        })
+        .collect::<Vec<_>>();
+    let format_row = |row: &[String]| {
+        let padded = widths.iter().enumerate().map(|(column, &width)| {
+            let cell = row.get(column).map_or("", String::as_str);
+            format!("{cell}{}", " ".repeat(width - cell.width()))
+        });
+        format!("| {} |", padded.collect::<Vec<_>>().join(" | "))
+    };
+    let delimiter = widths.iter().enumerate().map(|(column, &width)| match alignments.get(column) {
+        Some(Alignment::Left) => format!(":{}", "-".repeat(width - 1)),
+        Some(Alignment::Center) => format!(":{}:", "-".repeat(width - 2)),
+        Some(Alignment::Right) => format!("{}:", "-".repeat(width - 1)),
+        _ => "-".repeat(width),
+    });
+
+    let mut lines = vec![
+        format_row(rows.first().map_or(&[], Vec::as_slice)),
+        format!("| {} |", delimiter.collect::<Vec<_>>().join(" | ")),
+    ];
+    lines.extend(rows.iter().skip(1).map(|row| format_row(row)));
+    lines.join("\n")
+}
+
+fn swap_columns(table: &mut Table, a: usize, b: usize) {
+    for cells in &mut table.rows {
+        cells.swap(a, b);
+    }
+    table.alignments.swap(a, b);
+}
+
+/// Offsets of the pipes in a table line that separate cells, i.e. those not escaped as `\|`.
+fn pipes(line: &str) -> impl Iterator<Item = usize> + '_ {
+    let mut escaped = false;
+    line.char_indices().filter_map(move |(i, ch)| {
+        let pipe = ch == '|' && !escaped;
+        escaped = ch == '\\' && !escaped;
+        pipe.then_some(i)
+    })
+}
+
+/// The cells of a table line, each as the range between its pipes and the range of its
+/// trimmed text. An empty cell's text is where typing into it goes, after the padding space.
+fn cells(line: &str) -> Vec<(Range<usize>, Range<usize>)> {
+    let start = line.len() - line.trim_start().len();
+    let end = line.trim_end().len();
+    let mut pipes = pipes(line).collect::<Vec<_>>();
+    // Pipes at the start and end of the line only enclose the cells.
+    let from = match pipes.first() {
+        Some(&first) if first == start => {
+            pipes.remove(0);
+            start + 1
+        }
+        _ => start,
+    };
+    let to = match pipes.last() {
+        Some(&last) if last + 1 == end => {
+            pipes.pop();
+            end - 1
+        }
+        _ => end,
+    };
+
+    let mut cells = Vec::new();
+    let mut cell_start = from;
+    for cell_end in pipes.into_iter().chain([to.max(from)]) {
+        let raw = cell_start..cell_end;
+        let text = &line[raw.clone()];
+        let content = match text.trim() {
+            "" => {
+                let at = (raw.start + 1).min(raw.end);
+                at..at
+            }
+            trimmed => {
+                let at = raw.start + text.find(trimmed).unwrap_or(0);
+                at..at + trimmed.len()
+            }
+        };
+        cells.push((raw, content));
+        cell_start = cell_end + 1;
+    }
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `edit` with the caret at `^` in `text`, and shows the result's selection in `[]`
+    /// (or the caret as `^`).
+    fn with_caret(text: &str, edit: impl FnOnce(&str, usize) -> Option<(String, Range<usize>)>) -> Option<String> {
+        let caret = text.find('^').unwrap();
+        let text = text.replacen('^', "", 1);
+        edit(&text, caret).map(|(mut text, selection)| {
+            if selection.is_empty() {
+                text.insert(selection.start, '^');
+            } else {
+                text.insert(selection.end, ']');
+                text.insert(selection.start, '[');
+            }
+            text
+        })
+    }
+
+    fn tab(text: &str, backwards: bool) -> Option<String> {
+        with_caret(text, |text, caret| next_cell(text, caret, backwards, Options::ENABLE_TABLES))
+    }
+
+    fn enter(text: &str) -> Option<String> {
+        with_caret(text, |text, caret| continue_table(text, caret, Options::ENABLE_TABLES))
+    }
+
+    fn edit(text: &str, edit: TableEdit) -> Option<String> {
+        with_caret(text, |text, caret| edit_table(text, caret, edit, Options::ENABLE_TABLES))
+    }
+
+    #[test]
+    fn tab_selects_the_next_cell_and_pads_the_table() {
+        assert_eq!(
+            tab("|a|bb|\n|-|-|\n|1^|2|", false).as_deref(),
+            Some("| a   | bb  |\n| --- | --- |\n| 1   | [2]   |"),
+        );
+        assert_eq!(
+            tab("| a | b |\n|---|---|\n| 1 | 2^ |", false).as_deref(),
+            Some("| a   | b   |\n| --- | --- |\n| 1   | 2   |\n| ^    |     |"),
+        );
+    }
+
+    #[test]
+    fn shift_tab_selects_the_previous_cell() {
+        assert_eq!(tab("| a | b |\n|---|---|\n| 1 | ^2 |", true).as_deref(), Some("| a   | b   |\n| --- | --- |\n| [1]   | 2   |"));
+        assert_eq!(tab("| ^a | b |\n|---|---|", true).as_deref(), Some("| [a]   | b   |\n| --- | --- |"));
+    }
+
+    #[test]
+    fn enter_adds_a_row_below() {
+        assert_eq!(
+            enter("| a | b |\n|---|---|\n| 1 | 2^ |\n| 3 | 4 |").as_deref(),
+            Some("| a   | b   |\n| --- | --- |\n| 1   | 2   |\n|     | ^    |\n| 3   | 4   |"),
+        );
+    }
+
+    #[test]
+    fn enter_on_an_empty_last_row_ends_the_table() {
+        assert_eq!(enter("| a |\n|---|\n| ^ |\n\nafter").as_deref(), Some("| a   |\n| --- |\n^\n\nafter"));
+        assert_eq!(enter("> | a |\n> |---|\n> | ^ |").as_deref(), Some("> | a   |\n> | --- |\n>^"));
+    }
+
+    #[test]
+    fn keeps_blockquote_markers_and_list_indentation() {
+        assert_eq!(
+            edit("> |a|b|\n> |-|-|\n> |1|^2|", TableEdit::Format).as_deref(),
+            Some("> | a   | b   |\n> | --- | --- |\n> | 1   | ^2   |"),
+        );
+        assert_eq!(
+            edit("- |a|\n  |-|\n  |^1|", TableEdit::MoveRowUp).as_deref(),
+            Some("- | ^1   |\n  | --- |\n  | a   |"),
+        );
+    }
+
+    #[test]
+    fn edits_rows_and_columns() {
+        let table = "| a | b |\n|:--|--:|\n| ^1 | 2 |";
+        assert_eq!(edit(table, TableEdit::MoveColumnRight).as_deref(), Some("| b   | a   |\n| --: | :-- |\n| 2   | ^1   |"));
+        assert_eq!(edit(table, TableEdit::InsertColumnLeft).as_deref(), Some("|     | a   | b   |\n| --- | :-- | --: |\n| ^    | 1   | 2   |"));
+        assert_eq!(edit(table, TableEdit::DeleteColumn).as_deref(), Some("| b   |\n| --: |\n| ^2   |"));
+        assert_eq!(edit(table, TableEdit::InsertRowAbove).as_deref(), Some("| a   | b   |\n| :-- | --: |\n| ^    |     |\n| 1   | 2   |"));
+        assert_eq!(edit(table, TableEdit::DeleteRow).as_deref(), Some("| ^a   | b   |\n| :-- | --: |"));
+        assert_eq!(
+            edit(table, TableEdit::Align(Alignment::Center)).as_deref(),
+            Some("| a   | b   |\n| :-: | --: |\n| ^1   | 2   |"),
+        );
+    }
+
+    #[test]
+    fn refuses_impossible_edits() {
+        assert_eq!(edit("| ^a |\n|---|", TableEdit::DeleteColumn), None);
+        assert_eq!(edit("| ^a |\n|---|", TableEdit::MoveRowUp), None);
+        assert_eq!(edit("| ^a | b |\n|---|---|", TableEdit::MoveColumnLeft), None);
+    }
+
+    #[test]
+    fn only_finds_tables_the_parser_sees() {
+        assert!(in_table("| a |\n| - |", 2, Options::ENABLE_TABLES));
+        assert!(!in_table("```\n| a |\n| - |\n```", 6, Options::ENABLE_TABLES));
+        assert!(!in_table("| a |\n| b |", 2, Options::ENABLE_TABLES));
+        assert!(!in_table("| a |\n| - |", 2, Options::empty()));
+        assert!(!in_table("| a |\n| - |\n\n| x", 15, Options::ENABLE_TABLES));
+    }
+
+    #[test]
+    fn pads_wide_characters_by_display_width() {
+        let rows = [vec!["名前".to_string(), "x".to_string()], vec!["🦀🦀🦀".to_string(), "é".to_string()]];
+        assert_eq!(
+            format_table(&rows, &[Alignment::None, Alignment::Right]),
+            "| 名前   | x   |\n| ------ | --: |\n| 🦀🦀🦀 | é   |",
+        );
+    }
+
+    #[test]
+    fn keeps_escaped_pipes_in_cells() {
+        assert_eq!(edit("| a \\| b | ^c |\n|-|-|", TableEdit::Format).as_deref(), Some("| a \\| b | ^c   |\n| ------ | --- |"));
+    }
+}